@@ -0,0 +1,469 @@
+//! Logging contacts in ADIF.
+//!
+//! A [`Qso`] is built from the messages exchanged with a station, written as ADIF 3
+//! ADI records and read back. [`WorkedBefore`] indexes a log so decodes of stations
+//! and grids not worked yet can be picked out.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    io::Write,
+    time::SystemTime,
+};
+
+use crate::{
+    message::{callsign::is_station, Grid4Value, MessageData, Standard},
+    utils::{civil_from_days, days_from_civil, from_unix_ms, unix_ms},
+    Error,
+};
+
+const ADIF_VERSION: &str = "3.1.4";
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+// Modes ADIF files under MFSK and logs as a submode
+const MFSK_SUBMODES: [&str; 3] = ["FT4", "JS8", "Q65"];
+// ADIF band names and their edges in Hz
+const BANDS: [(&str, u64, u64); 21] = [
+    ("2190m", 135_700, 137_800),
+    ("630m", 472_000, 479_000),
+    ("160m", 1_800_000, 2_000_000),
+    ("80m", 3_500_000, 4_000_000),
+    ("60m", 5_060_000, 5_450_000),
+    ("40m", 7_000_000, 7_300_000),
+    ("30m", 10_100_000, 10_150_000),
+    ("20m", 14_000_000, 14_350_000),
+    ("17m", 18_068_000, 18_168_000),
+    ("15m", 21_000_000, 21_450_000),
+    ("12m", 24_890_000, 24_990_000),
+    ("10m", 28_000_000, 29_700_000),
+    ("8m", 40_000_000, 45_000_000),
+    ("6m", 50_000_000, 54_000_000),
+    ("4m", 70_000_000, 71_000_000),
+    ("2m", 144_000_000, 148_000_000),
+    ("1.25m", 222_000_000, 225_000_000),
+    ("70cm", 420_000_000, 450_000_000),
+    ("33cm", 902_000_000, 928_000_000),
+    ("23cm", 1_240_000_000, 1_300_000_000),
+    ("13cm", 2_300_000_000, 2_450_000_000),
+];
+
+/// One contact, the fields of an ADIF record this crate reads and writes.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Qso {
+    pub call: String,
+    pub station_callsign: Option<String>,
+    pub grid: Option<String>,
+    pub rst_sent: Option<String>,
+    pub rst_rcvd: Option<String>,
+    // ADIF band name such as "20m"
+    pub band: Option<String>,
+    pub freq_hz: Option<u64>,
+    pub mode: String,
+    pub time_on: SystemTime,
+    pub time_off: SystemTime,
+    pub contest_id: Option<String>,
+    pub exchange_sent: Option<String>,
+    pub exchange_rcvd: Option<String>,
+}
+
+// What one transmission of a contact carried
+struct Exchange {
+    to: String,
+    from: String,
+    report: Option<String>,
+    grid: Option<String>,
+    exchange: Option<String>,
+    contest_id: Option<&'static str>,
+}
+
+impl Qso {
+    pub fn new(call: &str, mode: &str, time_on: SystemTime) -> Self {
+        Self {
+            call: call.to_string(),
+            station_callsign: None,
+            grid: None,
+            rst_sent: None,
+            rst_rcvd: None,
+            band: None,
+            freq_hz: None,
+            mode: mode.to_string(),
+            time_on,
+            time_off: time_on,
+            contest_id: None,
+            exchange_sent: None,
+            exchange_rcvd: None,
+        }
+    }
+
+    /// The contact `my_call` made in `mode` from a sequence of timestamped messages, from
+    /// the standard, RTTY Roundup, Field Day and EU VHF exchanges in it. Messages between
+    /// other stations, and with anyone but the first station worked, are skipped.
+    pub fn from_exchanges(
+        my_call: &str,
+        mode: &str,
+        exchanges: &[(SystemTime, MessageData)],
+    ) -> Option<Self> {
+        let my_call = my_call.to_uppercase();
+        let mut qso: Option<Qso> = None;
+        for (time, data) in exchanges {
+            let Some(exchange) = exchange(data) else {
+                continue;
+            };
+            let (call, sent) = if exchange.from == my_call {
+                (&exchange.to, true)
+            } else if exchange.to == my_call {
+                (&exchange.from, false)
+            } else {
+                continue;
+            };
+            if !is_station(call) {
+                continue;
+            }
+            let qso = qso.get_or_insert_with(|| {
+                let mut qso = Qso::new(call, mode, *time);
+                qso.station_callsign = Some(my_call.clone());
+                qso
+            });
+            if qso.call != *call {
+                continue;
+            }
+            qso.time_on = qso.time_on.min(*time);
+            qso.time_off = qso.time_off.max(*time);
+            if let Some(contest_id) = exchange.contest_id {
+                qso.contest_id = Some(contest_id.to_string());
+            }
+            if sent {
+                qso.rst_sent = exchange.report.or(qso.rst_sent.take());
+                qso.exchange_sent = exchange.exchange.or(qso.exchange_sent.take());
+            } else {
+                qso.rst_rcvd = exchange.report.or(qso.rst_rcvd.take());
+                qso.grid = exchange.grid.or(qso.grid.take());
+                qso.exchange_rcvd = exchange.exchange.or(qso.exchange_rcvd.take());
+            }
+        }
+        qso
+    }
+
+    /// Sets the frequency and the band it falls in.
+    pub fn set_frequency(&mut self, freq_hz: u64) {
+        self.freq_hz = Some(freq_hz);
+        self.band = band(freq_hz).map(str::to_string);
+    }
+
+    /// The contact as one ADI record, ending in `<EOR>`.
+    pub fn to_adif(&self) -> String {
+        let mut record = String::new();
+        let (date_on, time_on) = date_time(self.time_on);
+        let (date_off, time_off) = date_time(self.time_off);
+        put_field(&mut record, "CALL", Some(&self.call));
+        put_field(&mut record, "QSO_DATE", Some(&date_on));
+        put_field(&mut record, "TIME_ON", Some(&time_on));
+        put_field(&mut record, "QSO_DATE_OFF", Some(&date_off));
+        put_field(&mut record, "TIME_OFF", Some(&time_off));
+        put_field(&mut record, "BAND", self.band.as_deref());
+        let freq = self
+            .freq_hz
+            .map(|hz| format!("{}.{:06}", hz / 1_000_000, hz % 1_000_000));
+        put_field(&mut record, "FREQ", freq.as_deref());
+        if MFSK_SUBMODES.contains(&self.mode.as_str()) {
+            put_field(&mut record, "MODE", Some("MFSK"));
+            put_field(&mut record, "SUBMODE", Some(&self.mode));
+        } else {
+            put_field(&mut record, "MODE", Some(&self.mode));
+        }
+        put_field(&mut record, "RST_SENT", self.rst_sent.as_deref());
+        put_field(&mut record, "RST_RCVD", self.rst_rcvd.as_deref());
+        put_field(&mut record, "GRIDSQUARE", self.grid.as_deref());
+        put_field(
+            &mut record,
+            "STATION_CALLSIGN",
+            self.station_callsign.as_deref(),
+        );
+        put_field(&mut record, "CONTEST_ID", self.contest_id.as_deref());
+        put_field(&mut record, "STX_STRING", self.exchange_sent.as_deref());
+        put_field(&mut record, "SRX_STRING", self.exchange_rcvd.as_deref());
+        record.push_str("<EOR>\n");
+        record
+    }
+
+    fn from_fields(fields: &HashMap<String, String>) -> Result<Self, Error> {
+        let field = |name: &str| fields.get(name).filter(|value| !value.is_empty()).cloned();
+        let call = field("CALL").ok_or_else(|| missing("CALL"))?;
+        let time_on = parse_date_time(
+            &field("QSO_DATE").ok_or_else(|| missing("QSO_DATE"))?,
+            &field("TIME_ON").ok_or_else(|| missing("TIME_ON"))?,
+        )?;
+        let time_off = match (field("QSO_DATE_OFF"), field("TIME_OFF")) {
+            (Some(date), Some(time)) => parse_date_time(&date, &time)?,
+            (None, Some(time)) => parse_date_time(&fields["QSO_DATE"], &time)?,
+            _ => time_on,
+        };
+        let freq_hz = match field("FREQ") {
+            Some(freq) => Some(
+                freq.parse::<f64>()
+                    .map(|mhz| (mhz * 1e6).round() as u64)
+                    .map_err(|_| Error::MalformedLog(format!("invalid FREQ {}", freq)))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            call: call.to_uppercase(),
+            station_callsign: field("STATION_CALLSIGN"),
+            grid: field("GRIDSQUARE"),
+            rst_sent: field("RST_SENT"),
+            rst_rcvd: field("RST_RCVD"),
+            band: field("BAND").map(|band| band.to_lowercase()),
+            freq_hz,
+            mode: field("SUBMODE").or(field("MODE")).unwrap_or_default(),
+            time_on,
+            time_off,
+            contest_id: field("CONTEST_ID"),
+            exchange_sent: field("STX_STRING"),
+            exchange_rcvd: field("SRX_STRING"),
+        })
+    }
+}
+
+/// The ADIF band `freq_hz` falls in.
+pub fn band(freq_hz: u64) -> Option<&'static str> {
+    BANDS
+        .iter()
+        .find(|(_, low, high)| (*low..=*high).contains(&freq_hz))
+        .map(|(name, _, _)| *name)
+}
+
+/// The header to start a new ADI file with.
+pub fn header() -> String {
+    let mut header = String::from("weaksignal ADIF export\n");
+    put_field(&mut header, "ADIF_VER", Some(ADIF_VERSION));
+    put_field(&mut header, "PROGRAMID", Some("weaksignal"));
+    put_field(
+        &mut header,
+        "PROGRAMVERSION",
+        Some(env!("CARGO_PKG_VERSION")),
+    );
+    header.push_str("<EOH>\n");
+    header
+}
+
+/// Writes a complete ADI file holding `qsos`.
+pub fn write<W: Write>(writer: &mut W, qsos: &[Qso]) -> Result<(), Error> {
+    writer.write_all(header().as_bytes())?;
+    for qso in qsos {
+        writer.write_all(qso.to_adif().as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads every record of an ADI file. Fields this crate does not model are skipped.
+pub fn read(text: &str) -> Result<Vec<Qso>, Error> {
+    let mut qsos = Vec::new();
+    let mut fields = HashMap::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let end = rest[start..]
+            .find('>')
+            .ok_or_else(|| Error::MalformedLog("unterminated field".to_string()))?;
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+        // <NAME:LENGTH> or <NAME:LENGTH:TYPE>
+        let mut parts = tag.split(':');
+        let name = parts.next().unwrap_or_default().trim().to_uppercase();
+        match (name.as_str(), parts.next()) {
+            // Fields before the end of the header describe the file, not a contact
+            ("EOH", _) => fields.clear(),
+            ("EOR", _) => {
+                qsos.push(Qso::from_fields(&fields)?);
+                fields.clear();
+            }
+            (_, Some(len)) => {
+                let len = len
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| Error::MalformedLog(format!("invalid length in <{}>", tag)))?;
+                let value: String = rest.chars().take(len).collect();
+                if value.chars().count() < len {
+                    return Err(Error::MalformedLog(format!("{} is cut short", name)));
+                }
+                rest = &rest[value.len()..];
+                fields.insert(name, value.trim().to_string());
+            }
+            (_, None) => return Err(Error::MalformedLog(format!("<{}> has no length", tag))),
+        }
+    }
+    if !fields.is_empty() {
+        return Err(Error::MalformedLog("last record has no <EOR>".to_string()));
+    }
+    Ok(qsos)
+}
+
+/// Calls and grids already in a log, to pick out decodes of stations not worked yet.
+#[derive(Debug, Clone, Default)]
+pub struct WorkedBefore {
+    // Bands each call or 4 character grid was worked on
+    calls: HashMap<String, HashSet<String>>,
+    grids: HashMap<String, HashSet<String>>,
+}
+
+impl WorkedBefore {
+    pub fn new(qsos: &[Qso]) -> Self {
+        let mut worked = Self::default();
+        qsos.iter().for_each(|qso| worked.add(qso));
+        worked
+    }
+
+    pub fn add(&mut self, qso: &Qso) {
+        let band = qso.band.clone();
+        self.calls
+            .entry(qso.call.to_uppercase())
+            .or_default()
+            .extend(band.clone());
+        if let Some(grid) = &qso.grid {
+            self.grids.entry(grid_key(grid)).or_default().extend(band);
+        }
+    }
+
+    /// Whether `call` is in the log, on `band` if one is given.
+    pub fn call(&self, call: &str, band: Option<&str>) -> bool {
+        worked(&self.calls, &call.to_uppercase(), band)
+    }
+
+    /// Whether the 4 character square of `grid` is in the log, on `band` if one is given.
+    pub fn grid(&self, grid: &str, band: Option<&str>) -> bool {
+        worked(&self.grids, &grid_key(grid), band)
+    }
+
+    /// Whether the station that sent `data` is in the log, on `band` if one is given.
+    /// `None` when the message does not say who sent it.
+    pub fn message(&self, data: &MessageData, band: Option<&str>) -> Option<bool> {
+        let sender = match data {
+            MessageData::NonStdCall(m) if m.callsign_swap => m.callsign1.to_string(),
+            MessageData::NonStdCall(m) => m.callsign2.to_string(),
+            _ => exchange(data)?.from,
+        };
+        is_station(&sender).then(|| self.call(&sender, band))
+    }
+}
+
+fn worked(index: &HashMap<String, HashSet<String>>, key: &str, band: Option<&str>) -> bool {
+    index
+        .get(key)
+        .is_some_and(|bands| band.is_none_or(|band| bands.contains(&band.to_lowercase())))
+}
+
+fn grid_key(grid: &str) -> String {
+    grid.chars().take(4).collect::<String>().to_uppercase()
+}
+
+fn exchange(data: &MessageData) -> Option<Exchange> {
+    let (callsign1, callsign2) = match data {
+        MessageData::StandardR(Standard {
+            callsign1,
+            callsign2,
+            ..
+        })
+        | MessageData::StandardP(Standard {
+            callsign1,
+            callsign2,
+            ..
+        }) => (callsign1.to_string(), callsign2.to_string()),
+        MessageData::RTTYRU(m) => (m.callsign1.to_string(), m.callsign2.to_string()),
+        MessageData::FieldDay(m) => (m.callsign1.to_string(), m.callsign2.to_string()),
+        MessageData::EUVHF(m) => (m.callsign1.to_string(), m.callsign2.to_string()),
+        _ => return None,
+    };
+    let mut exchange = Exchange {
+        to: callsign1,
+        from: callsign2,
+        report: None,
+        grid: None,
+        exchange: None,
+        contest_id: None,
+    };
+    match data {
+        MessageData::StandardR(Standard { grid, .. })
+        | MessageData::StandardP(Standard { grid, .. }) => match grid.value() {
+            Grid4Value::Locator(locator) if !grid.has_r => exchange.grid = Some(locator),
+            Grid4Value::Report(report) => exchange.report = Some(format!("{:+03}", report)),
+            _ => {}
+        },
+        MessageData::RTTYRU(m) => {
+            exchange.report = Some((m.signal_strength as u16 * 10 + 529).to_string());
+            exchange.exchange = Some(m.s13.to_string());
+            exchange.contest_id = Some("ARRL-RTTY");
+        }
+        MessageData::FieldDay(m) => {
+            exchange.exchange = Some(m.exchange());
+            exchange.contest_id = Some("ARRL-FD");
+        }
+        MessageData::EUVHF(m) => {
+            exchange.report = Some((m.signal_strength as u16 + 52).to_string());
+            // The grid is part of the exchange, so the locator sent is logged too
+            exchange.exchange = Some(format!("{:04} {}", m.serial_number, m.grid));
+            exchange.grid = Some(m.grid.to_string());
+        }
+        _ => {}
+    }
+    Some(exchange)
+}
+
+fn put_field(record: &mut String, name: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        let _ = write!(record, "<{}:{}>{} ", name, value.chars().count(), value);
+    }
+}
+
+fn missing(field: &str) -> Error {
+    Error::MalformedLog(format!("record without {}", field))
+}
+
+// ADIF dates are YYYYMMDD and times HHMMSS, both UTC
+fn date_time(time: SystemTime) -> (String, String) {
+    let ms = unix_ms(time);
+    let (year, month, day) = civil_from_days(ms.div_euclid(DAY_MS));
+    let seconds = ms.rem_euclid(DAY_MS) / 1000;
+    (
+        format!("{:04}{:02}{:02}", year, month, day),
+        format!(
+            "{:02}{:02}{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ),
+    )
+}
+
+fn parse_date_time(date: &str, time: &str) -> Result<SystemTime, Error> {
+    let invalid = || Error::MalformedLog(format!("invalid date and time {} {}", date, time));
+    let digits = |text: &str, range: std::ops::Range<usize>| {
+        text.get(range)
+            .filter(|digits| digits.chars().all(|c| c.is_ascii_digit()))
+            .and_then(|digits| digits.parse::<u32>().ok())
+            .ok_or_else(invalid)
+    };
+    if date.len() != 8 || !(time.len() == 4 || time.len() == 6) {
+        return Err(invalid());
+    }
+    let (year, month, day) = (
+        digits(date, 0..4)?,
+        digits(date, 4..6)?,
+        digits(date, 6..8)?,
+    );
+    let (hour, minute) = (digits(time, 0..2)?, digits(time, 2..4)?);
+    let second = if time.len() == 6 {
+        digits(time, 4..6)?
+    } else {
+        0
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+    let days = days_from_civil(year as i64, month, day);
+    let ms = days * DAY_MS + (hour as i64 * 3600 + minute as i64 * 60 + second as i64) * 1000;
+    from_unix_ms(ms).ok_or_else(invalid)
+}
@@ -0,0 +1,165 @@
+//! Cabrillo 3.0 logs for the contests FT8 has exchange messages for.
+//!
+//! QSO lines are written from [`Qso`] records built from [`FieldDay`], [`RTTYRU`] and
+//! [`EUVHF`] exchanges, the header fields are whatever the contest sponsor asks for
+//! and are passed through as given.
+//!
+//! [`FieldDay`]: crate::message::FieldDay
+//! [`RTTYRU`]: crate::message::RTTYRU
+//! [`EUVHF`]: crate::message::EUVHF
+
+use std::io::Write;
+
+use crate::{
+    adif::Qso,
+    utils::{civil_from_days, unix_ms},
+    Error,
+};
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+// Above 30 MHz Cabrillo logs the band rather than the frequency
+const VHF_BANDS: [(&str, u64, u64); 8] = [
+    ("50", 50_000_000, 54_000_000),
+    ("70", 70_000_000, 71_000_000),
+    ("144", 144_000_000, 148_000_000),
+    ("222", 222_000_000, 225_000_000),
+    ("432", 420_000_000, 450_000_000),
+    ("902", 902_000_000, 928_000_000),
+    ("1.2G", 1_240_000_000, 1_300_000_000),
+    ("2.3G", 2_300_000_000, 2_450_000_000),
+];
+
+/// The contest a log is for, which decides the layout of its QSO lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Contest {
+    /// ARRL Field Day: class and section.
+    FieldDay,
+    /// ARRL RTTY Roundup: RST and state, province or serial number.
+    RttyRoundup,
+    /// EU VHF contests: RS, serial number and 6 character locator.
+    EuVhf,
+}
+
+impl Contest {
+    /// The CONTEST header value, `None` where it differs between sponsors.
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Contest::FieldDay => Some("ARRL-FD"),
+            Contest::RttyRoundup => Some("ARRL-RTTY"),
+            Contest::EuVhf => None,
+        }
+    }
+
+    // Field Day calls every digital mode DI, the rest use the Cabrillo 3.0 DG
+    fn mode(&self) -> &'static str {
+        match self {
+            Contest::FieldDay => "DI",
+            _ => "DG",
+        }
+    }
+}
+
+/// Writes a complete log. `headers` are tag and value pairs such as
+/// `("CALLSIGN", "W9XYZ")`, written in order after `START-OF-LOG`. CONTEST and
+/// CREATED-BY are added when not given.
+pub fn write<W: Write>(
+    writer: &mut W,
+    contest: Contest,
+    headers: &[(&str, &str)],
+    qsos: &[Qso],
+) -> Result<(), Error> {
+    let header = |tag: &str| {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(tag))
+            .map(|(_, value)| *value)
+    };
+    writeln!(writer, "START-OF-LOG: 3.0")?;
+    if let (None, Some(name)) = (header("CONTEST"), contest.name()) {
+        writeln!(writer, "CONTEST: {}", name)?;
+    }
+    for (tag, value) in headers {
+        writeln!(writer, "{}: {}", tag.to_uppercase(), value)?;
+    }
+    if header("CREATED-BY").is_none() {
+        writeln!(
+            writer,
+            "CREATED-BY: weaksignal {}",
+            env!("CARGO_PKG_VERSION")
+        )?;
+    }
+    for qso in qsos {
+        writeln!(writer, "{}", qso_line(contest, qso, header("CALLSIGN"))?)?;
+    }
+    writeln!(writer, "END-OF-LOG:")?;
+    Ok(())
+}
+
+/// One `QSO:` line, without the line ending. The call sent is the QSO's station
+/// callsign, or `callsign` if it has none.
+pub fn qso_line(contest: Contest, qso: &Qso, callsign: Option<&str>) -> Result<String, Error> {
+    let incomplete =
+        |what: &str| Error::MalformedLog(format!("QSO with {} has no {}", qso.call, what));
+    let my_call = qso
+        .station_callsign
+        .as_deref()
+        .or(callsign)
+        .ok_or_else(|| incomplete("station callsign"))?;
+    let frequency = frequency(qso).ok_or_else(|| incomplete("frequency"))?;
+    let exchange_sent = qso
+        .exchange_sent
+        .as_deref()
+        .ok_or_else(|| incomplete("exchange sent"))?;
+    let exchange_rcvd = qso
+        .exchange_rcvd
+        .as_deref()
+        .ok_or_else(|| incomplete("exchange received"))?;
+    let (sent, rcvd) = match contest {
+        Contest::FieldDay => (exchange_sent.to_string(), exchange_rcvd.to_string()),
+        Contest::RttyRoundup | Contest::EuVhf => {
+            let rst_sent = qso
+                .rst_sent
+                .as_deref()
+                .ok_or_else(|| incomplete("report sent"))?;
+            let rst_rcvd = qso
+                .rst_rcvd
+                .as_deref()
+                .ok_or_else(|| incomplete("report received"))?;
+            (
+                format!("{:<3} {}", rst_sent, exchange_sent),
+                format!("{:<3} {}", rst_rcvd, exchange_rcvd),
+            )
+        }
+    };
+    let ms = unix_ms(qso.time_on);
+    let (year, month, day) = civil_from_days(ms.div_euclid(DAY_MS));
+    let minutes = ms.rem_euclid(DAY_MS) / 60_000;
+    Ok(format!(
+        "QSO: {:>5} {} {:04}-{:02}-{:02} {:02}{:02} {:<13} {:<10} {:<13} {}",
+        frequency,
+        contest.mode(),
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60,
+        my_call,
+        sent,
+        qso.call,
+        rcvd,
+    )
+    .trim_end()
+    .to_string())
+}
+
+// kHz below 30 MHz, the band above
+fn frequency(qso: &Qso) -> Option<String> {
+    match qso.freq_hz {
+        Some(hz) if hz < 30_000_000 => Some((hz / 1000).to_string()),
+        Some(hz) => VHF_BANDS
+            .iter()
+            .find(|(_, low, high)| (*low..=*high).contains(&hz))
+            .map(|(band, _, _)| band.to_string()),
+        None => None,
+    }
+}
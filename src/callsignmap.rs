@@ -2,10 +2,15 @@ use std::{collections::{HashMap, hash_map}, num::Wrapping};
 
 use static_init::dynamic;
 
+/// Callsigns heard so far, used to resolve hashed callsigns.
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CallsignMap {
     pub map: HashMap<u32, String>,
     pub map12: HashMap<u16, u32>,
+    // WSPR sends callsigns as a 15 bit hash unrelated to the FT8 ones
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub map15: HashMap<u16, String>,
 }
 
 fn compute_char_map() -> [u8; 256] {
@@ -25,6 +30,7 @@ impl CallsignMap {
         Self {
             map: HashMap::new(),
             map12: HashMap::new(),
+            map15: HashMap::new(),
         }
     }
     pub(crate) fn hash(callsign: &String) -> u32 {
@@ -35,6 +41,54 @@ impl CallsignMap {
             .fold(0, |acc, c| acc * 38 + CHAR_MAP[c as usize] as u64);
         ((Wrapping(hash) * Wrapping(47055833459)).0 >> (64 - 22)) as u32
     }
+    // Bob Jenkins' lookup3 hashlittle, as WSJT-X's nhash
+    pub(crate) fn hash15(callsign: &str) -> u16 {
+        fn mix(a: &mut u32, b: &mut u32, c: &mut u32) {
+            *a = a.wrapping_sub(*c) ^ c.rotate_left(4);
+            *c = c.wrapping_add(*b);
+            *b = b.wrapping_sub(*a) ^ a.rotate_left(6);
+            *a = a.wrapping_add(*c);
+            *c = c.wrapping_sub(*b) ^ b.rotate_left(8);
+            *b = b.wrapping_add(*a);
+            *a = a.wrapping_sub(*c) ^ c.rotate_left(16);
+            *c = c.wrapping_add(*b);
+            *b = b.wrapping_sub(*a) ^ a.rotate_left(19);
+            *a = a.wrapping_add(*c);
+            *c = c.wrapping_sub(*b) ^ b.rotate_left(4);
+            *b = b.wrapping_add(*a);
+        }
+        fn finish(a: &mut u32, b: &mut u32, c: &mut u32) {
+            *c = (*c ^ *b).wrapping_sub(b.rotate_left(14));
+            *a = (*a ^ *c).wrapping_sub(c.rotate_left(11));
+            *b = (*b ^ *a).wrapping_sub(a.rotate_left(25));
+            *c = (*c ^ *b).wrapping_sub(b.rotate_left(16));
+            *a = (*a ^ *c).wrapping_sub(c.rotate_left(4));
+            *b = (*b ^ *a).wrapping_sub(a.rotate_left(14));
+            *c = (*c ^ *b).wrapping_sub(b.rotate_left(24));
+        }
+        let word = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+        let mut key = callsign.as_bytes();
+        let init = 0xdeadbeefu32
+            .wrapping_add(key.len() as u32)
+            .wrapping_add(146);
+        let (mut a, mut b, mut c) = (init, init, init);
+        while key.len() > 12 {
+            a = a.wrapping_add(word(&key[0..4]));
+            b = b.wrapping_add(word(&key[4..8]));
+            c = c.wrapping_add(word(&key[8..12]));
+            mix(&mut a, &mut b, &mut c);
+            key = &key[12..];
+        }
+        if !key.is_empty() {
+            let mut last = [0; 12];
+            last[..key.len()].copy_from_slice(key);
+            a = a.wrapping_add(word(&last[0..4]));
+            b = b.wrapping_add(word(&last[4..8]));
+            c = c.wrapping_add(word(&last[8..12]));
+            finish(&mut a, &mut b, &mut c);
+        }
+        (c & 0x7fff) as u16
+    }
     pub fn insert(&mut self, callsign: &String) -> u32 {
         let hash = Self::hash(&callsign);
         self.map.insert(hash as u32, callsign.clone());
@@ -45,6 +99,13 @@ impl CallsignMap {
         let hash = Self::hash(&callsign);
         self.map.remove(&hash);
         self.map12.remove(&((hash >> 10) as u16));
+        self.map15.remove(&Self::hash15(callsign));
+    }
+    /// Adds a callsign heard on WSPR, returning its 15 bit hash.
+    pub fn insert_wspr(&mut self, callsign: &str) -> u16 {
+        let hash = Self::hash15(callsign);
+        self.map15.insert(hash, callsign.to_string());
+        hash
     }
     pub fn get_22(&self, hash: u32) -> Option<&String> {
         self.map.get(&hash)
@@ -52,6 +113,9 @@ impl CallsignMap {
     pub fn get_12(&self, hash: u16) -> Option<&String> {
         self.map12.get(&hash).and_then(|&hash| self.map.get(&hash))
     }
+    pub fn get_15(&self, hash: u16) -> Option<&String> {
+        self.map15.get(&hash)
+    }
     pub fn get_10(&self, hash: u16) -> Option<&String> {
         (0..4)
             .map(|i| (hash << 2) + i)
@@ -1,6 +1,38 @@
 
 pub(crate) const FT8_SYMBOLS: [u8; 8] = [0, 1, 3, 2, 5, 6, 4, 7];
 
+pub(crate) const FT8_COSTAS: [u8; 7] = [3, 1, 4, 0, 6, 5, 2];
+
+pub(crate) const FT4_SYMBOLS: [u8; 4] = [0, 1, 3, 2];
+
+pub(crate) const FT4_COSTAS: [[u8; 4]; 4] =
+    [[0, 1, 3, 2], [1, 0, 2, 3], [2, 3, 1, 0], [3, 2, 0, 1]];
+
+// Added to the payload modulo 2 before encoding so FT4 never sends long runs of one tone
+pub(crate) const FT4_SCRAMBLE: [u8; 77] = [
+    0, 1, 0, 0, 1, 0, 1, 0, 0, 1, 0, 1, 1, 1, 1, 0, 1, 0, 0, 0, 1, 0, 0, 1, 1, 0, 1, 1, 0, 1, 0, 0,
+    1, 0, 1, 1, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 1, 0, 0, 1, 1, 1, 1, 0, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1,
+    1, 0, 1, 1, 1, 1, 1, 0, 0, 0, 1, 0, 1,
+];
+
+// Low bit of every WSPR channel symbol, the data bit is sent in the high bit
+pub(crate) const WSPR_SYNC: [u8; 162] = [
+    1, 1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1, 0, 1, 1, 1, 1, 0, 0, 0, 0, 0,
+    0, 0, 1, 0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 1, 0, 1, 1, 0, 0, 1, 1, 0, 1, 0, 0, 0, 1, 1, 0, 1, 0,
+    0, 0, 0, 1, 1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 0, 1, 0, 0, 1, 0, 1, 1, 0, 0, 0, 1, 1, 0, 1, 0, 1, 0,
+    0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 1, 0, 0, 1, 1, 1, 0, 1, 1, 0, 0, 1, 1, 0, 1, 0, 0, 0, 1, 1, 1,
+    0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 0, 1, 1, 0, 0, 0, 1, 1, 0,
+    0, 0,
+];
+
+// 1 where JT65 sends the sync tone, the data symbols fill the other 63 places
+pub(crate) const JT65_SYNC: [u8; 126] = [
+    1, 0, 0, 1, 1, 0, 0, 0, 1, 1, 1, 1, 1, 1, 0, 1, 0, 1, 0, 0, 0, 1, 0, 1, 1, 0, 0, 1, 0, 0, 0, 1,
+    1, 1, 0, 0, 1, 1, 1, 1, 0, 1, 1, 0, 1, 1, 1, 1, 0, 0, 0, 1, 1, 0, 1, 0, 1, 0, 1, 1, 0, 0, 1, 1,
+    0, 1, 0, 1, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 0, 0, 1, 0,
+    1, 1, 0, 1, 0, 1, 0, 1, 0, 0, 1, 1, 0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1,
+];
+
 pub(crate) const FT8_NM: [[u8; 7]; 83] = [
     [3, 30, 58, 90, 91, 95, 152],
     [4, 31, 59, 92, 114, 145, 255],
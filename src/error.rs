@@ -0,0 +1,66 @@
+use std::fmt::Display;
+
+use realfft::FftError;
+
+use crate::message::{callsign::CallsignError, grid::GridError};
+
+#[derive(Debug)]
+pub enum Error {
+    Fft(FftError),
+    // A field unpacked to a value the protocol never sends
+    InvalidField {
+        field: &'static str,
+        value: u64,
+    },
+    Callsign(CallsignError),
+    Grid(GridError),
+    Io(std::io::Error),
+    // A network packet that is truncated or does not follow its protocol
+    MalformedPacket(String),
+    // A log file that does not follow its format
+    MalformedLog(String),
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fft(err) => write!(f, "FFT failed: {}", err),
+            Self::InvalidField { field, value } => write!(f, "invalid {} {}", field, value),
+            Self::Callsign(err) => write!(f, "{}", err),
+            Self::Grid(err) => write!(f, "{}", err),
+            Self::Io(err) => write!(f, "{}", err),
+            Self::MalformedPacket(reason) => write!(f, "malformed packet: {}", reason),
+            Self::MalformedLog(reason) => write!(f, "malformed log: {}", reason),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Fft(err) => Some(err),
+            Self::Callsign(err) => Some(err),
+            Self::Grid(err) => Some(err),
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+impl From<FftError> for Error {
+    fn from(err: FftError) -> Self {
+        Self::Fft(err)
+    }
+}
+impl From<CallsignError> for Error {
+    fn from(err: CallsignError) -> Self {
+        Self::Callsign(err)
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+impl From<GridError> for Error {
+    fn from(err: GridError) -> Self {
+        Self::Grid(err)
+    }
+}
@@ -0,0 +1,508 @@
+//! DXpedition Fox and Hound operation.
+//!
+//! The Fox transmits below 1000 Hz on up to five frequencies at once, each stream
+//! giving one Hound RR73 and the next Hound its report in a single [`Dxpedition`]
+//! message. Hounds call above 1000 Hz, listen only to the Fox, answer its report
+//! with R and a report and are done when the Fox sends them RR73.
+//!
+//! [`Dxpedition`]: crate::message::Dxpedition
+
+use std::{
+    collections::{HashSet, VecDeque},
+    iter::zip,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    adif::Qso,
+    ft8::{EncodeParams, FT8Encoder},
+    message::{callsign::is_station, HashedCallsign, Message, MessageData},
+    sequencer::{heard, is_standard, report, Heard, Slot},
+};
+
+// Hounds call above this and the Fox transmits below it
+pub const HOUND_MIN_FREQ: f32 = 1000.0;
+const HOUND_MAX_FREQ: f32 = 2950.0;
+// Bandwidth of one FT8 signal
+const SIGNAL_WIDTH: f32 = 50.0;
+const CYCLE_MS: u64 = 15_000;
+// Cycles after a Hound is dropped in which its R still completes the contact
+const LATE_ROGER_CYCLES: u64 = 4;
+
+/// Audio of `streams`, each a frequency and a message, sent at once. The total
+/// amplitude is shared between the streams, JT65 messages have no FT8 form and are
+/// left out.
+pub fn synthesize(streams: &[(f32, MessageData)], sample_rate: f32, amplitude: f32) -> Vec<f32> {
+    let mut samples = Vec::new();
+    for (freq, data) in streams {
+        let Some(bits) = data.to_bits() else {
+            continue;
+        };
+        let encoder = FT8Encoder::new(&EncodeParams {
+            sr: sample_rate,
+            freq: *freq,
+            amplitude: amplitude / streams.len() as f32,
+        });
+        let wave = encoder.encode(&bits);
+        samples.resize(samples.len().max(wave.len()), 0.0);
+        zip(&mut samples, wave).for_each(|(s, w)| *s += w);
+    }
+    samples
+}
+
+/// A clear frequency for a Hound to call on, the one furthest from the signals in
+/// `messages` above 1000 Hz.
+pub fn pick_tx_freq(messages: &[Message]) -> f32 {
+    let occupied: Vec<f32> = messages
+        .iter()
+        .map(|message| message.freq_bin_hz as f32)
+        .filter(|freq| {
+            (HOUND_MIN_FREQ - SIGNAL_WIDTH..=HOUND_MAX_FREQ + SIGNAL_WIDTH).contains(freq)
+        })
+        .collect();
+    let mut best = (HOUND_MIN_FREQ, f32::MIN);
+    let mut freq = HOUND_MIN_FREQ;
+    while freq <= HOUND_MAX_FREQ {
+        let clearance = occupied
+            .iter()
+            .map(|signal| (freq - signal).abs())
+            .fold(f32::MAX, f32::min);
+        if clearance > best.1 {
+            best = (freq, clearance);
+        }
+        freq += 10.0;
+    }
+    best.0
+}
+
+fn cycle_start(cycle: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(cycle * CYCLE_MS)
+}
+
+pub struct HoundParams {
+    pub my_call: String,
+    pub my_grid: String,
+    pub fox_call: String,
+    // Raised to 1000 Hz if lower
+    pub tx_freq: f32,
+    // Calls without an answer from the Fox before giving up
+    pub max_calls: u32,
+    // R reports sent without the Fox's RR73 before giving up
+    pub max_reports: u32,
+}
+
+impl Default for HoundParams {
+    fn default() -> Self {
+        HoundParams {
+            my_call: String::new(),
+            my_grid: String::new(),
+            fox_call: String::new(),
+            tx_freq: 1200.0,
+            max_calls: 10,
+            max_reports: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoundState {
+    Idle,
+    /// Calling the Fox with the locator.
+    Calling,
+    /// Sending R and a report after the Fox's report.
+    Reported,
+}
+
+pub struct Hound {
+    params: HoundParams,
+    state: HoundState,
+    slot: Slot,
+    calls: u32,
+    reports: u32,
+    // Last SNR the Fox was heard at
+    fox_snr: f32,
+    report_rcvd: Option<i16>,
+    report_sent: Option<i16>,
+    time_on: SystemTime,
+    logged: Vec<Qso>,
+}
+
+impl Hound {
+    pub fn new(params: HoundParams) -> Self {
+        let params = HoundParams {
+            my_call: params.my_call.to_uppercase(),
+            my_grid: params.my_grid.to_uppercase(),
+            fox_call: params.fox_call.to_uppercase(),
+            tx_freq: params.tx_freq.max(HOUND_MIN_FREQ),
+            ..params
+        };
+        Self {
+            params,
+            state: HoundState::Idle,
+            slot: Slot::Odd,
+            calls: 0,
+            reports: 0,
+            fox_snr: 0.0,
+            report_rcvd: None,
+            report_sent: None,
+            time_on: UNIX_EPOCH,
+            logged: Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> HoundState {
+        self.state
+    }
+    pub fn tx_freq(&self) -> f32 {
+        self.params.tx_freq
+    }
+    /// Moves the calling frequency, never below 1000 Hz.
+    pub fn set_tx_freq(&mut self, freq: f32) {
+        self.params.tx_freq = freq.max(HOUND_MIN_FREQ);
+    }
+
+    /// Starts calling the Fox, in the slot after the Fox's if it has been heard.
+    pub fn call(&mut self) {
+        self.state = HoundState::Calling;
+        self.calls = 0;
+        self.report_rcvd = None;
+    }
+
+    pub fn halt(&mut self) {
+        self.state = HoundState::Idle;
+    }
+
+    /// The messages in `messages` sent by the Fox.
+    pub fn fox_messages<'a>(&self, messages: &'a [Message]) -> Vec<&'a Message> {
+        let fox_hash = HashedCallsign::<10>::from_callsign(&self.params.fox_call).hashed;
+        messages
+            .iter()
+            .filter(|message| match &message.data {
+                MessageData::Dxpedition(m) => m.hashed_callsign.hashed == fox_hash,
+                data => heard(data).is_some_and(|(_, from, _)| from == self.params.fox_call),
+            })
+            .collect()
+    }
+
+    /// Follows the Fox's messages decoded in `cycle`.
+    pub fn receive(&mut self, messages: &[Message], cycle: u64) {
+        let me = self.params.my_call.clone();
+        for message in self.fox_messages(messages) {
+            self.slot = Slot::of(cycle).other();
+            self.fox_snr = message.snr();
+            // What the Fox says to this station: a report, or None for RR73
+            let to_me = match &message.data {
+                MessageData::Dxpedition(m) if m.callsign1.to_string() == me => Some(None),
+                MessageData::Dxpedition(m) if m.callsign2.to_string() == me => {
+                    Some(Some(m.signal_strength as i16 * 2 - 30))
+                }
+                data => match heard(data) {
+                    Some((to, _, heard)) if to == me => match heard {
+                        Heard::Report | Heard::RogerReport => Some(message.report()),
+                        Heard::Roger | Heard::SeventyThree => Some(None),
+                        Heard::Grid => None,
+                    },
+                    _ => None,
+                },
+            };
+            match (self.state, to_me) {
+                (HoundState::Calling | HoundState::Reported, Some(Some(report))) => {
+                    if self.state == HoundState::Calling {
+                        self.time_on = cycle_start(cycle);
+                        self.reports = 0;
+                    }
+                    self.state = HoundState::Reported;
+                    self.report_rcvd = Some(report);
+                }
+                (HoundState::Reported, Some(None)) => self.complete(cycle),
+                _ => {}
+            }
+        }
+    }
+
+    /// The message to send in `cycle` on [`Hound::tx_freq`], `None` when idle or when
+    /// `cycle` is the Fox's.
+    pub fn transmit(&mut self, cycle: u64) -> Option<MessageData> {
+        if Slot::of(cycle) != self.slot {
+            return None;
+        }
+        let me = &self.params.my_call;
+        let fox = if is_standard(&self.params.fox_call) {
+            self.params.fox_call.clone()
+        } else {
+            format!("<{}>", self.params.fox_call)
+        };
+        let text = match self.state {
+            HoundState::Idle => return None,
+            HoundState::Calling => {
+                if self.calls >= self.params.max_calls {
+                    self.state = HoundState::Idle;
+                    return None;
+                }
+                self.calls += 1;
+                let grid = self.params.my_grid.get(..4).unwrap_or(&self.params.my_grid);
+                format!("{} {} {}", fox, me, grid)
+            }
+            HoundState::Reported => {
+                if self.reports >= self.params.max_reports {
+                    self.state = HoundState::Idle;
+                    return None;
+                }
+                self.reports += 1;
+                let report = report(self.fox_snr);
+                self.report_sent = Some(report);
+                format!("{} {} R{:+03}", fox, me, report)
+            }
+        };
+        MessageData::from_text(&text)
+    }
+
+    /// Contacts completed since the last call.
+    pub fn take_logged(&mut self) -> Vec<Qso> {
+        std::mem::take(&mut self.logged)
+    }
+
+    fn complete(&mut self, cycle: u64) {
+        let mut qso = Qso::new(&self.params.fox_call, "FT8", self.time_on);
+        qso.time_off = cycle_start(cycle);
+        qso.station_callsign = Some(self.params.my_call.clone());
+        qso.rst_sent = self.report_sent.map(|report| format!("{:+03}", report));
+        qso.rst_rcvd = self.report_rcvd.map(|report| format!("{:+03}", report));
+        self.logged.push(qso);
+        self.state = HoundState::Idle;
+    }
+}
+
+pub struct FoxParams {
+    pub my_call: String,
+    pub my_grid: String,
+    // Simultaneous signals, at most 5
+    pub streams: usize,
+    pub base_freq: f32,
+    pub stream_spacing: f32,
+    // Reports sent to a Hound without an R in return before moving on, an R that
+    // arrives shortly after still completes the contact
+    pub max_repeats: u32,
+}
+
+impl Default for FoxParams {
+    fn default() -> Self {
+        FoxParams {
+            my_call: String::new(),
+            my_grid: String::new(),
+            streams: 5,
+            base_freq: 300.0,
+            stream_spacing: 60.0,
+            max_repeats: 3,
+        }
+    }
+}
+
+// A Hound the Fox has heard
+struct Caller {
+    call: String,
+    grid: Option<String>,
+    snr: f32,
+    time_on: SystemTime,
+    report_sent: Option<i16>,
+    report_rcvd: Option<i16>,
+    repeats: u32,
+}
+
+pub struct Fox {
+    params: FoxParams,
+    // Hounds waiting for a report, in the order they called
+    queue: VecDeque<Caller>,
+    // Hounds sent a report and waiting for their R
+    working: Vec<Caller>,
+    // Hounds that sent their R and are owed RR73
+    acknowledged: Vec<Caller>,
+    // Hounds moved on from without an R, with the cycle they were dropped in
+    dropped: Vec<(u64, Caller)>,
+    worked: HashSet<String>,
+    logged: Vec<Qso>,
+}
+
+impl Fox {
+    pub fn new(params: FoxParams) -> Self {
+        let params = FoxParams {
+            my_call: params.my_call.to_uppercase(),
+            my_grid: params.my_grid.to_uppercase(),
+            streams: params.streams.clamp(1, 5),
+            ..params
+        };
+        Self {
+            params,
+            queue: VecDeque::new(),
+            working: Vec::new(),
+            acknowledged: Vec::new(),
+            dropped: Vec::new(),
+            worked: HashSet::new(),
+            logged: Vec::new(),
+        }
+    }
+
+    /// Callers waiting for a report, first to be worked first.
+    pub fn queue(&self) -> Vec<&str> {
+        self.queue
+            .iter()
+            .map(|caller| caller.call.as_str())
+            .collect()
+    }
+
+    /// Queues Hounds calling and notes the ones sending R, from decodes in `cycle`.
+    pub fn receive(&mut self, messages: &[Message], cycle: u64) {
+        for message in messages {
+            let Some((to, from, heard)) = heard(&message.data) else {
+                continue;
+            };
+            if to != self.params.my_call || !is_station(&from) {
+                continue;
+            }
+            match heard {
+                Heard::Grid => {
+                    let known = self
+                        .queue
+                        .iter()
+                        .chain(&self.working)
+                        .chain(&self.acknowledged)
+                        .any(|caller| caller.call == from);
+                    if !known {
+                        self.queue.push_back(Caller {
+                            call: from,
+                            grid: message.grid(),
+                            snr: message.snr(),
+                            time_on: cycle_start(cycle),
+                            report_sent: None,
+                            report_rcvd: None,
+                            repeats: 0,
+                        });
+                    }
+                }
+                Heard::RogerReport => {
+                    let late = |(dropped, caller): &(u64, Caller)| {
+                        caller.call == from && cycle.saturating_sub(*dropped) <= LATE_ROGER_CYCLES
+                    };
+                    if let Some(i) = self.working.iter().position(|caller| caller.call == from) {
+                        let mut caller = self.working.remove(i);
+                        caller.report_rcvd = message.report();
+                        self.acknowledged.push(caller);
+                    } else if let Some(i) = self.dropped.iter().position(late) {
+                        let (_, mut caller) = self.dropped.remove(i);
+                        caller.report_rcvd = message.report();
+                        self.acknowledged.push(caller);
+                    } else if self.worked.contains(&from)
+                        && !self.acknowledged.iter().any(|caller| caller.call == from)
+                    {
+                        // The Hound missed its RR73, send it again
+                        self.acknowledged.push(Caller {
+                            call: from,
+                            grid: None,
+                            snr: message.snr(),
+                            time_on: cycle_start(cycle),
+                            report_sent: None,
+                            report_rcvd: None,
+                            repeats: 0,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The streams to send in `cycle`, each a frequency and a message. Pairs an RR73
+    /// with the next report where it can, and calls CQ when nobody is waiting.
+    pub fn transmit(&mut self, cycle: u64) -> Vec<(f32, MessageData)> {
+        let streams = self.params.streams;
+        let max_repeats = self.params.max_repeats;
+        let (dropped, working) = std::mem::take(&mut self.working)
+            .into_iter()
+            .partition::<Vec<_>, _>(|caller| caller.repeats >= max_repeats);
+        self.working = working;
+        self.dropped
+            .retain(|(dropped, _)| cycle.saturating_sub(*dropped) <= LATE_ROGER_CYCLES);
+        self.dropped
+            .extend(dropped.into_iter().map(|caller| (cycle, caller)));
+        while self.working.len() < streams {
+            let Some(caller) = self.queue.pop_front() else {
+                break;
+            };
+            self.working.push(caller);
+        }
+        let rr73: Vec<Caller> = self
+            .acknowledged
+            .drain(..self.acknowledged.len().min(streams))
+            .collect();
+
+        let fox = &self.params.my_call;
+        let hashed_fox = format!("<{}>", fox);
+        let mut texts = Vec::new();
+        for i in 0..rr73.len().max(self.working.len()) {
+            let report = self.working.get_mut(i).map(|caller| {
+                // The Dxpedition message only carries even reports
+                let report = ((caller.snr / 2.0).round() as i16 * 2).clamp(-30, 32);
+                caller.report_sent = Some(report);
+                caller.repeats += 1;
+                (caller.call.clone(), report)
+            });
+            let text = match (rr73.get(i), report) {
+                (Some(done), Some((call, report))) => {
+                    format!("{} RR73; {} {} {:+03}", done.call, call, hashed_fox, report)
+                }
+                (Some(done), None) => format!("{} {} RR73", done.call, self.fox_word()),
+                (None, Some((call, report))) => {
+                    format!("{} {} {:+03}", call, self.fox_word(), report)
+                }
+                (None, None) => continue,
+            };
+            texts.push(text);
+        }
+        if texts.is_empty() {
+            texts.push(if is_standard(fox) {
+                format!("CQ {} {}", fox, self.params.my_grid.get(..4).unwrap_or(""))
+            } else {
+                format!("CQ {}", fox)
+            });
+        }
+
+        let time = cycle_start(cycle);
+        for caller in rr73 {
+            // Hounds sent RR73 again are already in the log
+            if self.worked.insert(caller.call.clone()) {
+                let mut qso = Qso::new(&caller.call, "FT8", caller.time_on);
+                qso.time_off = time;
+                qso.station_callsign = Some(fox.clone());
+                qso.grid = caller.grid;
+                qso.rst_sent = caller.report_sent.map(|report| format!("{:+03}", report));
+                qso.rst_rcvd = caller.report_rcvd.map(|report| format!("{:+03}", report));
+                self.logged.push(qso);
+            }
+        }
+        texts
+            .iter()
+            .filter_map(|text| MessageData::from_text(text))
+            .enumerate()
+            .map(|(i, data)| {
+                (
+                    self.params.base_freq + i as f32 * self.params.stream_spacing,
+                    data,
+                )
+            })
+            .collect()
+    }
+
+    /// Contacts completed since the last call, a contact is complete once RR73 is sent.
+    pub fn take_logged(&mut self) -> Vec<Qso> {
+        std::mem::take(&mut self.logged)
+    }
+
+    // The Fox's call in standard messages, hashed when it is not a standard callsign
+    fn fox_word(&self) -> String {
+        if is_standard(&self.params.my_call) {
+            self.params.my_call.clone()
+        } else {
+            format!("<{}>", self.params.my_call)
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! FT4, the contest mode with 7.5 s cycles.
+//!
+//! FT4 carries the same 77 bit payloads as FT8, protected by the same CRC and (174,91)
+//! LDPC code, but sends them as 4-GFSK at 20.8 baud. The payload is scrambled before
+//! encoding and the 87 data symbols are framed by four 4x4 Costas arrays and a ramp
+//! symbol at each end, 105 symbols or 5.04 s in all.
+
+use std::{f32::consts::PI, iter::zip, path::Path};
+
+use realfft::RealFftPlanner;
+
+use crate::{
+    callsignmap::CallsignMap,
+    constants::{FT4_COSTAS, FT4_SCRAMBLE, FT4_SYMBOLS},
+    ft8::{DecodeParams, EncodeParams, FT8Decoder, OSD_MAX_HARD_ERRORS},
+    gfsk::{gfsk_modulate, GfskParams},
+    ldpc::{ldpc_decode, ldpc_encode, osd_decode},
+    message::{Message, MessageData},
+    resample::Resampler,
+    utils::{sigmoid, write_wav},
+    Error,
+};
+
+/// Length of one transmit or receive cycle in seconds.
+pub const CYCLE_SECONDS: f32 = 7.5;
+
+// Channel symbols, the ramp symbols at either end are not counted
+const FT4_CHANNEL_SYMBOLS: usize = 103;
+// First symbol of each Costas array
+const SYNC_OFFSETS: [usize; 4] = [0, 33, 66, 99];
+
+fn ft4_gfsk_params(sr: f32) -> GfskParams {
+    GfskParams {
+        sample_rate: sr,
+        symbol_rate: 12000.0 / 576.0,
+        tone_spacing: 12000.0 / 576.0,
+        bt: 1.0,
+        ramp_samples: (sr * 576.0 / 12000.0) as usize,
+    }
+}
+
+fn data_symbols() -> impl Iterator<Item = usize> + Clone {
+    (4..33).chain(37..66).chain(70..99)
+}
+
+// The scrambling is its own inverse
+fn scramble(message: &[bool; 77]) -> [bool; 77] {
+    let mut scrambled = *message;
+    zip(&mut scrambled, FT4_SCRAMBLE).for_each(|(bit, x)| *bit ^= x == 1);
+    scrambled
+}
+
+struct SyncCandidate {
+    frame: usize,
+    bin: usize,
+    score: f32,
+}
+
+/// Decodes whole FT4 cycles. Takes the same [`DecodeParams`] as FT8, signals are not
+/// subtracted so `decode_passes` has no effect.
+pub struct FT4Decoder {
+    sr: f32,
+    symbol_samples: usize,
+    fft_size: usize,
+    from_bin: usize,
+    to_bin: usize,
+    sync_threshold: f32,
+    max_candidates: usize,
+    osd_depth: Option<u8>,
+    osd_preprocess: bool,
+    decode_attempts: u32,
+    callsign_map: CallsignMap,
+    resampler: Option<Resampler>,
+}
+
+impl FT4Decoder {
+    pub fn new(params: &DecodeParams) -> FT4Decoder {
+        let resampler = (params.resample && params.sr != 12000.0)
+            .then(|| Resampler::new(params.sr.round() as usize, 12000));
+        let sr = if resampler.is_some() {
+            12000.0
+        } else {
+            params.sr
+        };
+        let symbol_samples = (sr * 576.0 / 12000.0).round() as usize;
+        // Two symbols per FFT gives bins at half the tone spacing
+        let fft_size = symbol_samples * 2;
+        FT4Decoder {
+            sr,
+            symbol_samples,
+            fft_size,
+            from_bin: (params.from_freq * fft_size as f32 / sr).ceil() as usize,
+            to_bin: ((params.to_freq * fft_size as f32 / sr).floor() as usize)
+                .min(fft_size / 2 - 2 * 4),
+            sync_threshold: params.sync_threshold,
+            max_candidates: params.max_candidates,
+            osd_depth: params.osd_depth.map(|depth| depth.min(3)),
+            osd_preprocess: params.osd_preprocess,
+            decode_attempts: params.decode_attempts,
+            callsign_map: CallsignMap::new(),
+            resampler,
+        }
+    }
+
+    /// Decodes one cycle of audio, best started on the cycle boundary.
+    pub fn decode_cycle(&mut self, samples: &[f32]) -> Result<Vec<Message>, Error> {
+        let resampled;
+        let samples = match self.resampler.as_mut() {
+            Some(resampler) => {
+                resampler.reset();
+                resampled = resampler.process(samples);
+                &resampled
+            }
+            None => samples,
+        };
+        let pwr = self.waterfall(samples)?;
+        let messages = self
+            .sync_candidates(&pwr)
+            .iter()
+            .filter_map(|candidate| self.decode_candidate(&pwr, candidate.bin, candidate.frame))
+            .collect();
+        Ok(Message::deduplicate_signals(messages))
+    }
+
+    pub fn insert_callsign(&mut self, callsign: &String) -> u32 {
+        self.callsign_map.insert(callsign)
+    }
+
+    // Power in dB of every bin, in frames half a symbol apart
+    fn waterfall(&self, samples: &[f32]) -> Result<Vec<Vec<f32>>, Error> {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let plan = planner.plan_fft_forward(self.fft_size);
+        let mut input = plan.make_input_vec();
+        let mut spectrum = plan.make_output_vec();
+        let window = (0..self.fft_size)
+            .map(|i| 0.5 * (1.0 - (2.0 * PI * (i as f32) / ((self.fft_size - 1) as f32)).cos()))
+            .collect::<Vec<_>>();
+        let hop = self.symbol_samples / 2;
+        let frames = samples.len().saturating_sub(self.fft_size) / hop + 1;
+        let mut pwr = Vec::with_capacity(frames);
+        for frame in samples.windows(self.fft_size).step_by(hop) {
+            zip(&mut input, zip(frame, &window)).for_each(|(i, (x, w))| *i = x * w);
+            plan.process(&mut input, &mut spectrum)?;
+            pwr.push(
+                spectrum
+                    .iter()
+                    .map(|x| (x.norm_sqr() + 1e-12).log10() * 10.0)
+                    .collect::<Vec<f32>>(),
+            );
+        }
+        Ok(pwr)
+    }
+
+    // `frame` is centred on the first Costas symbol, `j` is the bin of the lowest tone
+    fn sync_score(pwr: &[Vec<f32>], j: usize, frame: usize) -> f32 {
+        let score = zip(SYNC_OFFSETS, FT4_COSTAS)
+            .flat_map(|(start, costas)| (0..4).map(move |k| (start + k, costas[k] as usize)))
+            .map(|(symbol, tone)| {
+                let idx = frame + symbol * 2;
+                let expected = pwr[idx][j + tone * 2];
+                let total = (0..4).map(|k| pwr[idx][j + k * 2]).sum::<f32>();
+                expected - (total - expected) / 3.0
+            })
+            .sum::<f32>();
+        score / 16.0
+    }
+
+    // Peaks of the sync score in time and frequency
+    fn sync_candidates(&self, pwr: &[Vec<f32>]) -> Vec<SyncCandidate> {
+        let span = (FT4_CHANNEL_SYMBOLS - 1) * 2;
+        if pwr.len() <= span || self.from_bin >= self.to_bin {
+            return Vec::new();
+        }
+        let scores = (0..pwr.len() - span)
+            .map(|frame| {
+                (self.from_bin..self.to_bin)
+                    .map(|j| Self::sync_score(pwr, j, frame))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let score = |frame: isize, i: isize| {
+            scores
+                .get(frame as usize)
+                .and_then(|row| row.get(i as usize))
+                .copied()
+                .unwrap_or(f32::NEG_INFINITY)
+        };
+        let mut candidates = Vec::new();
+        for (frame, row) in scores.iter().enumerate() {
+            for (i, &s) in row.iter().enumerate() {
+                let (frame, i) = (frame as isize, i as isize);
+                // Ties go to the earliest frame and lowest bin
+                let peak = s >= self.sync_threshold
+                    && (-1..=1)
+                        .flat_map(|df| (-1..=1).map(move |di| (df, di)))
+                        .filter(|&offset| offset != (0, 0))
+                        .all(|(df, di)| {
+                            let other = score(frame + df, i + di);
+                            if (df, di) < (0, 0) {
+                                s > other
+                            } else {
+                                s >= other
+                            }
+                        });
+                if peak {
+                    candidates.push(SyncCandidate {
+                        frame: frame as usize,
+                        bin: self.from_bin + i as usize,
+                        score: s,
+                    });
+                }
+            }
+        }
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+        candidates.truncate(self.max_candidates);
+        candidates
+    }
+
+    fn decode_candidate(&self, pwr: &[Vec<f32>], j: usize, frame: usize) -> Option<Message> {
+        let mut codeword = [0.0; 174];
+        let data_idx = data_symbols().map(|symbol| frame + symbol * 2);
+        data_idx
+            .clone()
+            .flat_map(|idx| {
+                let s2 = FT4_SYMBOLS.map(|tone| pwr[idx][j + (tone as usize) * 2]);
+                let logl_0_1 = s2[2].max(s2[3]);
+                let logl_0_0 = s2[0].max(s2[1]);
+                let logl_1_1 = s2[1].max(s2[3]);
+                let logl_1_0 = s2[0].max(s2[2]);
+                [sigmoid(logl_0_1 - logl_0_0), sigmoid(logl_1_1 - logl_1_0)]
+            })
+            .enumerate()
+            .for_each(|(i, p)| codeword[i] = p);
+
+        let mut soft = [0.0; 174];
+        let decoded = ldpc_decode(&codeword, self.decode_attempts, &mut soft)
+            .filter(FT8Decoder::crc_matches)
+            .map(|decoded| (decoded, false))
+            .or_else(|| {
+                self.osd_fallback(&soft, &codeword)
+                    .map(|decoded| (decoded, true))
+            })
+            .filter(|(decoded, _)| decoded.iter().any(|&x| x))?;
+        let (decoded, fallback) = decoded;
+
+        let symbols = decoded.chunks_exact(2).map(|x| x[0] as u8 * 2 + x[1] as u8);
+        let (signal, noise) = zip(symbols, data_idx)
+            .map(|(symbol, idx)| {
+                let mut signal = 0.0;
+                let mut noise = 0.0;
+                for (k, &tone) in FT4_SYMBOLS.iter().enumerate() {
+                    let bin_pwr = pwr[idx][j + (tone as usize) * 2];
+                    if k as u8 == symbol {
+                        signal += bin_pwr;
+                    } else {
+                        noise += bin_pwr;
+                    }
+                }
+                (signal, noise)
+            })
+            .fold((0.0, 0.0), |(s, n), (s2, n2)| (s + s2, n + n2));
+        let signal = signal + 1e-12;
+        let noise = (noise + 1e-12) / 3.0;
+        let snr = ((signal - noise) / noise).log10() * 10.0;
+
+        // The frame is centred on the first Costas symbol, which follows the ramp symbol
+        let start_sample =
+            (frame * self.symbol_samples / 2) as i64 - (self.symbol_samples / 2) as i64;
+        let data = scramble(decoded.first_chunk::<77>()?);
+        let message = Message::from_bits(
+            snr,
+            (j as f32 * self.sr / self.fft_size as f32).round() as u32,
+            (start_sample as f64 * 1000.0 / self.sr as f64).round() as i64,
+            &data,
+            &self.callsign_map,
+        )
+        .ok()?;
+        (!fallback || !matches!(message.data, MessageData::Unknown(_))).then_some(message)
+    }
+
+    fn osd_fallback(&self, soft: &[f32; 174], codeword: &[f32; 174]) -> Option<[bool; 174]> {
+        let depth = self.osd_depth?;
+        let result = osd_decode(soft, codeword, depth, self.osd_preprocess);
+        (FT8Decoder::crc_matches(&result.codeword) && result.hard_errors <= OSD_MAX_HARD_ERRORS)
+            .then_some(result.codeword)
+    }
+}
+
+/// Generates FT4 audio. Takes the same [`EncodeParams`] as FT8.
+pub struct FT4Encoder {
+    sr: f32,
+    freq: f32,
+    amplitude: f32,
+}
+
+impl FT4Encoder {
+    pub fn new(params: &EncodeParams) -> FT4Encoder {
+        FT4Encoder {
+            sr: params.sr,
+            freq: params.freq,
+            amplitude: params.amplitude,
+        }
+    }
+
+    pub fn encode(&self, message: &[bool; 77]) -> Vec<f32> {
+        self.synthesize(&Self::encode_symbols(message))
+    }
+
+    /// Modulates the channel symbols, adding the ramp symbols at either end.
+    pub fn synthesize(&self, symbols: &[u8; 103]) -> Vec<f32> {
+        let mut framed = Vec::with_capacity(symbols.len() + 2);
+        framed.push(symbols[0]);
+        framed.extend(symbols);
+        framed.push(symbols[symbols.len() - 1]);
+        let mut wave = gfsk_modulate(&framed, self.freq, &ft4_gfsk_params(self.sr));
+        wave.iter_mut().for_each(|x| *x *= self.amplitude);
+        wave
+    }
+
+    pub fn write_wav<P: AsRef<Path>>(&self, path: P, samples: &[f32]) -> Result<(), hound::Error> {
+        write_wav(path, samples, self.sr as u32)
+    }
+
+    pub fn encode_symbols(message: &[bool; 77]) -> [u8; 103] {
+        let scrambled = scramble(message);
+        let crc = FT8Decoder::bitwise_crc14(&scrambled);
+        let mut payload = [false; 91];
+        payload[..77].copy_from_slice(&scrambled);
+        for i in 0..14 {
+            payload[77 + i] = crc & (1 << (13 - i)) != 0;
+        }
+        let codeword = ldpc_encode(&payload);
+
+        let mut symbols = [0; FT4_CHANNEL_SYMBOLS];
+        for (offset, costas) in zip(SYNC_OFFSETS, FT4_COSTAS) {
+            symbols[offset..offset + 4].copy_from_slice(&costas);
+        }
+        data_symbols()
+            .zip(codeword.chunks_exact(2))
+            .for_each(|(i, x)| {
+                symbols[i] = FT4_SYMBOLS[(x[0] as usize) * 2 + x[1] as usize];
+            });
+        symbols
+    }
+}
@@ -1,18 +1,135 @@
-use std::{collections::VecDeque, f32::consts::PI, iter::zip, sync::Arc};
+use std::{collections::VecDeque, f32::consts::PI, fmt::Display, iter::zip, path::Path, sync::Arc};
 
-use realfft::{FftError, RealFftPlanner, RealToComplex};
+use realfft::{RealFftPlanner, RealToComplex};
 use rustfft::num_complex::Complex;
 
 use crate::{
-    callsignmap::CallsignMap, constants::FT8_SYMBOLS, ldpc::ldpc_decode, message::Message,
-    utils::bool_slice_to_u16,
+    callsignmap::CallsignMap,
+    constants::{FT8_COSTAS, FT8_SYMBOLS},
+    gfsk::{gfsk_modulate, gfsk_reference, GfskParams},
+    ldpc::{ldpc_decode, ldpc_encode, osd_decode},
+    message::{callsign::Callsign, Message, MessageData},
+    resample::Resampler,
+    utils::{
+        bit_field, bool_slice_to_u16, sigmoid, u16_to_bool_slice, u32_to_bool_slice, write_wav,
+    },
+    Error,
 };
 
+// Half-symbol frames spanned by one transmission
+const FT8_FRAMES: usize = 79 * 2;
+// OSD results further than this from the received bits are treated as noise
+pub(crate) const OSD_MAX_HARD_ERRORS: u32 = 30;
+// Probability given to bits fixed by an AP pass
+const AP_CONFIDENCE: f32 = 0.9999;
+
+// A-priori passes, numbered a1 to a6 as in WSJT-X. Each assumes a standard message
+// and fixes the bits it knows before running the LDPC decoder again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ApPass {
+    // CQ ??? ???
+    Cq,
+    // MyCall ??? ???
+    MyCall,
+    // MyCall DxCall ???
+    MyCallDxCall,
+    // MyCall DxCall RRR
+    Rrr,
+    // MyCall DxCall 73
+    SeventyThree,
+    // MyCall DxCall RR73
+    Rr73,
+}
+
+impl ApPass {
+    pub const ALL: [ApPass; 6] = [
+        ApPass::Cq,
+        ApPass::MyCall,
+        ApPass::MyCallDxCall,
+        ApPass::Rrr,
+        ApPass::SeventyThree,
+        ApPass::Rr73,
+    ];
+
+    pub fn number(&self) -> u8 {
+        match self {
+            ApPass::Cq => 1,
+            ApPass::MyCall => 2,
+            ApPass::MyCallDxCall => 3,
+            ApPass::Rrr => 4,
+            ApPass::SeventyThree => 5,
+            ApPass::Rr73 => 6,
+        }
+    }
+
+    // Known message bits, or None when the pass needs a call that is not set
+    fn pattern(
+        &self,
+        my_call: Option<&Callsign>,
+        dx_call: Option<&Callsign>,
+    ) -> Option<[Option<bool>; 77]> {
+        let mut pattern = [None; 77];
+        let mut fix = |start: usize, bits: &[bool]| {
+            zip(&mut pattern[start..], bits).for_each(|(p, &bit)| *p = Some(bit));
+        };
+        // i3 = 1
+        fix(74, &[false, false, true]);
+        if *self == ApPass::Cq {
+            fix(0, &u32_to_bool_slice::<28>(2));
+            fix(28, &[false]);
+            return Some(pattern);
+        }
+        let my_call = my_call?;
+        fix(0, &my_call.to_bits());
+        fix(28, &[my_call.suffix() == Some('R')]);
+        if *self == ApPass::MyCall {
+            return Some(pattern);
+        }
+        let dx_call = dx_call?;
+        fix(29, &dx_call.to_bits());
+        fix(57, &[dx_call.suffix() == Some('R')]);
+        let grid = match self {
+            ApPass::Rrr => 32402,
+            ApPass::SeventyThree => 32404,
+            ApPass::Rr73 => 32403,
+            _ => return Some(pattern),
+        };
+        fix(58, &[false]);
+        fix(59, &u16_to_bool_slice::<15>(grid));
+        Some(pattern)
+    }
+}
+
+impl Display for ApPass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a{}", self.number())
+    }
+}
+
+// QSO context used for AP decoding, passes that need a call which is unset or
+// cannot be encoded are skipped
+#[derive(Debug, Clone, Default)]
+pub struct ApContext {
+    pub my_call: Option<String>,
+    pub dx_call: Option<String>,
+    pub passes: Vec<ApPass>,
+}
+
 pub struct DecodeParams {
     pub sr: f32,
     pub decode_attempts: u32,
     pub from_freq: f32,
     pub to_freq: f32,
+    pub sync_threshold: f32,
+    pub max_candidates: usize,
+    // Bits flipped by the OSD fallback (0-3), None, the default, disables it
+    pub osd_depth: Option<u8>,
+    pub osd_preprocess: bool,
+    // Passes over a cycle in decode_cycle, each after subtracting the signals found so far
+    pub decode_passes: u32,
+    // Resample the input to 12 kHz so other rates decode like native 12 kHz audio
+    pub resample: bool,
 }
 
 impl Default for DecodeParams {
@@ -22,11 +139,18 @@ impl Default for DecodeParams {
             decode_attempts: 10,
             from_freq: 0.0,
             to_freq: 3000.0,
+            sync_threshold: 2.0,
+            max_candidates: 100,
+            osd_depth: None,
+            osd_preprocess: true,
+            decode_passes: 3,
+            resample: false,
         }
     }
 }
 
 pub struct FT8Decoder {
+    sr: f32,
     fft_size: usize,
     plan: Arc<dyn RealToComplex<f32>>,
     input: Vec<f32>,
@@ -35,29 +159,114 @@ pub struct FT8Decoder {
     from_bin: usize,
     to_bin: usize,
     pwr: Vec<VecDeque<f32>>,
-    symbols: Vec<VecDeque<f32>>,
+    sync_scores: VecDeque<Vec<f32>>,
+    sync_threshold: f32,
+    max_candidates: usize,
+    osd_depth: Option<u8>,
+    osd_preprocess: bool,
     callsign_map: CallsignMap,
     decode_attempts: u32,
     pending_samples: VecDeque<f32>,
     total_samples: usize,
+    decode_passes: u32,
+    cycle: Vec<f32>,
+    resampler: Option<Resampler>,
+    ap_patterns: Vec<(ApPass, [Option<bool>; 77])>,
+}
+
+pub(crate) struct SyncCandidate {
+    pub(crate) bin: usize,
+    pub(crate) score: f32,
+}
+
+fn ft8_gfsk_params(sr: f32) -> GfskParams {
+    GfskParams {
+        sample_rate: sr,
+        symbol_rate: 6.25,
+        tone_spacing: 6.25,
+        bt: 2.0,
+        ramp_samples: (sr * 0.16 / 8.0) as usize,
+    }
 }
 
-fn sigmoid(x: f32) -> f32 {
-    1.0 / (1.0 + (-x).exp())
+// Range of reference samples that overlap the audio when the reference begins at `start`
+fn overlap(audio_len: usize, reference_len: usize, start: i64) -> std::ops::Range<usize> {
+    let first = (-start).clamp(0, reference_len as i64) as usize;
+    let last = (audio_len as i64 - start).clamp(first as i64, reference_len as i64) as usize;
+    first..last
+}
+
+// Magnitude of the correlation between the audio and the reference shifted by `df` Hz
+fn correlate_reference(
+    audio: &[f32],
+    reference: &[Complex<f32>],
+    start: i64,
+    df: f32,
+    sr: f32,
+) -> f32 {
+    let rotation = Complex::from_polar(1.0, -2.0 * std::f64::consts::PI * df as f64 / sr as f64);
+    let range = overlap(audio.len(), reference.len(), start);
+    let mut phasor = rotation.powu(range.start as u32);
+    let mut sum = Complex::new(0.0f64, 0.0);
+    for n in range {
+        let x = audio[(start + n as i64) as usize] as f64;
+        let r = reference[n].conj();
+        sum += phasor * Complex::new(x * r.re as f64, x * r.im as f64);
+        phasor *= rotation;
+    }
+    sum.norm() as f32
+}
+
+// Removes the signal following the reference, its amplitude and phase are tracked
+// by low-pass filtering the audio mixed down with the reference
+fn subtract_reference(audio: &mut [f32], reference: &[Complex<f32>], start: i64, df: f32, sr: f32) {
+    let rotation = Complex::from_polar(1.0, 2.0 * std::f64::consts::PI * df as f64 / sr as f64);
+    let range = overlap(audio.len(), reference.len(), start);
+    let mut phasor = rotation.powu(range.start as u32);
+    let shifted = range
+        .clone()
+        .map(|n| {
+            let r = reference[n];
+            let shifted = phasor * Complex::new(r.re as f64, r.im as f64);
+            phasor *= rotation;
+            shifted
+        })
+        .collect::<Vec<_>>();
+    let mut prefix = vec![Complex::new(0.0, 0.0); shifted.len() + 1];
+    for (i, r) in shifted.iter().enumerate() {
+        let x = audio[(start + (range.start + i) as i64) as usize] as f64;
+        prefix[i + 1] = prefix[i] + r.conj() * x;
+    }
+
+    // Two symbols of averaging rejects the mixing product at twice the tone frequency
+    let half_width = (sr / 6.25) as usize;
+    for (i, r) in shifted.iter().enumerate() {
+        let lo = i.saturating_sub(half_width);
+        let hi = (i + half_width + 1).min(shifted.len());
+        let envelope = (prefix[hi] - prefix[lo]) / (hi - lo) as f64;
+        audio[(start + (range.start + i) as i64) as usize] -= (2.0 * (envelope * r).re) as f32;
+    }
 }
 
 impl FT8Decoder {
     pub fn new(params: &DecodeParams) -> FT8Decoder {
-        let sr = params.sr;
+        let resampler = (params.resample && params.sr != 12000.0)
+            .then(|| Resampler::new(params.sr.round() as usize, 12000));
+        let sr = if resampler.is_some() {
+            12000.0
+        } else {
+            params.sr
+        };
         let mut fft_planner = RealFftPlanner::<f32>::new();
         let fft_size = (sr * 2.0 * 1920.0 / 12000.0).round() as usize;
         let plan = fft_planner.plan_fft_forward(fft_size);
         let input = plan.make_input_vec();
         let spectrum = plan.make_output_vec();
         let window = (0..fft_size)
-            .map(|i| 0.5 * (1.0 - (2.0 * PI * (i as f32) / ((fft_size - 1) as f32).cos())))
+            .map(|i| 0.5 * (1.0 - (2.0 * PI * (i as f32) / ((fft_size - 1) as f32)).cos()))
             .collect();
         FT8Decoder {
+            sr,
             fft_size: fft_size,
             plan: plan,
             input: input,
@@ -67,15 +276,144 @@ impl FT8Decoder {
             to_bin: ((params.to_freq * fft_size as f32 / sr).floor() as usize)
                 .min(fft_size / 2 - 2 * 8),
             pwr: vec![VecDeque::new(); fft_size / 2 + 1],
-            symbols: vec![VecDeque::new(); fft_size / 2 + 1],
+            sync_scores: VecDeque::new(),
+            sync_threshold: params.sync_threshold,
+            max_candidates: params.max_candidates,
+            osd_depth: params.osd_depth.map(|depth| depth.min(3)),
+            osd_preprocess: params.osd_preprocess,
             callsign_map: CallsignMap::new(),
             decode_attempts: params.decode_attempts,
             pending_samples: VecDeque::new(),
             total_samples: 0,
+            decode_passes: params.decode_passes.max(1),
+            cycle: Vec::new(),
+            resampler,
+            ap_patterns: Vec::new(),
         }
     }
 
-    pub fn decode(&mut self, data: &[f32]) -> Result<Vec<Message>, FftError> {
+    pub fn set_ap_context(&mut self, context: &ApContext) {
+        let parse = |call: &Option<String>| {
+            call.as_ref()
+                .and_then(|call| call.to_uppercase().parse::<Callsign>().ok())
+        };
+        let my_call = parse(&context.my_call);
+        let dx_call = parse(&context.dx_call);
+        self.ap_patterns = context
+            .passes
+            .iter()
+            .filter_map(|pass| Some((*pass, pass.pattern(my_call.as_ref(), dx_call.as_ref())?)))
+            .collect();
+    }
+
+    // Decodes one whole cycle, subtracting every decoded signal from the cycle
+    // audio and decoding the residual again to uncover weaker signals. The
+    // streaming state of decode is set aside meanwhile, so a cycle can be decoded
+    // between two calls to decode without losing the audio they have buffered
+    pub fn decode_cycle(&mut self, samples: &[f32]) -> Result<Vec<Message>, Error> {
+        self.cycle = match self.resampler.as_ref() {
+            Some(resampler) => {
+                let mut resampler = resampler.clone();
+                resampler.reset();
+                resampler.process(samples)
+            }
+            None => samples.to_vec(),
+        };
+        let stream = (
+            std::mem::replace(&mut self.pwr, vec![VecDeque::new(); self.fft_size / 2 + 1]),
+            std::mem::take(&mut self.sync_scores),
+            std::mem::take(&mut self.pending_samples),
+            self.total_samples,
+        );
+        let messages = self.subtraction_passes();
+        (
+            self.pwr,
+            self.sync_scores,
+            self.pending_samples,
+            self.total_samples,
+        ) = stream;
+        messages
+    }
+
+    fn subtraction_passes(&mut self) -> Result<Vec<Message>, Error> {
+        let mut messages: Vec<Message> = Vec::new();
+        for _ in 0..self.decode_passes {
+            self.reset();
+            let residual = std::mem::take(&mut self.cycle);
+            let decoded = self.decode_native(&residual);
+            self.cycle = residual;
+            let new_messages = decoded?
+                .into_iter()
+                .filter(|message| {
+                    let text = message.data.to_string();
+                    messages.iter().all(|m| m.data.to_string() != text)
+                })
+                .collect::<Vec<_>>();
+            if new_messages.is_empty() {
+                break;
+            }
+            for message in &new_messages {
+                self.subtract(message);
+            }
+            messages.extend(new_messages);
+        }
+        Ok(messages)
+    }
+
+    fn reset(&mut self) {
+        self.pwr.iter_mut().for_each(|pwr| pwr.clear());
+        self.sync_scores.clear();
+        self.pending_samples.clear();
+        self.total_samples = 0;
+    }
+
+    fn subtract(&mut self, message: &Message) {
+        // The decoder only gives 77 bit payloads
+        let symbols = FT8Encoder::encode_symbols(message.bits[..].try_into().unwrap());
+        let reference = gfsk_reference(
+            &symbols,
+            message.freq_bin_hz as f32,
+            &ft8_gfsk_params(self.sr),
+        );
+        let start = (message.time_offset_ms as f64 * self.sr as f64 / 1000.0).round() as i64;
+
+        // The decoder only knows the start to a quarter symbol and the frequency to a bin
+        let quarter_symbol = (self.sr / 6.25 / 4.0) as i64;
+        let step = (quarter_symbol / 24).max(1);
+        let correlate =
+            |start: i64, df: f32| correlate_reference(&self.cycle, &reference, start, df, self.sr);
+        let best_start = |centre: i64, span: i64, step: i64, df: f32| {
+            (-span / step..=span / step)
+                .map(|k| centre + k * step)
+                .map(|start| (start, correlate(start, df)))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap()
+                .0
+        };
+        let start = best_start(start, quarter_symbol, step * 4, 0.0);
+        let df = (-8..=8)
+            .map(|k| k as f32 * 0.25)
+            .map(|df| (df, correlate(start, df)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+            .0;
+        let start = best_start(start, step * 4, step, df);
+
+        subtract_reference(&mut self.cycle, &reference, start, df, self.sr);
+    }
+
+    pub fn decode(&mut self, data: &[f32]) -> Result<Vec<Message>, Error> {
+        match self.resampler.as_mut() {
+            Some(resampler) => {
+                let resampled = resampler.process(data);
+                self.decode_native(&resampled)
+            }
+            None => self.decode_native(data),
+        }
+    }
+
+    // Decodes audio that is already at the rate the FFT was planned for
+    fn decode_native(&mut self, data: &[f32]) -> Result<Vec<Message>, Error> {
         self.pending_samples.extend(data);
         let mut messages = Vec::new();
         while self.pending_samples.len() >= self.fft_size {
@@ -88,7 +426,7 @@ impl FT8Decoder {
         Ok(Message::deduplicate_signals(messages))
     }
 
-    pub fn process_frame(&mut self, frame: &[f32]) -> Result<Vec<Message>, FftError> {
+    pub fn process_frame(&mut self, frame: &[f32]) -> Result<Vec<Message>, Error> {
         debug_assert!(frame.len() == self.fft_size);
 
         zip(frame, &self.window)
@@ -105,117 +443,199 @@ impl FT8Decoder {
             .map(|x| (x.norm_sqr() + 1e-12).log10() * 10.0)
             .collect::<Vec<f32>>();
 
-        for j in 0..fft_pwr.len() {
-            self.pwr[j].push_back(fft_pwr[j]);
-            while self.pwr[j].len() > 65 * 2 + 2 {
-                self.pwr[j].pop_front();
+        for (pwr, &x) in zip(&mut self.pwr, &fft_pwr) {
+            pwr.push_back(x);
+            while pwr.len() > FT8_FRAMES + 1 {
+                pwr.pop_front();
             }
         }
+        if self.pwr[0].len() <= FT8_FRAMES {
+            return Ok(Vec::new());
+        }
 
-        let mut messages: Vec<Message> = Vec::new();
+        // Score the newest window, the window one frame older is decoded once it is a peak in time
+        let scores = (self.from_bin..self.to_bin)
+            .map(|j| self.sync_score(j, 1))
+            .collect::<Vec<f32>>();
+        self.sync_scores.push_back(scores);
+        while self.sync_scores.len() > 3 {
+            self.sync_scores.pop_front();
+        }
+
+        let messages = self
+            .sync_candidates()
+            .iter()
+            .filter_map(|candidate| self.decode_candidate(candidate.bin, 0))
+            .collect();
+        Ok(messages)
+    }
+
+    fn sync_score(&self, j: usize, offset: usize) -> f32 {
+        let score = [0, 36, 72]
+            .iter()
+            .flat_map(|&start| (0..7).map(move |k| (start + k, FT8_COSTAS[k] as usize)))
+            .map(|(symbol, tone)| {
+                let idx = offset + symbol * 2;
+                let expected = self.pwr[j + tone * 2][idx];
+                let total = (0..8).map(|k| self.pwr[j + k * 2][idx]).sum::<f32>();
+                expected - (total - expected) / 7.0
+            })
+            .sum::<f32>();
+        score / 21.0
+    }
+
+    pub(crate) fn sync_candidates(&self) -> Vec<SyncCandidate> {
+        let (older, scores, newer) = match self.sync_scores.len() {
+            3 => (
+                Some(&self.sync_scores[0]),
+                &self.sync_scores[1],
+                &self.sync_scores[2],
+            ),
+            2 => (None, &self.sync_scores[0], &self.sync_scores[1]),
+            _ => return Vec::new(),
+        };
+        let mut candidates = (0..scores.len())
+            .filter(|&i| scores[i] >= self.sync_threshold)
+            .filter(|&i| scores[i] >= newer[i] && older.is_none_or(|older| scores[i] > older[i]))
+            .filter(|&i| i == 0 || scores[i] >= scores[i - 1])
+            .filter(|&i| i + 1 == scores.len() || scores[i] > scores[i + 1])
+            .map(|i| SyncCandidate {
+                bin: self.from_bin + i,
+                score: scores[i],
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+        candidates.truncate(self.max_candidates);
+        candidates
+    }
+
+    fn decode_candidate(&self, j: usize, offset: usize) -> Option<Message> {
         let mut codeword = [0.0; 174];
-        for j in self.from_bin..self.to_bin {
-            let mut s2 = [0.0; 8];
-            for k in 0..8 {
-                s2[k] = fft_pwr[j + (FT8_SYMBOLS[k] as usize) * 2];
-            }
-            let logl_0_1 = s2[4].max(s2[5]).max(s2[6]).max(s2[7]);
-            let logl_0_0 = s2[0].max(s2[1]).max(s2[2]).max(s2[3]);
-            let logl_1_1 = s2[2].max(s2[3]).max(s2[6]).max(s2[7]);
-            let logl_1_0 = s2[0].max(s2[1]).max(s2[4]).max(s2[5]);
-            let logl_2_1 = s2[1].max(s2[3]).max(s2[5]).max(s2[7]);
-            let logl_2_0 = s2[0].max(s2[2]).max(s2[4]).max(s2[6]);
-            let p0 = sigmoid(logl_0_1 - logl_0_0);
-            let p1 = sigmoid(logl_1_1 - logl_1_0);
-            let p2 = sigmoid(logl_2_1 - logl_2_0);
-
-            self.symbols[j].push_back(p0);
-            self.symbols[j].push_back(p1);
-            self.symbols[j].push_back(p2);
-
-            if self.symbols[j].len() > 65 * 3 * 2 {
-                (0..29)
-                    .chain(36..65)
-                    .map(|x| x * 2)
-                    .flat_map(|x| [x * 3, x * 3 + 1, x * 3 + 2])
-                    .enumerate()
-                    .for_each(|(i, x)| {
-                        codeword[i] = self.symbols[j][x];
-                    });
-
-                ldpc_decode(&codeword, self.decode_attempts)
-                    .filter(|decoded| decoded.iter().any(|&x| x))
-                    .filter(|decoded| {
-                        let crc: &[bool; 14] = &decoded[77..91].try_into().unwrap();
-                        let data: &[bool; 77] = &decoded[..77].try_into().unwrap();
-                        let decoded_crc = bool_slice_to_u16(crc) as u16;
-                        Self::bitwise_crc14(data) == decoded_crc
-                    })
-                    .map(|decoded| {
-                        let symbols = decoded
-                            .chunks_exact(3)
-                            .map(|x| x[0] as u8 * 4 + x[1] as u8 * 2 + x[2] as u8);
-
-                        let symbol_pwr_idx = (0..29).chain(36..65).map(|x| x * 2);
-
-                        let signal_noise = zip(symbols, symbol_pwr_idx)
-                            .map(|(symbol, idx)| {
-                                let mut signal = 0.0;
-                                let mut noise = 0.0;
-                                let mut s = [0.0; 8];
-                                for k in 0..8 {
-                                    let bin_pwr = self.pwr[j + (FT8_SYMBOLS[k] as usize) * 2][idx];
-                                    s[k] = bin_pwr;
-                                    if k as u8 == symbol {
-                                        signal += bin_pwr;
-                                    } else {
-                                        noise += bin_pwr;
-                                    }
-                                }
-                                (signal, noise)
-                            })
-                            .fold((0.0, 0.0), |(s, n), (s2, n2)| (s + s2, n + n2));
-
-                        let signal = signal_noise.0 + 1e-12;
-                        let noise = (signal_noise.1 + 1e-12) / 7.0;
-                        let snr = ((signal - noise) / noise).log10() * 10.0;
-
-                        let data: &[bool; 77] = &decoded[..77].try_into().unwrap();
-                        Message::from_bits(
-                            snr,
-                            (j * 625 / 200) as u32,
-                            (self.total_samples as i64 - 71 * (self.fft_size as i64) / 2) * 1000
-                                / 12000,
-                            data,
-                            &self.callsign_map,
-                        )
+        let data_idx = (7..36).chain(43..72).map(|x| offset + x * 2);
+        data_idx
+            .clone()
+            .flat_map(|idx| {
+                let s2 = FT8_SYMBOLS.map(|tone| self.pwr[j + (tone as usize) * 2][idx]);
+                let logl_0_1 = s2[4].max(s2[5]).max(s2[6]).max(s2[7]);
+                let logl_0_0 = s2[0].max(s2[1]).max(s2[2]).max(s2[3]);
+                let logl_1_1 = s2[2].max(s2[3]).max(s2[6]).max(s2[7]);
+                let logl_1_0 = s2[0].max(s2[1]).max(s2[4]).max(s2[5]);
+                let logl_2_1 = s2[1].max(s2[3]).max(s2[5]).max(s2[7]);
+                let logl_2_0 = s2[0].max(s2[2]).max(s2[4]).max(s2[6]);
+                [
+                    sigmoid(logl_0_1 - logl_0_0),
+                    sigmoid(logl_1_1 - logl_1_0),
+                    sigmoid(logl_2_1 - logl_2_0),
+                ]
+            })
+            .enumerate()
+            .for_each(|(i, p)| codeword[i] = p);
+
+        let mut soft = [0.0; 174];
+        // The fallbacks are more prone to false decodes, so they are tracked for extra checks
+        let decoded = ldpc_decode(&codeword, self.decode_attempts, &mut soft)
+            .filter(Self::crc_matches)
+            .map(|decoded| (decoded, false, None))
+            .or_else(|| {
+                self.osd_fallback(&soft, &codeword)
+                    .map(|decoded| (decoded, true, None))
+            })
+            .or_else(|| {
+                self.ap_decode(&codeword)
+                    .map(|(decoded, pass)| (decoded, true, Some(pass)))
+            });
+        decoded
+            .filter(|(decoded, _, _)| decoded.iter().any(|&x| x))
+            .and_then(|(decoded, fallback, ap_pass)| {
+                let symbols = decoded
+                    .chunks_exact(3)
+                    .map(|x| x[0] as u8 * 4 + x[1] as u8 * 2 + x[2] as u8);
+
+                let signal_noise = zip(symbols, data_idx)
+                    .map(|(symbol, idx)| {
+                        let mut signal = 0.0;
+                        let mut noise = 0.0;
+                        for (k, &tone) in FT8_SYMBOLS.iter().enumerate() {
+                            let bin_pwr = self.pwr[j + (tone as usize) * 2][idx];
+                            if k as u8 == symbol {
+                                signal += bin_pwr;
+                            } else {
+                                noise += bin_pwr;
+                            }
+                        }
+                        (signal, noise)
                     })
-                    .into_iter()
-                    .for_each(|message| messages.push(message));
+                    .fold((0.0, 0.0), |(s, n), (s2, n2)| (s + s2, n + n2));
+
+                let signal = signal_noise.0 + 1e-12;
+                let noise = (signal_noise.1 + 1e-12) / 7.0;
+                let snr = ((signal - noise) / noise).log10() * 10.0;
+
+                // The oldest frame in the window is centred half a symbol before the first symbol
+                let start_sample = self.total_samples as i64 - 79 * (self.fft_size as i64) / 2
+                    + (offset * self.fft_size / 4) as i64;
+                let data = decoded.first_chunk::<77>()?;
+                // A codeword can pass the CRC and still hold values no sender would produce
+                let mut message = Message::from_bits(
+                    snr,
+                    (j as f32 * self.sr / self.fft_size as f32).round() as u32,
+                    (start_sample as f64 * 1000.0 / self.sr as f64).round() as i64,
+                    data,
+                    &self.callsign_map,
+                )
+                .ok()?;
+                message.ap_pass = ap_pass;
+                Some((message, fallback))
+            })
+            .filter(|(message, fallback)| {
+                !fallback || !matches!(message.data, MessageData::Unknown(_))
+            })
+            .map(|(message, _)| message)
+    }
+
+    fn osd_fallback(&self, soft: &[f32; 174], codeword: &[f32; 174]) -> Option<[bool; 174]> {
+        let depth = self.osd_depth?;
+        let result = osd_decode(soft, codeword, depth, self.osd_preprocess);
+        (Self::crc_matches(&result.codeword) && result.hard_errors <= OSD_MAX_HARD_ERRORS)
+            .then_some(result.codeword)
+    }
 
-                while self.symbols[j].len() > 65 * 3 * 2 {
-                    self.symbols[j].pop_front();
+    fn ap_decode(&self, codeword: &[f32; 174]) -> Option<([bool; 174], ApPass)> {
+        self.ap_patterns.iter().find_map(|(pass, pattern)| {
+            let mut probabilities = *codeword;
+            zip(&mut probabilities, pattern).for_each(|(p, known)| {
+                if let Some(bit) = known {
+                    *p = if *bit {
+                        AP_CONFIDENCE
+                    } else {
+                        1.0 - AP_CONFIDENCE
+                    };
                 }
-            }
-        }
-        Ok(messages)
+            });
+            ldpc_decode(&probabilities, self.decode_attempts, &mut [0.0; 174])
+                .filter(Self::crc_matches)
+                .filter(|decoded| {
+                    zip(pattern, decoded).all(|(known, bit)| known.is_none_or(|k| k == *bit))
+                })
+                .map(|decoded| (decoded, *pass))
+        })
     }
 
-    pub fn decode_messages(&mut self, data: &[f32]) -> Result<Vec<Message>, FftError> {
-        let fft_size: usize = self.fft_size;
-        let blocks = data.len() / fft_size;
-        let mut messages = Vec::new();
-        for i in 0..blocks {
-            let sample_input: &[f32] = &data[i * fft_size..(i + 1) * fft_size];
-            messages.extend(self.process_frame(sample_input)?);
-        }
-        Ok(messages)
+    pub(crate) fn crc_matches(decoded: &[bool; 174]) -> bool {
+        Self::bitwise_crc14(bit_field(decoded, 0))
+            == bool_slice_to_u16::<14>(bit_field(decoded, 77))
+    }
+
+    /// The same as [`FT8Decoder::decode`], frames overlap and the time offset runs on
+    /// from the audio decoded before.
+    pub fn decode_messages(&mut self, data: &[f32]) -> Result<Vec<Message>, Error> {
+        self.decode(data)
     }
 
     pub fn insert_callsign(&mut self, callsign: &String) -> u32 {
         self.callsign_map.insert(callsign)
     }
-    fn bitwise_crc14(bits: &[bool; 77]) -> u16 {
+    pub(crate) fn bitwise_crc14(bits: &[bool; 77]) -> u16 {
         let mut crc = 0;
         let poly = 0x6757;
         for k in 0..77 {
@@ -233,3 +653,71 @@ impl FT8Decoder {
         crc
     }
 }
+
+pub struct EncodeParams {
+    pub sr: f32,
+    pub freq: f32,
+    pub amplitude: f32,
+}
+
+impl Default for EncodeParams {
+    fn default() -> Self {
+        EncodeParams {
+            sr: 12000.0,
+            freq: 1500.0,
+            amplitude: 1.0,
+        }
+    }
+}
+
+pub struct FT8Encoder {
+    sr: f32,
+    freq: f32,
+    amplitude: f32,
+}
+
+impl FT8Encoder {
+    pub fn new(params: &EncodeParams) -> FT8Encoder {
+        FT8Encoder {
+            sr: params.sr,
+            freq: params.freq,
+            amplitude: params.amplitude,
+        }
+    }
+
+    pub fn encode(&self, message: &[bool; 77]) -> Vec<f32> {
+        self.synthesize(&Self::encode_symbols(message))
+    }
+
+    pub fn synthesize(&self, symbols: &[u8; 79]) -> Vec<f32> {
+        let mut wave = gfsk_modulate(symbols, self.freq, &ft8_gfsk_params(self.sr));
+        wave.iter_mut().for_each(|x| *x *= self.amplitude);
+        wave
+    }
+
+    pub fn write_wav<P: AsRef<Path>>(&self, path: P, samples: &[f32]) -> Result<(), hound::Error> {
+        write_wav(path, samples, self.sr as u32)
+    }
+
+    pub fn encode_symbols(message: &[bool; 77]) -> [u8; 79] {
+        let crc = FT8Decoder::bitwise_crc14(message);
+        let mut payload = [false; 91];
+        payload[..77].copy_from_slice(message);
+        for i in 0..14 {
+            payload[77 + i] = crc & (1 << (13 - i)) != 0;
+        }
+        let codeword = ldpc_encode(&payload);
+
+        let mut symbols = [0; 79];
+        for offset in [0, 36, 72] {
+            symbols[offset..offset + 7].copy_from_slice(&FT8_COSTAS);
+        }
+        (7..36)
+            .chain(43..72)
+            .zip(codeword.chunks_exact(3))
+            .for_each(|(i, x)| {
+                symbols[i] = FT8_SYMBOLS[(x[0] as usize) * 4 + (x[1] as usize) * 2 + x[2] as usize];
+            });
+        symbols
+    }
+}
@@ -0,0 +1,71 @@
+use std::{f64::consts::PI, ops::MulAssign};
+
+use rustfft::num_complex::Complex;
+
+use crate::utils::erf;
+
+pub(crate) struct GfskParams {
+    pub sample_rate: f32,
+    pub symbol_rate: f32,
+    pub tone_spacing: f32,
+    pub bt: f32,
+    pub ramp_samples: usize,
+}
+
+// Frequency pulse of a single symbol, t is in symbol periods relative to the symbol centre
+pub(crate) fn gfsk_pulse(bt: f32, t: f32) -> f32 {
+    let c = std::f32::consts::PI * (2.0 / std::f32::consts::LN_2).sqrt();
+    0.5 * (erf(c * bt * (t + 0.5)) - erf(c * bt * (t - 0.5)))
+}
+
+fn gfsk_phase(symbols: &[u8], freq: f32, params: &GfskParams) -> Vec<f64> {
+    let samples_per_symbol = (params.sample_rate / params.symbol_rate) as f64;
+    let num_samples = (symbols.len() as f64 * samples_per_symbol).round() as usize;
+    let last = symbols.len() as isize - 1;
+    let mut phase = 0.0f64;
+    let mut phases = Vec::with_capacity(num_samples);
+    for n in 0..num_samples {
+        let t = n as f64 / samples_per_symbol;
+        // Each pulse spans three symbols, the first and last tones are extended past the ends
+        let centre = t.floor() as isize;
+        let deviation = (centre - 1..=centre + 1)
+            .map(|j| {
+                let tone = symbols[j.clamp(0, last) as usize] as f32;
+                tone * gfsk_pulse(params.bt, (t - j as f64 - 0.5) as f32)
+            })
+            .sum::<f32>();
+        let inst_freq = freq as f64 + (params.tone_spacing * deviation) as f64;
+        phases.push(phase);
+        phase = (phase + 2.0 * PI * inst_freq / params.sample_rate as f64) % (2.0 * PI);
+    }
+    phases
+}
+
+fn apply_ramp<T: MulAssign<f32>>(wave: &mut [T], ramp_samples: usize) {
+    let num_samples = wave.len();
+    let ramp = ramp_samples.min(num_samples / 2);
+    for i in 0..ramp {
+        let gain = (1.0 - (PI * i as f64 / ramp as f64).cos()) as f32 / 2.0;
+        wave[i] *= gain;
+        wave[num_samples - 1 - i] *= gain;
+    }
+}
+
+pub(crate) fn gfsk_modulate(symbols: &[u8], freq: f32, params: &GfskParams) -> Vec<f32> {
+    let mut wave = gfsk_phase(symbols, freq, params)
+        .iter()
+        .map(|phase| phase.sin() as f32)
+        .collect::<Vec<_>>();
+    apply_ramp(&mut wave, params.ramp_samples);
+    wave
+}
+
+// Unit amplitude analytic signal with the same phase and ramps as gfsk_modulate
+pub(crate) fn gfsk_reference(symbols: &[u8], freq: f32, params: &GfskParams) -> Vec<Complex<f32>> {
+    let mut wave = gfsk_phase(symbols, freq, params)
+        .iter()
+        .map(|&phase| Complex::new(phase.sin() as f32, -phase.cos() as f32))
+        .collect::<Vec<_>>();
+    apply_ramp(&mut wave, params.ramp_samples);
+    wave
+}
@@ -0,0 +1,483 @@
+// The JT65 72 bit message: two 28 bit callsign fields and a 16 bit locator field,
+// sent most significant bit first as twelve 6 bit symbols. Callsigns and
+// locators pack the same way as in WSPR, free text fills all three fields
+
+use std::{fmt::Display, iter::zip, ops::Range};
+
+use crate::{
+    message::{CallsignError, Grid4Value, GridError, MessageData, Standard},
+    utils::char_lookup,
+    wspr::message::{
+        pack_callsign, pack_grid, unpack_callsign, unpack_grid, CHARS37, MAX_CALLSIGN,
+    },
+    Error,
+};
+
+const NBASE: u32 = MAX_CALLSIGN;
+const CQ: u32 = NBASE + 1;
+const QRZ: u32 = NBASE + 2;
+// "CQ 000" to "CQ 999", the frequency a station listens on
+const CQ_FREQ: u32 = NBASE + 3;
+// CQ, QRZ and DE before a callsign with a prefix of up to 4 characters, then the
+// same with a suffix of up to 3, each word a block of its own
+const PREFIX_BASE: u32 = NBASE + 1003;
+const PREFIX_BLOCK: u32 = 36 * 37 * 37 * 37 + 1;
+const SUFFIX_BASE: u32 = PREFIX_BASE + 3 * PREFIX_BLOCK;
+const SUFFIX_BLOCK: u32 = 36 * 37 * 37 + 1;
+const DE: u32 = SUFFIX_BASE + 3 * SUFFIX_BLOCK;
+const ADD_ON_WORDS: [&str; 3] = ["CQ", "QRZ", "DE"];
+const NGBASE: u32 = 180 * 180;
+const BLANK: u32 = NGBASE + 1;
+const REPORT: u32 = NGBASE + 1;
+const R_REPORT: u32 = NGBASE + 31;
+const RO: u32 = NGBASE + 62;
+const RRR: u32 = NGBASE + 63;
+const SEVENTY_THREE: u32 = NGBASE + 64;
+// Set in the locator field of free text
+const TEXT_FLAG: u32 = 1 << 15;
+const TEXT_CHARS: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ +-./?";
+// A prefix or suffix from WSJT's list is sent as its number in the locator field,
+// in the squares from 85 degrees north. Prefixes are numbered from 1 and suffixes
+// from 401, plus 450 when they go on the second callsign
+const LISTED_PREFIXES: u32 = 339;
+const LISTED_SUFFIX_BASE: u32 = 400;
+const LISTED_SUFFIXES: u32 = 12;
+
+/// A JT65 72 bit message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Jt65Message {
+    /// Two callsigns, or CQ, QRZ or DE and a callsign, with a locator, a report such
+    /// as `-15` or `R-15`, `RO`, `RRR`, `73` or nothing in `grid`. After CQ, QRZ or DE
+    /// the callsign may carry any prefix of up to 4 characters or suffix of up to 3.
+    /// Either callsign may instead carry one from WSJT's list, leaving `grid` empty,
+    /// written with its number in the list as in `<12>/K1ABC` or `K1ABC/<3>`.
+    Standard {
+        callsign1: String,
+        callsign2: String,
+        grid: String,
+    },
+    /// Up to 13 characters of free text.
+    FreeText(String),
+}
+
+impl Jt65Message {
+    pub fn from_bits(message: &[bool; 72]) -> Result<Self, Error> {
+        let value = |range: Range<usize>| {
+            message[range]
+                .iter()
+                .fold(0u32, |acc, &bit| (acc << 1) | bit as u32)
+        };
+        let (nc1, nc2, ng) = (value(0..28), value(28..56), value(56..72));
+        if ng & TEXT_FLAG != 0 {
+            return Ok(Self::FreeText(unpack_free_text(nc1, nc2, ng)));
+        }
+        let callsign2 = unpack_callsign(nc2, true)?;
+        let invalid = |field| Error::InvalidField {
+            field,
+            value: nc1 as u64,
+        };
+        let (mut callsign1, mut callsign2) = match nc1 {
+            PREFIX_BASE..SUFFIX_BASE => {
+                let n = nc1 - PREFIX_BASE;
+                let prefix = unpack_add_on(n % PREFIX_BLOCK, 4).ok_or_else(|| invalid("prefix"))?;
+                (
+                    ADD_ON_WORDS[(n / PREFIX_BLOCK) as usize].to_string(),
+                    format!("{}/{}", prefix, callsign2),
+                )
+            }
+            SUFFIX_BASE..DE => {
+                let n = nc1 - SUFFIX_BASE;
+                let suffix = unpack_add_on(n % SUFFIX_BLOCK, 3).ok_or_else(|| invalid("suffix"))?;
+                (
+                    ADD_ON_WORDS[(n / SUFFIX_BLOCK) as usize].to_string(),
+                    format!("{}/{}", callsign2, suffix),
+                )
+            }
+            _ => (unpack_first(nc1)?, callsign2),
+        };
+        let grid = match listed_add_on(ng) {
+            Some((0, n)) => {
+                callsign1 = with_listed(&callsign1, n);
+                String::new()
+            }
+            Some((_, n)) => {
+                callsign2 = with_listed(&callsign2, n);
+                String::new()
+            }
+            None => unpack_locator(ng)?,
+        };
+        Ok(Self::Standard {
+            callsign1,
+            callsign2,
+            grid,
+        })
+    }
+
+    /// Parses the text form, as in `CQ 050 K1ABC FN42`, `CQ PJ4/K1ABC FN42`,
+    /// `W9XYZ K1ABC RO` or free text.
+    pub fn from_text(text: &str) -> Option<Self> {
+        let text = text.trim().to_uppercase();
+        let mut words = text.split_whitespace().collect::<Vec<_>>();
+        // "CQ 050" is packed together as the first callsign
+        let cq = match words[..] {
+            ["CQ", freq, _, ..] if freq.len() == 3 && freq.bytes().all(|c| c.is_ascii_digit()) => {
+                Some(format!("CQ {}", words.remove(1)))
+            }
+            _ => None,
+        };
+        if let Some(cq) = &cq {
+            words[0] = cq;
+        }
+        let standard = match words[..] {
+            [callsign1, callsign2] => Some((callsign1, callsign2, "")),
+            [callsign1, callsign2, grid] => Some((callsign1, callsign2, grid)),
+            _ => None,
+        }
+        .map(|(callsign1, callsign2, grid)| Self::Standard {
+            callsign1: callsign1.to_string(),
+            callsign2: callsign2.to_string(),
+            grid: grid.to_string(),
+        });
+        standard
+            .into_iter()
+            .chain([Self::FreeText(text.clone())])
+            .find(|message| message.to_bits().is_ok())
+    }
+
+    // Standard messages are converted field by field, anything else that fits is
+    // sent as free text
+    pub(crate) fn from_data(data: &MessageData) -> Option<Self> {
+        let standard = match data {
+            MessageData::Jt65(message) => return Some(message.clone()),
+            MessageData::StandardR(message) => Self::from_standard(message),
+            MessageData::StandardP(message) => Self::from_standard(message),
+            _ => None,
+        };
+        standard
+            .into_iter()
+            .chain([Self::FreeText(data.to_string().trim().to_string())])
+            .find(|message| message.to_bits().is_ok())
+    }
+
+    // JT65 has no R before a locator, RRR or 73, and no RR73
+    fn from_standard<const SUFFIX: char>(message: &Standard<SUFFIX>) -> Option<Self> {
+        let grid = match (message.grid.value(), message.grid.has_r) {
+            (Grid4Value::Blank, false) => String::new(),
+            (Grid4Value::Locator(locator), false) => locator,
+            (Grid4Value::Report(n), false) => format!("{:03}", n),
+            (Grid4Value::Report(n), true) => format!("R{:03}", n),
+            (Grid4Value::Roger, false) => "RRR".to_string(),
+            (Grid4Value::SeventyThree, false) => "73".to_string(),
+            _ => return None,
+        };
+        Some(Self::Standard {
+            callsign1: message.callsign1.to_string(),
+            callsign2: message.callsign2.to_string(),
+            grid,
+        })
+    }
+
+    pub fn to_bits(&self) -> Result<[bool; 72], Error> {
+        let (nc1, nc2, ng) = match self {
+            Self::Standard {
+                callsign1,
+                callsign2,
+                grid,
+            } => pack_standard(callsign1, callsign2, grid)?,
+            Self::FreeText(text) => pack_free_text(text)?,
+        };
+        let packed = (nc1 as u128) << 44 | (nc2 as u128) << 16 | ng as u128;
+        let mut bits = [false; 72];
+        bits.iter_mut()
+            .enumerate()
+            .for_each(|(i, bit)| *bit = packed >> (71 - i) & 1 == 1);
+        Ok(bits)
+    }
+
+    /// The callsigns as sent, CQ, QRZ and DE included.
+    pub fn callsigns(&self) -> Vec<String> {
+        match self {
+            Self::Standard {
+                callsign1,
+                callsign2,
+                ..
+            } => vec![callsign1.clone(), callsign2.clone()],
+            Self::FreeText(_) => vec![],
+        }
+    }
+
+    /// The locator, when `grid` holds one.
+    pub fn grid(&self) -> Option<&str> {
+        match self {
+            Self::Standard { grid, .. } if pack_grid(grid).is_ok() => Some(grid),
+            _ => None,
+        }
+    }
+
+    /// The report in dB, when `grid` holds one.
+    pub fn report(&self) -> Option<i16> {
+        match self {
+            Self::Standard { grid, .. } => {
+                let report = grid.strip_prefix('R').unwrap_or(grid);
+                report.starts_with('-').then(|| report.parse().ok())?
+            }
+            Self::FreeText(_) => None,
+        }
+    }
+}
+
+impl Display for Jt65Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Standard {
+                callsign1,
+                callsign2,
+                grid,
+            } => {
+                write!(f, "{} {}", callsign1, callsign2)?;
+                if !grid.is_empty() {
+                    write!(f, " {}", grid)?;
+                }
+                Ok(())
+            }
+            Self::FreeText(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+// Six bits to a symbol, most significant first
+pub(crate) fn to_symbols(bits: &[bool; 72]) -> [u8; 12] {
+    let mut symbols = [0; 12];
+    for (symbol, chunk) in zip(&mut symbols, bits.chunks(6)) {
+        *symbol = chunk.iter().fold(0, |acc, &bit| (acc << 1) | bit as u8);
+    }
+    symbols
+}
+
+pub(crate) fn from_symbols(symbols: &[u8; 12]) -> [bool; 72] {
+    let mut bits = [false; 72];
+    for (chunk, &symbol) in zip(bits.chunks_mut(6), symbols) {
+        for (i, bit) in chunk.iter_mut().enumerate() {
+            *bit = symbol >> (5 - i) & 1 == 1;
+        }
+    }
+    bits
+}
+
+fn pack_first(word: &str) -> Result<u32, Error> {
+    match word.split_once(' ') {
+        Some(("CQ", freq)) if freq.len() == 3 && freq.bytes().all(|c| c.is_ascii_digit()) => {
+            Ok(CQ_FREQ + freq.parse::<u32>().unwrap())
+        }
+        _ => match word {
+            "CQ" => Ok(CQ),
+            "QRZ" => Ok(QRZ),
+            "DE" => Ok(DE),
+            _ => pack_callsign(word),
+        },
+    }
+}
+
+fn unpack_first(n: u32) -> Result<String, Error> {
+    match n {
+        CQ => Ok("CQ".to_string()),
+        QRZ => Ok("QRZ".to_string()),
+        DE => Ok("DE".to_string()),
+        n if (CQ_FREQ..CQ_FREQ + 1000).contains(&n) => Ok(format!("CQ {:03}", n - CQ_FREQ)),
+        n => unpack_callsign(n, true),
+    }
+}
+
+// CQ, QRZ or DE with a callsign carrying a prefix or suffix, a callsign on the right
+// of the slash makes the left a prefix as in PJ4/K1ABC
+fn pack_add_on(word: &str, callsign: &str) -> Option<(u32, u32)> {
+    let word = ADD_ON_WORDS.iter().position(|&w| w == word)? as u32;
+    let (left, right) = callsign.split_once('/')?;
+    let chars = |text: &str, len: usize| {
+        if text.is_empty() || text.len() > len || text.contains(' ') {
+            return None;
+        }
+        format!("{:<len$}", text)
+            .chars()
+            .try_fold(0, |acc, c| Some(acc * 37 + CHARS37.find(c)? as u32))
+    };
+    if let (Some(prefix), Ok(nc2)) = (chars(left, 4), pack_callsign(right)) {
+        return Some((PREFIX_BASE + word * PREFIX_BLOCK + prefix, nc2));
+    }
+    let suffix = chars(right, 3)?;
+    Some((
+        SUFFIX_BASE + word * SUFFIX_BLOCK + suffix,
+        pack_callsign(left).ok()?,
+    ))
+}
+
+fn unpack_add_on(n: u32, len: u32) -> Option<String> {
+    let text = (0..len)
+        .rev()
+        .map(|i| char_lookup((n / 37u32.pow(i) % 37) as usize, CHARS37))
+        .collect::<String>();
+    let text = text.trim_end();
+    (!text.is_empty() && !text.contains(' ')).then(|| text.to_string())
+}
+
+// The callsign, 0 or 1, and the number of a listed prefix or suffix in the locator field
+fn listed_add_on(ng: u32) -> Option<(u32, u32)> {
+    if ng >= NGBASE || ng % 180 < 175 {
+        return None;
+    }
+    let k = 5 * (ng / 180) + ng % 180 - 175;
+    let n = k % 450 + 1;
+    let suffixes = LISTED_SUFFIX_BASE + 1..=LISTED_SUFFIX_BASE + LISTED_SUFFIXES;
+    (n <= LISTED_PREFIXES || suffixes.contains(&n)).then_some((k / 450, n))
+}
+
+fn listed_square(callsign: u32, n: u32) -> u32 {
+    let k = n - 1 + 450 * callsign;
+    k / 5 * 180 + 175 + k % 5
+}
+
+fn with_listed(callsign: &str, n: u32) -> String {
+    if n <= LISTED_PREFIXES {
+        format!("<{}>/{}", n, callsign)
+    } else {
+        format!("{}/<{}>", callsign, n - LISTED_SUFFIX_BASE)
+    }
+}
+
+// The number of the listed prefix or suffix on `callsign` and the callsign without it
+fn split_listed(callsign: &str) -> Option<(u32, &str)> {
+    let number = |text: &str| {
+        text.strip_prefix('<')?
+            .strip_suffix('>')?
+            .parse::<u32>()
+            .ok()
+    };
+    let (left, right) = callsign.split_once('/')?;
+    match (number(left), number(right)) {
+        (Some(n), None) if (1..=LISTED_PREFIXES).contains(&n) => Some((n, right)),
+        (None, Some(n)) if (1..=LISTED_SUFFIXES).contains(&n) => {
+            Some((LISTED_SUFFIX_BASE + n, left))
+        }
+        _ => None,
+    }
+}
+
+fn pack_standard(callsign1: &str, callsign2: &str, grid: &str) -> Result<(u32, u32, u32), Error> {
+    let listed = [callsign1, callsign2].map(split_listed);
+    if listed.iter().any(Option::is_some) {
+        // A listed prefix or suffix takes the place of the locator
+        if !grid.is_empty() {
+            return Err(GridError::InvalidLocator(grid.to_string()).into());
+        }
+        return match listed {
+            [Some((n, callsign1)), None] => Ok((
+                pack_callsign(callsign1)?,
+                pack_callsign(callsign2)?,
+                listed_square(0, n),
+            )),
+            [None, Some((n, callsign2))] => Ok((
+                pack_first(callsign1)?,
+                pack_callsign(callsign2)?,
+                listed_square(1, n),
+            )),
+            _ => Err(CallsignError::NotStandard(callsign1.to_string()).into()),
+        };
+    }
+    let ng = pack_locator(grid)?;
+    if callsign2.contains('/') {
+        let (nc1, nc2) = pack_add_on(callsign1, callsign2)
+            .ok_or_else(|| CallsignError::NotStandard(callsign2.to_string()))?;
+        return Ok((nc1, nc2, ng));
+    }
+    Ok((pack_first(callsign1)?, pack_callsign(callsign2)?, ng))
+}
+
+// Reports run from -01 to -30 dB, there is no R before a locator, RRR or 73
+fn pack_locator(grid: &str) -> Result<u32, Error> {
+    let report = |text: &str| {
+        let digits = text.strip_prefix('-')?;
+        let n = digits.parse::<u32>().ok()?;
+        (digits.len() == 2 && digits.bytes().all(|c| c.is_ascii_digit()) && (1..=30).contains(&n))
+            .then_some(n)
+    };
+    match grid {
+        "" => Ok(BLANK),
+        "RO" => Ok(RO),
+        "RRR" => Ok(RRR),
+        "73" => Ok(SEVENTY_THREE),
+        _ => {
+            if let Some(n) = report(grid) {
+                return Ok(REPORT + n);
+            }
+            if let Some(n) = grid.strip_prefix('R').and_then(report) {
+                return Ok(R_REPORT + n);
+            }
+            let ng = pack_grid(grid)?;
+            // These squares carry listed prefixes and suffixes
+            if listed_add_on(ng).is_some() {
+                return Err(GridError::InvalidLocator(grid.to_string()).into());
+            }
+            Ok(ng)
+        }
+    }
+}
+
+fn unpack_locator(ng: u32) -> Result<String, Error> {
+    match ng {
+        BLANK => Ok(String::new()),
+        ng if (REPORT + 1..=REPORT + 30).contains(&ng) => Ok(format!("-{:02}", ng - REPORT)),
+        ng if (R_REPORT + 1..=R_REPORT + 30).contains(&ng) => Ok(format!("R-{:02}", ng - R_REPORT)),
+        RO => Ok("RO".to_string()),
+        RRR => Ok("RRR".to_string()),
+        SEVENTY_THREE => Ok("73".to_string()),
+        ng => unpack_grid(ng),
+    }
+}
+
+fn pack_free_text(text: &str) -> Result<(u32, u32, u32), Error> {
+    if text.len() > 13 {
+        return Err(Error::InvalidField {
+            field: "free text length",
+            value: text.len() as u64,
+        });
+    }
+    let padded = format!("{:<13}", text);
+    let mut values = padded.chars().map(|c| {
+        TEXT_CHARS
+            .find(c)
+            .map(|i| i as u32)
+            .ok_or(Error::InvalidField {
+                field: "free text character",
+                value: c as u64,
+            })
+    });
+    let mut take = |n: usize| {
+        (0..n).try_fold(0, |acc, _| {
+            Ok::<_, Error>(acc * 42 + values.next().unwrap()?)
+        })
+    };
+    let (nc1, nc2, nc3) = (take(5)?, take(5)?, take(3)?);
+    // The top two bits of the last three characters ride on the callsign fields
+    Ok((
+        2 * nc1 + (nc3 >> 15 & 1),
+        2 * nc2 + (nc3 >> 16 & 1),
+        TEXT_FLAG | (nc3 & 0x7fff),
+    ))
+}
+
+fn unpack_free_text(nc1: u32, nc2: u32, ng: u32) -> String {
+    let nc3 = (ng & 0x7fff) | (nc1 & 1) << 15 | (nc2 & 1) << 16;
+    let chars = |mut n: u32, len: usize| {
+        let mut chars = vec![' '; len];
+        for c in chars.iter_mut().rev() {
+            *c = TEXT_CHARS.as_bytes()[(n % 42) as usize] as char;
+            n /= 42;
+        }
+        chars
+    };
+    let mut text = chars(nc1 >> 1, 5);
+    text.extend(chars(nc2 >> 1, 5));
+    text.extend(chars(nc3, 3));
+    text.iter().collect::<String>().trim().to_string()
+}
@@ -0,0 +1,446 @@
+//! JT65A, JT65B and JT65C encoding and decoding.
+//!
+//! A JT65 transmission is 126 symbols of 65-FSK, each 4096 samples at 11025 Hz. The
+//! lowest tone is sent wherever a pseudo-random sync vector is set, the other 63
+//! symbols carry a Reed-Solomon (63,12) codeword over GF(64), interleaved and Gray
+//! coded, two tones or more above it. The 72 bit message, a [`Jt65Message`], holds
+//! two callsigns and a locator, a report or free text. Decodes are given as the same
+//! [`Message`] the FT8 decoder gives, carrying [`MessageData::Jt65`] and the 72 bits
+//! received.
+
+use std::{iter::zip, path::Path};
+
+use crate::{
+    constants::JT65_SYNC,
+    message::{Message, MessageData},
+    utils::{power_spectrogram, write_wav},
+    Error,
+};
+
+use self::{
+    message::{from_symbols, to_symbols},
+    rs::{rs_decode, rs_encode, NROOTS},
+};
+
+pub use self::message::Jt65Message;
+
+pub(crate) mod message;
+pub(crate) mod rs;
+
+pub const CYCLE_SECONDS: f32 = 60.0;
+const JT65_SYMBOLS: usize = 126;
+const DATA_SYMBOLS: usize = 63;
+// Transmissions start this long into the cycle
+const NOMINAL_START: f32 = 1.0;
+// Erasing more symbols than this leaves too few errors to correct to trust a decode
+const MAX_ERASURES: usize = 36;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Jt65Submode {
+    /// Tones 2.7 Hz apart.
+    A,
+    /// Tones 5.4 Hz apart.
+    B,
+    /// Tones 10.8 Hz apart.
+    C,
+}
+
+impl Jt65Submode {
+    // Tone spacing in multiples of the symbol rate
+    fn spacing(&self) -> usize {
+        match self {
+            Jt65Submode::A => 1,
+            Jt65Submode::B => 2,
+            Jt65Submode::C => 4,
+        }
+    }
+
+    /// Tone spacing in Hz.
+    pub fn tone_spacing(&self) -> f32 {
+        11025.0 / 4096.0 * self.spacing() as f32
+    }
+}
+
+pub struct Jt65DecodeParams {
+    pub sr: f32,
+    pub submode: Jt65Submode,
+    // Range searched for the sync tone
+    pub from_freq: f32,
+    pub to_freq: f32,
+    pub sync_threshold: f32,
+    pub max_candidates: usize,
+    // Sets of erasures tried on a candidate the hard decisions do not decode
+    pub erasure_trials: usize,
+}
+
+impl Default for Jt65DecodeParams {
+    fn default() -> Self {
+        Jt65DecodeParams {
+            sr: 12000.0,
+            submode: Jt65Submode::A,
+            from_freq: 200.0,
+            to_freq: 2500.0,
+            sync_threshold: 0.2,
+            max_candidates: 20,
+            erasure_trials: 1000,
+        }
+    }
+}
+
+// Codeword position of each channel data symbol, the 7 by 9 interleaver transposed
+fn interleave_order() -> [usize; DATA_SYMBOLS] {
+    let mut order = [0; DATA_SYMBOLS];
+    for i in 0..7 {
+        for j in 0..9 {
+            order[j + 9 * i] = i + 7 * j;
+        }
+    }
+    order
+}
+
+fn gray(n: u8) -> u8 {
+    n ^ (n >> 1)
+}
+
+fn channel_symbols(message: &[u8; 12]) -> [u8; JT65_SYMBOLS] {
+    let codeword = rs_encode(message);
+    let mut data = interleave_order()
+        .map(|p| gray(codeword[p]) + 2)
+        .into_iter();
+    JT65_SYNC.map(|sync| if sync == 1 { 0 } else { data.next().unwrap() })
+}
+
+/// Decodes whole JT65 cycles.
+pub struct Jt65Decoder {
+    sr: f32,
+    spacing: usize,
+    symbol_samples: f32,
+    hop: usize,
+    fft_size: usize,
+    bin_hz: f32,
+    // Bins kept from the spectrum, the sync tone is searched for in the first `search_bins`
+    first_bin: usize,
+    last_bin: usize,
+    search_bins: usize,
+    sync_threshold: f32,
+    max_candidates: usize,
+    erasure_trials: usize,
+}
+
+impl Jt65Decoder {
+    pub fn new(params: &Jt65DecodeParams) -> Jt65Decoder {
+        let symbol_samples = params.sr * 4096.0 / 11025.0;
+        // Two symbols per FFT gives bins at half the JT65A tone spacing
+        let fft_size = 2 * symbol_samples.round() as usize;
+        let bin_hz = params.sr / fft_size as f32;
+        let spacing = params.submode.spacing();
+        // Room above the sync tone for the highest data tone
+        let span = 2 * spacing * (DATA_SYMBOLS + 2);
+        let first_bin = (params.from_freq / bin_hz).ceil() as usize;
+        let last_bin =
+            ((params.to_freq / bin_hz).floor() as usize + span + 1).min(fft_size / 2 + 1);
+        let to_bin =
+            ((params.to_freq / bin_hz).floor() as usize + 1).min(last_bin.saturating_sub(span));
+        Jt65Decoder {
+            sr: params.sr,
+            spacing,
+            symbol_samples,
+            hop: (symbol_samples / 2.0).round() as usize,
+            fft_size,
+            bin_hz,
+            first_bin,
+            last_bin,
+            search_bins: to_bin.saturating_sub(first_bin),
+            sync_threshold: params.sync_threshold,
+            max_candidates: params.max_candidates,
+            erasure_trials: params.erasure_trials,
+        }
+    }
+
+    /// Decodes one cycle of audio, best started on the cycle boundary. Messages
+    /// report the frequency of the sync tone.
+    pub fn decode(&mut self, samples: &[f32]) -> Result<Vec<Message>, Error> {
+        // Frames half a symbol apart
+        let pwr = power_spectrogram(
+            samples,
+            self.fft_size,
+            self.hop,
+            self.first_bin..self.last_bin,
+        )?;
+        let noise = Self::noise_power(&pwr);
+        let messages = self
+            .candidates(&pwr)
+            .into_iter()
+            .filter_map(|(frame, bin)| self.decode_candidate(&pwr, frame, bin, noise))
+            .collect::<Vec<_>>();
+        let mut messages = Message::deduplicate_signals(messages);
+        messages.sort_by_key(|m| m.freq_bin_hz);
+        Ok(messages)
+    }
+
+    // Median bin power, most bins only hold noise
+    fn noise_power(pwr: &[Vec<f32>]) -> f32 {
+        let mut powers = pwr.iter().flatten().copied().collect::<Vec<_>>();
+        if powers.is_empty() {
+            return 1.0;
+        }
+        let mid = powers.len() / 2;
+        *powers.select_nth_unstable_by(mid, f32::total_cmp).1
+    }
+
+    // Frame centred on `symbol` of a transmission whose first symbol is centred on `frame`
+    fn symbol_frame(&self, frame: usize, symbol: usize) -> usize {
+        frame + (symbol as f32 * self.symbol_samples / self.hop as f32).round() as usize
+    }
+
+    // Power of `tone` in `symbol`, tone 0 is the sync tone at `bin`
+    fn tone_power(
+        &self,
+        pwr: &[Vec<f32>],
+        frame: usize,
+        bin: usize,
+        symbol: usize,
+        tone: usize,
+    ) -> f32 {
+        pwr[self.symbol_frame(frame, symbol)][bin + 2 * self.spacing * tone]
+    }
+
+    // Sync tone power summed over the sync symbols and over the data symbols
+    fn sync_power(&self, pwr: &[Vec<f32>], frame: usize, bin: usize) -> (f32, f32) {
+        zip(0..JT65_SYMBOLS, JT65_SYNC)
+            .map(|(i, sync)| (sync, self.tone_power(pwr, frame, bin, i, 0)))
+            .fold(
+                (0.0, 0.0),
+                |(s, d), (sync, p)| {
+                    if sync == 1 {
+                        (s + p, d)
+                    } else {
+                        (s, d + p)
+                    }
+                },
+            )
+    }
+
+    // The start of every bin where the sync tone best follows the sync vector,
+    // scored from -1 to 1. Bins passing the threshold are kept where the sync
+    // tone's excess power beats the neighbouring bins, strongest first
+    fn candidates(&self, pwr: &[Vec<f32>]) -> Vec<(usize, usize)> {
+        let last = self.symbol_frame(0, JT65_SYMBOLS - 1);
+        if pwr.len() <= last || self.search_bins == 0 {
+            return Vec::new();
+        }
+        let best = (0..self.search_bins)
+            .map(|bin| {
+                (0..pwr.len() - last)
+                    .map(|frame| {
+                        let (sync, data) = self.sync_power(pwr, frame, bin);
+                        (frame, (sync - data) / (sync + data + 1e-12), sync - data)
+                    })
+                    .fold(
+                        (0, f32::NEG_INFINITY, 0.0),
+                        |a, b| if b.1 > a.1 { b } else { a },
+                    )
+            })
+            .collect::<Vec<_>>();
+        let excess = |i: usize| best.get(i).map_or(f32::NEG_INFINITY, |b| b.2);
+        let mut peaks = best
+            .iter()
+            .enumerate()
+            .filter(|&(i, &(_, score, e))| {
+                // Ties go to the lowest bin
+                score >= self.sync_threshold && (i == 0 || e > excess(i - 1)) && e >= excess(i + 1)
+            })
+            .map(|(bin, &(frame, _, e))| (frame, bin, e))
+            .collect::<Vec<_>>();
+        peaks.sort_by(|a, b| b.2.total_cmp(&a.2));
+        peaks.truncate(self.max_candidates);
+        peaks
+            .into_iter()
+            .map(|(frame, bin, _)| (frame, bin))
+            .collect()
+    }
+
+    fn decode_candidate(
+        &self,
+        pwr: &[Vec<f32>],
+        frame: usize,
+        bin: usize,
+        noise: f32,
+    ) -> Option<Message> {
+        // Power of each value of each codeword symbol, undoing the interleaving
+        // and the Gray code
+        let data = zip(0..JT65_SYMBOLS, JT65_SYNC)
+            .filter(|&(_, sync)| sync == 0)
+            .map(|(i, _)| i);
+        let mut powers = [[0.0; 64]; DATA_SYMBOLS];
+        for (i, p) in zip(data, interleave_order()) {
+            for (value, power) in powers[p].iter_mut().enumerate() {
+                *power = self.tone_power(pwr, frame, bin, i, gray(value as u8) as usize + 2);
+            }
+        }
+        let (codeword, _) = self.rs_soft_decode(&powers)?;
+        // Every constant word is a codeword, a steady carrier on one tone decodes to one
+        if codeword.iter().all(|&c| c == codeword[0]) {
+            return None;
+        }
+        let message = codeword[NROOTS..].try_into().unwrap();
+        let bits = from_symbols(message);
+        // Only values no station sends fail to unpack
+        let data = MessageData::Jt65(Jt65Message::from_bits(&bits).ok()?);
+
+        // Signal power on the tones actually sent, against the noise in 2500 Hz
+        let signal = zip(0..JT65_SYMBOLS, channel_symbols(message))
+            .map(|(i, tone)| self.tone_power(pwr, frame, bin, i, tone as usize))
+            .sum::<f32>()
+            / JT65_SYMBOLS as f32;
+        let snr = 10.0 * ((signal - noise).max(noise * 1e-4) / noise).log10()
+            + 10.0 * (1.5 * self.bin_hz / 2500.0).log10();
+        let start = frame * self.hop + self.symbol_samples as usize / 2;
+        Some(Message {
+            snr,
+            freq_bin_hz: ((self.first_bin + bin) as f32 * self.bin_hz).round() as u32,
+            time_offset_ms: (start as f32 / self.sr * 1000.0).round() as i64,
+            data,
+            bits: bits.to_vec(),
+            ap_pass: None,
+            nominal_start_ms: (NOMINAL_START * 1000.0) as i64,
+        })
+    }
+
+    // Hard decisions first, then the least reliable symbols erased at random in
+    // the manner of the Franke-Taylor decoder, reliability being the ratio of
+    // the strongest value's power to the next
+    fn rs_soft_decode(
+        &self,
+        powers: &[[f32; 64]; DATA_SYMBOLS],
+    ) -> Option<([u8; DATA_SYMBOLS], usize)> {
+        let mut hard = [0; DATA_SYMBOLS];
+        let mut reliability = [0.0; DATA_SYMBOLS];
+        for (p, values) in powers.iter().enumerate() {
+            let (mut first, mut second) = (0, 1);
+            if values[1] > values[0] {
+                (first, second) = (1, 0);
+            }
+            for (value, &power) in values.iter().enumerate().skip(2) {
+                if power > values[first] {
+                    (first, second) = (value, first);
+                } else if power > values[second] {
+                    second = value;
+                }
+            }
+            hard[p] = first as u8;
+            reliability[p] = values[first] / (values[second] + 1e-12);
+        }
+        if let Some(decoded) = rs_decode(&hard, &[]) {
+            return Some(decoded);
+        }
+
+        let mut order = (0..DATA_SYMBOLS).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| reliability[a].total_cmp(&reliability[b]));
+        // A fixed seed keeps decodes repeatable
+        let mut state = 0x2545f491u32;
+        let mut random = || {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            (state >> 8) as f32 / (1 << 24) as f32
+        };
+        let mut erasures = Vec::with_capacity(MAX_ERASURES);
+        for _ in 0..self.erasure_trials {
+            // Less reliable symbols are more likely to be erased
+            let scale = 0.5 + 0.5 * random();
+            erasures.clear();
+            for (rank, &p) in order.iter().enumerate() {
+                if erasures.len() == MAX_ERASURES {
+                    break;
+                }
+                if random() < scale * (1.0 - rank as f32 / DATA_SYMBOLS as f32) {
+                    erasures.push(p);
+                }
+            }
+            if let Some(decoded) = rs_decode(&hard, &erasures) {
+                return Some(decoded);
+            }
+        }
+        None
+    }
+}
+
+pub struct Jt65EncodeParams {
+    pub sr: f32,
+    pub submode: Jt65Submode,
+    // Audio frequency of the sync tone
+    pub freq: f32,
+    pub amplitude: f32,
+}
+
+impl Default for Jt65EncodeParams {
+    fn default() -> Self {
+        Jt65EncodeParams {
+            sr: 12000.0,
+            submode: Jt65Submode::A,
+            freq: 1270.5,
+            amplitude: 1.0,
+        }
+    }
+}
+
+/// Generates JT65 audio.
+pub struct Jt65Encoder {
+    sr: f32,
+    submode: Jt65Submode,
+    freq: f32,
+    amplitude: f32,
+}
+
+impl Jt65Encoder {
+    pub fn new(params: &Jt65EncodeParams) -> Jt65Encoder {
+        Jt65Encoder {
+            sr: params.sr,
+            submode: params.submode,
+            freq: params.freq,
+            amplitude: params.amplitude,
+        }
+    }
+
+    /// The transmission alone, 126 symbols long. `None` if the message has no JT65
+    /// form. Standard messages are converted field by field and anything else is
+    /// sent as up to 13 characters of free text.
+    pub fn encode(&self, message: &MessageData) -> Option<Vec<f32>> {
+        Some(self.synthesize(&Self::encode_symbols(message)?))
+    }
+
+    /// A whole cycle with the transmission starting 1 s in.
+    pub fn encode_cycle(&self, message: &MessageData) -> Option<Vec<f32>> {
+        let wave = self.encode(message)?;
+        let mut samples = vec![0.0; (CYCLE_SECONDS * self.sr) as usize];
+        let start = (NOMINAL_START * self.sr) as usize;
+        zip(&mut samples[start..], wave).for_each(|(s, w)| *s = w);
+        Some(samples)
+    }
+
+    /// Continuous phase 65-FSK, tone 0 is the sync tone.
+    pub fn synthesize(&self, symbols: &[u8; JT65_SYMBOLS]) -> Vec<f32> {
+        let symbol_samples = self.sr * 4096.0 / 11025.0;
+        let num_samples = (JT65_SYMBOLS as f32 * symbol_samples).round() as usize;
+        let mut phase = 0.0f64;
+        (0..num_samples)
+            .map(|n| {
+                let symbol = symbols[((n as f32 / symbol_samples) as usize).min(JT65_SYMBOLS - 1)];
+                let freq = self.freq + symbol as f32 * self.submode.tone_spacing();
+                let x = self.amplitude * phase.sin() as f32;
+                phase = (phase + 2.0 * std::f64::consts::PI * freq as f64 / self.sr as f64)
+                    % (2.0 * std::f64::consts::PI);
+                x
+            })
+            .collect()
+    }
+
+    pub fn write_wav<P: AsRef<Path>>(&self, path: P, samples: &[f32]) -> Result<(), hound::Error> {
+        write_wav(path, samples, self.sr as u32)
+    }
+
+    pub fn encode_symbols(message: &MessageData) -> Option<[u8; JT65_SYMBOLS]> {
+        let bits = Jt65Message::from_data(message)?.to_bits().ok()?;
+        Some(channel_symbols(&to_symbols(&bits)))
+    }
+}
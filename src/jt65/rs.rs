@@ -0,0 +1,185 @@
+// The RS(63,12) code over GF(64) JT65 sends its 12 message symbols with, as in
+// Karn's library: field polynomial x^6 + x + 1, first consecutive root alpha^3.
+// Codewords are held lowest power first, parity in 0..51 and message in 51..63
+
+use static_init::dynamic;
+
+const NN: usize = 63;
+pub(crate) const NROOTS: usize = 51;
+const FCR: usize = 3;
+const GF_POLY: u32 = 0x43;
+
+struct Field {
+    exp: [u8; NN],
+    log: [usize; NN + 1],
+    generator: [u8; NROOTS + 1],
+}
+
+#[dynamic]
+static FIELD: Field = Field::new();
+
+impl Field {
+    fn new() -> Field {
+        let mut exp = [0; NN];
+        let mut log = [0; NN + 1];
+        let mut x = 1u32;
+        for (i, e) in exp.iter_mut().enumerate() {
+            *e = x as u8;
+            log[x as usize] = i;
+            x <<= 1;
+            if x & 0x40 != 0 {
+                x ^= GF_POLY;
+            }
+        }
+        let mut field = Field {
+            exp,
+            log,
+            generator: [0; NROOTS + 1],
+        };
+        // Product of (x + alpha^(FCR + i)), lowest power first
+        field.generator[0] = 1;
+        for i in 0..NROOTS {
+            let root = field.pow(FCR + i);
+            for j in (1..=i + 1).rev() {
+                field.generator[j] = field.generator[j - 1] ^ field.mul(field.generator[j], root);
+            }
+            field.generator[0] = field.mul(field.generator[0], root);
+        }
+        field
+    }
+
+    fn pow(&self, n: usize) -> u8 {
+        self.exp[n % NN]
+    }
+
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            0
+        } else {
+            self.exp[(self.log[a as usize] + self.log[b as usize]) % NN]
+        }
+    }
+
+    fn div(&self, a: u8, b: u8) -> u8 {
+        if a == 0 {
+            0
+        } else {
+            self.exp[(self.log[a as usize] + NN - self.log[b as usize]) % NN]
+        }
+    }
+
+    // Evaluates a polynomial held lowest power first
+    fn eval(&self, poly: &[u8], x: u8) -> u8 {
+        poly.iter().rev().fold(0, |acc, &c| self.mul(acc, x) ^ c)
+    }
+}
+
+pub(crate) fn rs_encode(message: &[u8; NN - NROOTS]) -> [u8; NN] {
+    let field = &*FIELD;
+    let mut codeword = [0; NN];
+    codeword[NROOTS..].copy_from_slice(message);
+    // The parity is the remainder of message * x^51 divided by the generator
+    let mut remainder = codeword;
+    for k in (NROOTS..NN).rev() {
+        let coefficient = remainder[k];
+        for (i, &g) in field.generator.iter().enumerate() {
+            remainder[k - NROOTS + i] ^= field.mul(coefficient, g);
+        }
+    }
+    codeword[..NROOTS].copy_from_slice(&remainder[..NROOTS]);
+    codeword
+}
+
+// Berlekamp-Massey errors and erasures decoding, `erasures` are codeword positions.
+// Gives the corrected codeword and the number of symbols changed
+pub(crate) fn rs_decode(received: &[u8; NN], erasures: &[usize]) -> Option<([u8; NN], usize)> {
+    let field = &*FIELD;
+    if erasures.len() > NROOTS {
+        return None;
+    }
+    let syndromes = (0..NROOTS)
+        .map(|j| field.eval(received, field.pow(FCR + j)))
+        .collect::<Vec<_>>();
+    if syndromes.iter().all(|&s| s == 0) {
+        return Some((*received, 0));
+    }
+
+    // Start from the erasure locator, the product of (1 + X x) over the erasures
+    let mut lambda = vec![0u8; NROOTS + 1];
+    lambda[0] = 1;
+    for &position in erasures {
+        let x = field.pow(position);
+        for j in (1..=NROOTS).rev() {
+            lambda[j] ^= field.mul(x, lambda[j - 1]);
+        }
+    }
+    let mut b = lambda.clone();
+    let mut length = erasures.len();
+    for r in erasures.len() + 1..=NROOTS {
+        let discrepancy = (0..r)
+            .map(|i| field.mul(lambda[i], syndromes[r - 1 - i]))
+            .fold(0, |acc, x| acc ^ x);
+        b.rotate_right(1);
+        b[0] = 0;
+        if discrepancy == 0 {
+            continue;
+        }
+        let t = lambda
+            .iter()
+            .zip(&b)
+            .map(|(&l, &b)| l ^ field.mul(discrepancy, b))
+            .collect::<Vec<_>>();
+        if 2 * length < r + erasures.len() {
+            length = r + erasures.len() - length;
+            // The locator before this step becomes the new correction term
+            b = lambda
+                .iter()
+                .map(|&l| field.div(l, discrepancy))
+                .collect::<Vec<_>>();
+        }
+        lambda = t;
+    }
+    let degree = lambda.iter().rposition(|&l| l != 0)?;
+
+    // Chien search, position k is in error when lambda(alpha^-k) is zero
+    let positions = (0..NN)
+        .filter(|&k| field.eval(&lambda, field.pow(NN - k)) == 0)
+        .collect::<Vec<_>>();
+    // Beyond 2 errors + erasures <= 51 a codeword is still found but is not the nearest
+    let errors = positions.iter().filter(|k| !erasures.contains(k)).count();
+    if positions.len() != degree || 2 * errors + erasures.len() > NROOTS {
+        return None;
+    }
+
+    // Forney: e = X^(1 - FCR) omega(X^-1) / lambda'(X^-1)
+    let mut omega = vec![0u8; NROOTS];
+    for (i, &s) in syndromes.iter().enumerate() {
+        for (j, &l) in lambda.iter().enumerate().take(NROOTS - i) {
+            omega[i + j] ^= field.mul(s, l);
+        }
+    }
+    let derivative = lambda
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, &l)| if i % 2 == 1 { l } else { 0 })
+        .collect::<Vec<_>>();
+    let mut corrected = *received;
+    for &k in &positions {
+        let x_inverse = field.pow(NN - k);
+        let denominator = field.eval(&derivative, x_inverse);
+        if denominator == 0 {
+            return None;
+        }
+        let numerator = field.mul(
+            field.eval(&omega, x_inverse),
+            field.pow((NN - k) * (FCR - 1)),
+        );
+        corrected[k] ^= field.div(numerator, denominator);
+    }
+    let valid = (0..NROOTS).all(|j| field.eval(&corrected, field.pow(FCR + j)) == 0);
+    let changed = std::iter::zip(&corrected, received)
+        .filter(|(a, b)| a != b)
+        .count();
+    valid.then_some((corrected, changed))
+}
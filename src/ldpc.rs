@@ -1,12 +1,70 @@
+use std::iter::zip;
+
+use static_init::dynamic;
+
 use crate::{
     constants::{FT8_MN, FT8_MNV, FT8_NM, FT8_NMC},
     utils::{optimal_leave_one_out_multiplication_6, optimal_leave_one_out_multiplication_7},
 };
 
-pub(crate) fn   ldpc_decode(bits: &[f32; 174], decode_times: u32) -> Option<[bool; 174]> {
+// Row i selects the message bits that are summed into parity bit 91 + i
+#[dynamic]
+static FT8_GENERATOR: [u128; 83] = compute_generator();
+
+fn compute_generator() -> [u128; 83] {
+    // Split every check into its message part and its parity part, then
+    // reduce the parity part to the identity so each check yields one parity bit
+    let mut message_part = [0u128; 83];
+    let mut parity_part = [0u128; 83];
+    for check in 0..83 {
+        for &var in FT8_NM[check].iter().filter(|&&var| var != 255) {
+            let var = var as usize;
+            if var < 91 {
+                message_part[check] ^= 1 << var;
+            } else {
+                parity_part[check] ^= 1 << (var - 91);
+            }
+        }
+    }
+    for col in 0..83 {
+        let pivot = (col..83)
+            .find(|&row| parity_part[row] & (1 << col) != 0)
+            .expect("parity part of the check matrix is singular");
+        parity_part.swap(col, pivot);
+        message_part.swap(col, pivot);
+        for row in 0..83 {
+            if row != col && parity_part[row] & (1 << col) != 0 {
+                parity_part[row] ^= parity_part[col];
+                message_part[row] ^= message_part[col];
+            }
+        }
+    }
+    message_part
+}
+
+pub(crate) fn ldpc_encode(message: &[bool; 91]) -> [bool; 174] {
+    let bits = message
+        .iter()
+        .enumerate()
+        .fold(0u128, |acc, (i, &x)| if x { acc | (1 << i) } else { acc });
+    let mut codeword = [false; 174];
+    codeword[..91].copy_from_slice(message);
+    for (i, row) in FT8_GENERATOR.iter().enumerate() {
+        codeword[91 + i] = (row & bits).count_ones() & 1 == 1;
+    }
+    codeword
+}
+
+// `soft` is left holding the bit probabilities of the last iteration
+pub(crate) fn ldpc_decode(
+    bits: &[f32; 174],
+    decode_times: u32,
+    soft: &mut [f32; 174],
+) -> Option<[bool; 174]> {
     let mut v = [[0.0; 3]; 174];
     let mut c = [[0.0; 7]; 83];
     let mut output = [false; 174];
+    *soft = *bits;
     let mut mults = [0.0; 7];
     for i in 0..174 {
         for j in 0..3 {
@@ -58,6 +116,7 @@ pub(crate) fn   ldpc_decode(bits: &[f32; 174], decode_times: u32) -> Option<[boo
             let p_2 = v[i][2];
             let is_1 = bits[i] * p_0 * p_1 * p_2;
             let is_0 = (1.0 - bits[i]) * (1.0 - p_0) * (1.0 - p_1) * (1.0 - p_2);
+            soft[i] = is_1 / (is_0 + is_1 + 1e-12);
             output[i] = is_1 > is_0;
         }
         if ldpc_check(&output) {
@@ -67,7 +126,7 @@ pub(crate) fn   ldpc_decode(bits: &[f32; 174], decode_times: u32) -> Option<[boo
     None
 }
 
-fn ldpc_check(codeword: &[bool; 174]) -> bool {
+pub(crate) fn ldpc_check(codeword: &[bool; 174]) -> bool {
     for i in 0..83 {
         let mut x = false;
         for j in 0..6 {
@@ -84,3 +143,155 @@ fn ldpc_check(codeword: &[bool; 174]) -> bool {
     }
     return true;
 }
+
+// Most reliable positions outside the information set that order 2 and 3
+// patterns are screened on when pre-processing is enabled
+const OSD_THETA: usize = 12;
+const OSD_THETA_MAX_ERRORS: u32 = 3;
+
+type Bits174 = [u64; 3];
+
+fn get_bit(bits: &Bits174, i: usize) -> bool {
+    bits[i / 64] >> (i % 64) & 1 == 1
+}
+
+fn set_bit(bits: &mut Bits174, i: usize) {
+    bits[i / 64] |= 1 << (i % 64);
+}
+
+fn xor_bits(a: &Bits174, b: &Bits174) -> Bits174 {
+    [a[0] ^ b[0], a[1] ^ b[1], a[2] ^ b[2]]
+}
+
+fn count_bits(bits: &Bits174) -> u32 {
+    bits.iter().map(|x| x.count_ones()).sum()
+}
+
+pub(crate) struct OsdResult {
+    pub codeword: [bool; 174],
+    // Bits that disagree with the channel hard decisions
+    pub hard_errors: u32,
+}
+
+// Ordered statistics decoding: re-encode the 91 most reliable independent bits of
+// the soft output, flip up to `depth` of them and keep the codeword closest to the channel
+pub(crate) fn osd_decode(
+    soft: &[f32; 174],
+    channel: &[f32; 174],
+    depth: u8,
+    preprocess: bool,
+) -> OsdResult {
+    let mut order: [usize; 174] = std::array::from_fn(|i| i);
+    order.sort_by(|&a, &b| (soft[b] - 0.5).abs().total_cmp(&(soft[a] - 0.5).abs()));
+
+    // Generator rows with the columns in reliability order
+    let generator = &*FT8_GENERATOR;
+    let mut rows = [[0u64; 3]; 91];
+    for (pos, &col) in order.iter().enumerate() {
+        let mut column = if col < 91 {
+            1 << col
+        } else {
+            generator[col - 91]
+        };
+        while column != 0 {
+            set_bit(&mut rows[column.trailing_zeros() as usize], pos);
+            column &= column - 1;
+        }
+    }
+
+    // Reduce to the identity on the most reliable independent columns
+    let mut is_pivot = [false; 174];
+    let mut pivots = [0; 91];
+    let mut col = 0;
+    for row in 0..91 {
+        let pivot = loop {
+            if let Some(pivot) = (row..91).find(|&i| get_bit(&rows[i], col)) {
+                break pivot;
+            }
+            col += 1;
+        };
+        rows.swap(row, pivot);
+        let reduced = rows[row];
+        for (i, other) in rows.iter_mut().enumerate() {
+            if i != row && get_bit(other, col) {
+                *other = xor_bits(other, &reduced);
+            }
+        }
+        is_pivot[col] = true;
+        pivots[row] = col;
+        col += 1;
+    }
+
+    let mut hard = [0u64; 3];
+    let mut weights = [0.0; 174];
+    for (pos, &i) in order.iter().enumerate() {
+        if channel[i] > 0.5 {
+            set_bit(&mut hard, pos);
+        }
+        let p = channel[i].clamp(1e-6, 1.0 - 1e-6);
+        weights[pos] = (p / (1.0 - p)).ln().abs();
+    }
+    let mut theta = [0u64; 3];
+    (0..174)
+        .filter(|&pos| !is_pivot[pos])
+        .take(OSD_THETA)
+        .for_each(|pos| set_bit(&mut theta, pos));
+
+    let mut base = [0u64; 3];
+    for (row, &pos) in zip(&rows, &pivots) {
+        if soft[order[pos]] > 0.5 {
+            base = xor_bits(&base, row);
+        }
+    }
+
+    let mut best = (f32::INFINITY, base);
+    let mut evaluate = |candidate: &Bits174, flips: u8| {
+        let diff = xor_bits(candidate, &hard);
+        if preprocess && flips >= 2 {
+            let theta_diff = [diff[0] & theta[0], diff[1] & theta[1], diff[2] & theta[2]];
+            if count_bits(&theta_diff) > OSD_THETA_MAX_ERRORS {
+                return;
+            }
+        }
+        let mut distance = 0.0;
+        for (k, &word) in diff.iter().enumerate() {
+            let mut word = word;
+            while word != 0 {
+                distance += weights[k * 64 + word.trailing_zeros() as usize];
+                word &= word - 1;
+            }
+        }
+        if distance < best.0 {
+            best = (distance, *candidate);
+        }
+    };
+    osd_search(&rows, 0, 0, depth, &base, &mut evaluate);
+
+    let candidate = best.1;
+    let mut codeword = [false; 174];
+    for (pos, &i) in order.iter().enumerate() {
+        codeword[i] = get_bit(&candidate, pos);
+    }
+    OsdResult {
+        codeword,
+        hard_errors: count_bits(&xor_bits(&candidate, &hard)),
+    }
+}
+
+// Visits the codeword with every combination of up to `depth` more information bits flipped
+fn osd_search(
+    rows: &[Bits174; 91],
+    start: usize,
+    flips: u8,
+    depth: u8,
+    codeword: &Bits174,
+    evaluate: &mut impl FnMut(&Bits174, u8),
+) {
+    evaluate(codeword, flips);
+    if flips < depth {
+        for (row, generator) in rows.iter().enumerate().skip(start) {
+            let flipped = xor_bits(codeword, generator);
+            osd_search(rows, row + 1, flips + 1, depth, &flipped, evaluate);
+        }
+    }
+}
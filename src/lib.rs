@@ -2,20 +2,77 @@
 
 extern crate test;
 
+pub mod adif;
+pub mod cabrillo;
 mod callsignmap;
 mod constants;
+mod error;
 mod ldpc;
+pub mod foxhound;
+pub mod ft4;
 pub mod ft8;
-mod message;
+mod gfsk;
+pub mod jt65;
+pub mod message;
+pub mod pskreporter;
+mod resample;
+pub mod sequencer;
 mod utils;
+pub mod wsjtx;
+pub mod wspr;
+
+pub use callsignmap::CallsignMap;
+pub use error::Error;
 
 #[cfg(test)]
 mod tests {
 
-    use crate::{callsignmap::CallsignMap, utils::{optimal_leave_one_out_multiplication_7, optimal_leave_one_out_multiplication_6}, ldpc::ldpc_decode};
+    use std::{
+        f32::consts::PI,
+        iter::zip,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{
+        callsignmap::CallsignMap,
+        constants::{
+            FT4_COSTAS, FT4_SCRAMBLE, FT4_SYMBOLS, FT8_COSTAS, FT8_SYMBOLS, JT65_SYNC,
+        },
+        ft4::FT4Encoder,
+        ft8::{ApContext, ApPass, DecodeParams, EncodeParams, FT8Decoder, FT8Encoder},
+        jt65::{
+            message::{from_symbols, to_symbols},
+            rs::{rs_decode, rs_encode},
+            Jt65DecodeParams, Jt65Decoder, Jt65EncodeParams, Jt65Encoder, Jt65Message,
+        },
+        ldpc::{ldpc_check, ldpc_decode, ldpc_encode, osd_decode},
+        message::MessageData,
+        pskreporter::{encode_packet, Receiver, Spot},
+        resample::Resampler,
+        utils::{optimal_leave_one_out_multiplication_6, optimal_leave_one_out_multiplication_7},
+        wsjtx::{
+            Clear, Configure, FreeText, HaltTx, Heartbeat, Location, Packet, QsoLogged, Reply,
+            Status,
+        },
+        wspr::{WsprDecoder, WsprEncoder, WsprMessage},
+    };
 
     use test::Bencher;
 
+    // Unit variance Gaussian noise, Box-Muller over a 64 bit LCG so runs repeat
+    fn lcg_noise(seed: u64, len: usize) -> Vec<f32> {
+        let mut state = seed;
+        let mut uniform = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((state >> 40) as f32 + 0.5) / (1u64 << 24) as f32
+        };
+        (0..len)
+            .map(|_| (-2.0 * uniform().ln()).sqrt() * (2.0 * PI * uniform()).cos())
+            .collect()
+    }
+
     #[test]
     fn optimal_mult_test() {
         let mults = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0];
@@ -69,9 +126,560 @@ mod tests {
         for i in 0..174 {
             bits[i] = ((i & 1) as f32) * 0.8 + 0.1;
         }
-        b.iter(|| ldpc_decode(&bits, 10));
+        b.iter(|| ldpc_decode(&bits, 10, &mut [0.0; 174]));
     }
     
+    #[test]
+    fn ldpc_encode_valid_codeword() {
+        let mut message = [false; 91];
+        message
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, x)| *x = (i * 7 + i / 3) % 5 < 2);
+        let codeword = ldpc_encode(&message);
+        assert!(ldpc_check(&codeword));
+        assert_eq!(&codeword[..91], &message[..]);
+
+        let probabilities = codeword.map(|x| if x { 0.9 } else { 0.1 });
+        assert_eq!(
+            ldpc_decode(&probabilities, 10, &mut [0.0; 174]),
+            Some(codeword)
+        );
+    }
+
+    #[test]
+    fn osd_corrects_reliable_errors() {
+        let mut message = [false; 91];
+        message
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, x)| *x = (i * 3 + i / 5) % 7 < 3);
+        let codeword = ldpc_encode(&message);
+
+        // Two confidently wrong bits land in the information set and need order 2
+        let mut probabilities = codeword.map(|x| if x { 0.8 } else { 0.2 });
+        for i in [3, 40] {
+            probabilities[i] = if codeword[i] { 0.01 } else { 0.99 };
+        }
+        let result = osd_decode(&probabilities, &probabilities, 2, false);
+        assert_eq!(result.codeword, codeword);
+        assert_eq!(result.hard_errors, 2);
+        assert_eq!(
+            osd_decode(&probabilities, &probabilities, 2, true).codeword,
+            codeword
+        );
+        assert_ne!(
+            osd_decode(&probabilities, &probabilities, 1, false).codeword,
+            codeword
+        );
+    }
+
+    #[test]
+    fn osd_no_false_decodes_on_noise() {
+        for samples in lcg_noise(0x2545f4914f6cdd1d, 4 * 12000 * 15).chunks(12000 * 15) {
+            let messages = FT8Decoder::new(&DecodeParams {
+                osd_depth: Some(2),
+                ..Default::default()
+            })
+            .decode(samples)
+            .unwrap();
+            assert!(messages.is_empty());
+        }
+    }
+
+    #[test]
+    fn ft8_encode_symbols() {
+        let mut message = [false; 77];
+        message
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, x)| *x = (i * 5 + i / 4) % 3 == 0);
+        let symbols = FT8Encoder::encode_symbols(&message);
+        assert_eq!(&symbols[0..7], &FT8_COSTAS);
+        assert_eq!(&symbols[36..43], &FT8_COSTAS);
+        assert_eq!(&symbols[72..79], &FT8_COSTAS);
+
+        let mut codeword = [false; 174];
+        (7..36)
+            .chain(43..72)
+            .flat_map(|i| {
+                let x = FT8_SYMBOLS.iter().position(|&s| s == symbols[i]).unwrap();
+                [x & 4 != 0, x & 2 != 0, x & 1 != 0]
+            })
+            .enumerate()
+            .for_each(|(i, x)| codeword[i] = x);
+        assert!(ldpc_check(&codeword));
+        assert_eq!(&codeword[..77], &message[..]);
+        let crc = codeword[77..91]
+            .iter()
+            .fold(0, |acc, &x| (acc << 1) | x as u16);
+        assert_eq!(crc, FT8Decoder::bitwise_crc14(&message));
+    }
+
+    #[test]
+    fn ft4_encode_symbols() {
+        let mut message = [false; 77];
+        message
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, x)| *x = (i * 5 + i / 4) % 3 == 0);
+        let symbols = FT4Encoder::encode_symbols(&message);
+        for (offset, costas) in zip([0, 33, 66, 99], FT4_COSTAS) {
+            assert_eq!(&symbols[offset..offset + 4], &costas);
+        }
+
+        let mut codeword = [false; 174];
+        (4..33)
+            .chain(37..66)
+            .chain(70..99)
+            .flat_map(|i| {
+                let x = FT4_SYMBOLS.iter().position(|&s| s == symbols[i]).unwrap();
+                [x & 2 != 0, x & 1 != 0]
+            })
+            .enumerate()
+            .for_each(|(i, x)| codeword[i] = x);
+        assert!(ldpc_check(&codeword));
+        // The payload is sent scrambled
+        zip(&codeword[..77], zip(message, FT4_SCRAMBLE))
+            .for_each(|(&sent, (bit, x))| assert_eq!(sent, bit ^ (x == 1)));
+    }
+
+    // K1ABC FN42 37: callsign 259047992, locator 22632 and power 37 + 64
+    fn wspr_k1abc() -> [bool; 50] {
+        let mut message = [false; 50];
+        let n = 259047992u64 << 22 | (22632 * 128 + 37 + 64);
+        message
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, bit)| *bit = n >> (49 - i) & 1 == 1);
+        message
+    }
+
+    #[test]
+    fn wspr_unpack() {
+        let mut map = CallsignMap::new();
+        let message = WsprMessage::from_bits(&wspr_k1abc(), &map).unwrap();
+        assert_eq!(message.to_string(), "K1ABC FN42 37");
+        assert_eq!(message.grid(), Some("FN42"));
+
+        // Type 3 sends the 15 bit hash and a 6 character locator rotated into the callsign field
+        let hash = map.insert_wspr("PJ4/K1ABC");
+        let mut message = [false; 50];
+        let n = 163802552u64 << 22 | (hash as u64 * 128 + 64 - 38);
+        message
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, bit)| *bit = n >> (49 - i) & 1 == 1);
+        let hashed = WsprMessage::from_bits(&message, &map).unwrap();
+        assert_eq!(hashed.to_string(), "<PJ4/K1ABC> FN42AX 37");
+        let unresolved = WsprMessage::from_bits(&message, &CallsignMap::new()).unwrap();
+        assert_eq!(unresolved.to_string(), "<...> FN42AX 37");
+    }
+
+    #[test]
+    fn wspr_decode_drifting_signal() {
+        let message = WsprMessage::from_text("K1ABC FN42 37").unwrap();
+        assert_eq!(message.to_bits().unwrap(), wspr_k1abc());
+        let symbols = WsprEncoder::encode_symbols(&message).unwrap();
+        let mut samples = lcg_noise(0x2545f4914f6cdd1d, 12000 * 120);
+        samples.iter_mut().for_each(|s| *s *= 0.1);
+        // Centred on 1502 Hz and drifting up 2 Hz over the transmission
+        let spacing = 12000.0 / 8192.0;
+        let mut phase = 0.0f64;
+        for (i, &symbol) in symbols.iter().enumerate() {
+            let freq = 1502.0 + 2.0 * (i as f32 - 81.0) / 162.0 + (symbol as f32 - 1.5) * spacing;
+            for n in 0..8192 {
+                samples[15000 + i * 8192 + n] += 0.01 * phase.sin() as f32;
+                phase += 2.0 * std::f64::consts::PI * freq as f64 / 12000.0;
+            }
+        }
+
+        let spots = WsprDecoder::new(&Default::default())
+            .decode(&samples)
+            .unwrap();
+        spots.iter().for_each(|spot| println!("{}", spot));
+        assert_eq!(spots.len(), 1);
+        assert_eq!(spots[0].message.to_string(), "K1ABC FN42 37");
+        assert!((spots[0].freq_hz - 1502.0).abs() <= 0.8);
+        assert!((spots[0].dt - 0.25).abs() <= 0.2);
+        assert!((spots[0].drift_hz - 2.0).abs() <= 1.0);
+    }
+
+    #[test]
+    fn jt65_message_round_trip() {
+        for text in [
+            "CQ K1ABC FN42",
+            "CQ 050 K1ABC FN42",
+            "K1ABC W9XYZ R-15",
+            "W9XYZ K1ABC RO",
+            "K1ABC W9XYZ RRR",
+            "W9XYZ K1ABC 73",
+            "K1ABC W9XYZ",
+            "CQ PJ4/K1ABC FN42",
+            "QRZ K1ABC/P FN42",
+            "DE KH6/W9XYZ",
+            "<12>/W9XYZ K1ABC",
+            "CQ K1ABC/<3>",
+            "TNX BOB 73 GL",
+        ] {
+            let message = Jt65Message::from_text(text).unwrap();
+            let bits = message.to_bits().unwrap();
+            assert_eq!(from_symbols(&to_symbols(&bits)), bits);
+            let received = Jt65Message::from_bits(&bits).unwrap();
+            assert_eq!(received, message);
+            assert_eq!(received.to_string(), text);
+        }
+        let roger = Jt65Message::from_text("W9XYZ K1ABC RO").unwrap();
+        assert_eq!(roger.report(), None);
+        assert_eq!(roger.grid(), None);
+        // RO is JT65's alone
+        assert!(MessageData::from_text("W9XYZ K1ABC RO").is_none());
+        // There is no room for a frequency beside a prefix, or a locator beside a
+        // listed prefix
+        assert!(Jt65Message::from_text("CQ 123 PJ4/K1ABC").is_none());
+        assert!(Jt65Message::from_text("<12>/W9XYZ K1ABC FN42").is_none());
+        // FT8 messages are converted field by field, or sent as free text
+        let data = MessageData::from_text("K1ABC W9XYZ R-15").unwrap();
+        let message = Jt65Message::from_data(&data).unwrap();
+        assert!(matches!(message, Jt65Message::Standard { .. }));
+        assert_eq!(message.report(), Some(-15));
+        for text in ["K1ABC W9XYZ RR73", "K1ABC W9XYZ +05", "K1ABC W9XYZ R FN42"] {
+            assert!(Jt65Message::from_data(&MessageData::from_text(text).unwrap()).is_none());
+        }
+    }
+
+    #[test]
+    fn jt65_reed_solomon() {
+        let bits = Jt65Message::from_text("K1ABC W9XYZ -15")
+            .unwrap()
+            .to_bits()
+            .unwrap();
+        let message = to_symbols(&bits);
+        let codeword = rs_encode(&message);
+        assert_eq!(codeword[51..], message);
+        // 25 errors, or 10 errors and 31 erasures, are within the minimum distance
+        let mut received = codeword;
+        (0..25).for_each(|i| received[2 * i] ^= 1 + i as u8);
+        assert_eq!(rs_decode(&received, &[]), Some((codeword, 25)));
+        let erasures = (0..41).map(|i| i + 22).collect::<Vec<_>>();
+        let mut received = codeword;
+        erasures.iter().for_each(|&p| received[p] ^= 7);
+        assert_eq!(rs_decode(&received, &erasures[..31]), Some((codeword, 41)));
+    }
+
+    #[test]
+    fn jt65_known_answer() {
+        // The worked example in the JT65 article in QEX, September/October 2005
+        let data = MessageData::from_text("G3LTF DL9KR JO40").unwrap();
+        let bits = Jt65Message::from_data(&data).unwrap().to_bits().unwrap();
+        assert_eq!(
+            to_symbols(&bits),
+            [61, 37, 30, 28, 9, 27, 61, 58, 26, 3, 49, 16]
+        );
+        let channel = [
+            14, 16, 9, 18, 4, 60, 41, 18, 22, 63, 43, 5, 30, 13, 15, 9, 25, 35, 50, 21, 0, 36, 17,
+            42, 33, 35, 39, 22, 25, 39, 46, 3, 47, 39, 55, 23, 61, 25, 58, 47, 16, 38, 39, 17, 2,
+            36, 4, 56, 5, 16, 15, 55, 18, 41, 7, 26, 51, 17, 18, 49, 10, 13, 24,
+        ];
+        let tones = Jt65Encoder::encode_symbols(&data).unwrap();
+        let mut data_tones = channel.iter().map(|&s| s + 2);
+        for (tone, sync) in zip(tones, JT65_SYNC) {
+            let expected = if sync == 1 { 0 } else { data_tones.next().unwrap() };
+            assert_eq!(tone, expected);
+        }
+    }
+
+    #[test]
+    fn jt65_decode_weak_signal() {
+        let message = Jt65Message::from_text("K1ABC W9XYZ R-15").unwrap();
+        let encoder = Jt65Encoder::new(&Jt65EncodeParams {
+            freq: 1200.0,
+            amplitude: 0.065,
+            ..Default::default()
+        });
+        // Unit variance noise from a fixed LCG, the signal is about -23 dB in 2500 Hz
+        let mut samples = lcg_noise(0x2545f4914f6cdd1d, 12000 * 60);
+        let wave = encoder
+            .encode_cycle(&MessageData::Jt65(message.clone()))
+            .unwrap();
+        zip(&mut samples, wave).for_each(|(s, w)| *s += w);
+
+        let messages = Jt65Decoder::new(&Jt65DecodeParams {
+            from_freq: 1100.0,
+            to_freq: 1300.0,
+            ..Default::default()
+        })
+        .decode(&samples)
+        .unwrap();
+        messages.iter().for_each(|f| println!("{}", f));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].bits(), message.to_bits().unwrap());
+        assert!((messages[0].freq_bin_hz as i32 - 1200).abs() <= 2);
+        assert!((messages[0].time_offset_ms - 1000).abs() <= 200);
+        assert!(messages[0].dt_seconds().abs() <= 0.2);
+        assert!((messages[0].snr() + 23.0).abs() <= 2.0);
+    }
+
+    #[test]
+    fn sync_candidates_rank_signal_first() {
+        let message = MessageData::from_text("CQ K1ABC FN42")
+            .unwrap()
+            .to_bits()
+            .unwrap();
+        let encoder = FT8Encoder::new(&EncodeParams {
+            freq: 1000.0,
+            amplitude: 0.2,
+            ..Default::default()
+        });
+        // Unit variance noise from a fixed LCG, the signal is about -13 dB in 2500 Hz
+        let mut samples = lcg_noise(0x2545f4914f6cdd1d, 12000 * 30);
+        zip(&mut samples[6000..], encoder.encode(&message)).for_each(|(s, w)| *s += w);
+
+        let mut decoder = FT8Decoder::new(&DecodeParams {
+            from_freq: 200.0,
+            to_freq: 2500.0,
+            ..Default::default()
+        });
+        // Frames half a symbol apart, each scored and ranked as the streaming decoder does
+        let mut candidates = Vec::new();
+        let mut messages = Vec::new();
+        for start in (0..samples.len() - 3840).step_by(960) {
+            messages.extend(
+                decoder
+                    .process_frame(&samples[start..start + 3840])
+                    .unwrap(),
+            );
+            let ranked = decoder.sync_candidates();
+            assert!(ranked.len() <= 100);
+            assert!(ranked.windows(2).all(|w| w[0].score >= w[1].score));
+            candidates.extend(ranked);
+        }
+        // The signal's lowest tone is bin 320 of the 3.125 Hz bins, it outscores any
+        // noise peak by far and is the only decode
+        let best = candidates
+            .iter()
+            .max_by(|a, b| a.score.total_cmp(&b.score))
+            .unwrap();
+        assert_eq!(best.bin, 320);
+        let noise_best = candidates
+            .iter()
+            .filter(|c| c.bin.abs_diff(320) > 2)
+            .map(|c| c.score)
+            .fold(f32::NEG_INFINITY, f32::max);
+        assert!(best.score > 2.0 * noise_best);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].to_string().ends_with("CQ K1ABC FN42"));
+    }
+
+    #[test]
+    fn ap_decode_weak_reply() {
+        let message = MessageData::from_text("W9XYZ K1ABC RR73")
+            .unwrap()
+            .to_bits()
+            .unwrap();
+        let encoder = FT8Encoder::new(&EncodeParams {
+            freq: 1000.0,
+            amplitude: 0.0813,
+            ..Default::default()
+        });
+        // Unit variance noise from a fixed LCG, the signal is about -21 dB in 2500 Hz
+        let mut samples = lcg_noise(0x2545f4914f6cdd1d, 12000 * 15);
+        zip(&mut samples[6000..], encoder.encode(&message)).for_each(|(s, w)| *s += w);
+
+        let params = DecodeParams {
+            from_freq: 900.0,
+            to_freq: 1100.0,
+            ..Default::default()
+        };
+        let is_reply = |text: String| text.contains("W9XYZ K1ABC RR73");
+        let plain = FT8Decoder::new(&params).decode(&samples).unwrap();
+        assert!(!plain.iter().any(|m| is_reply(m.to_string())));
+
+        let mut decoder = FT8Decoder::new(&params);
+        decoder.set_ap_context(&ApContext {
+            my_call: Some("W9XYZ".to_string()),
+            dx_call: Some("K1ABC".to_string()),
+            passes: ApPass::ALL.to_vec(),
+        });
+        let messages = decoder.decode(&samples).unwrap();
+        let reply = messages.iter().find(|m| is_reply(m.to_string())).unwrap();
+        assert!(matches!(
+            reply.ap_pass,
+            Some(ApPass::MyCallDxCall | ApPass::Rr73)
+        ));
+    }
+
+    #[test]
+    fn resampler_preserves_tone() {
+        for input_rate in [48000, 44100, 11025, 8000] {
+            let tone = |n: usize, rate: usize| (2.0 * PI * 1000.0 * n as f32 / rate as f32).sin();
+            let input = (0..input_rate / 2)
+                .map(|n| tone(n, input_rate))
+                .collect::<Vec<_>>();
+            let mut resampler = Resampler::new(input_rate, 12000);
+            // Feeding the input in uneven chunks must not change the output
+            let output = input
+                .chunks(1001)
+                .flat_map(|chunk| resampler.process(chunk))
+                .collect::<Vec<_>>();
+            // The last half filter of input is held back until more audio arrives
+            assert!((5900..=6000).contains(&output.len()));
+            for (n, &x) in output.iter().enumerate().skip(500).take(5000) {
+                assert!((x - tone(n, 12000)).abs() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn wsjtx_packet_roundtrip() {
+        let heartbeat = Packet::Heartbeat(Heartbeat {
+            max_schema: 3,
+            version: "2.6.1".to_string(),
+            revision: String::new(),
+        });
+        let bytes = heartbeat.to_bytes("WSJT-X");
+        assert_eq!(
+            &bytes[..22],
+            b"\xad\xbc\xcb\xda\0\0\0\x03\0\0\0\0\0\0\0\x06WSJT-X"
+        );
+        assert_eq!(
+            Packet::from_bytes(&bytes).unwrap(),
+            ("WSJT-X".to_string(), heartbeat)
+        );
+
+        let time_on = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let packets = [
+            Packet::Status(Status {
+                dial_frequency: 14074000,
+                mode: "FT8".to_string(),
+                dx_call: "K1ABC".to_string(),
+                decoding: true,
+                tr_period: 15,
+                ..Default::default()
+            }),
+            Packet::Clear(Clear { window: None }),
+            Packet::Clear(Clear { window: Some(2) }),
+            Packet::Reply(Reply {
+                time: 45_315_000,
+                snr: -12,
+                delta_time: 0.2,
+                delta_frequency: 1234,
+                mode: "~".to_string(),
+                message: "CQ K1ABC FN42".to_string(),
+                low_confidence: false,
+                modifiers: 0,
+            }),
+            Packet::QsoLogged(QsoLogged {
+                time_off: time_on + Duration::from_secs(90),
+                dx_call: "K1ABC".to_string(),
+                dx_grid: "FN42".to_string(),
+                tx_frequency: 14075234,
+                mode: "FT8".to_string(),
+                report_sent: "-12".to_string(),
+                report_received: "-08".to_string(),
+                tx_power: String::new(),
+                comments: String::new(),
+                name: String::new(),
+                time_on,
+                operator_call: String::new(),
+                my_call: "W9XYZ".to_string(),
+                my_grid: "EN37".to_string(),
+                exchange_sent: String::new(),
+                exchange_received: String::new(),
+                propagation_mode: String::new(),
+            }),
+            Packet::Close,
+            Packet::HaltTx(HaltTx { auto_tx_only: true }),
+            Packet::FreeText(FreeText {
+                text: "TNX BOB 73 GL".to_string(),
+                send: false,
+            }),
+            Packet::Location(Location {
+                location: "EN37".to_string(),
+            }),
+            Packet::Configure(Configure {
+                mode: "FT8".to_string(),
+                frequency_tolerance: u32::MAX,
+                sub_mode: String::new(),
+                fast_mode: false,
+                tr_period: u32::MAX,
+                rx_df: 1500,
+                dx_call: "K1ABC".to_string(),
+                dx_grid: "FN42".to_string(),
+                generate_messages: true,
+            }),
+        ];
+        for packet in packets {
+            let bytes = packet.to_bytes("weaksignal");
+            let (id, parsed) = Packet::from_bytes(&bytes).unwrap();
+            assert_eq!(id, "weaksignal");
+            assert_eq!(parsed, packet);
+            // The clear window is optional, so dropping it leaves a valid packet
+            if !matches!(packet, Packet::Clear(_)) {
+                assert!(Packet::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+            }
+        }
+        assert!(Packet::from_bytes(b"not a packet").is_err());
+    }
+
+    #[test]
+    fn pskreporter_packet_layout() {
+        let receiver = Receiver {
+            callsign: "W9XYZ".to_string(),
+            locator: "EN37".to_string(),
+            decoding_software: "weaksignal".to_string(),
+        };
+        let spot = Spot {
+            callsign: "K1ABC".to_string(),
+            frequency: 14075234,
+            snr: -12,
+            mode: "FT8".to_string(),
+            locator: Some("FN42".to_string()),
+            flow_start: 1_700_000_000,
+        };
+        let (packet, count) = encode_packet(
+            &receiver,
+            std::slice::from_ref(&spot),
+            true,
+            1_700_000_100,
+            7,
+            42,
+        );
+        assert_eq!(count, 1);
+        assert_eq!(&packet[..2], &[0, 10]);
+        assert_eq!(
+            u16::from_be_bytes([packet[2], packet[3]]) as usize,
+            packet.len()
+        );
+        assert_eq!(&packet[8..16], &[0, 0, 0, 7, 0, 0, 0, 42]);
+        // Templates as given in the PSK Reporter developer notes
+        let receiver_template = [
+            0x00, 0x03, 0x00, 0x24, 0x99, 0x92, 0x00, 0x03, 0x00, 0x00, 0x80, 0x02, 0xff, 0xff,
+            0x00, 0x00, 0x76, 0x8f, 0x80, 0x04, 0xff, 0xff, 0x00, 0x00, 0x76, 0x8f, 0x80, 0x08,
+            0xff, 0xff, 0x00, 0x00, 0x76, 0x8f, 0x00, 0x00,
+        ];
+        assert_eq!(&packet[16..52], &receiver_template);
+        let sender_template = [
+            0x00, 0x02, 0x00, 0x3c, 0x99, 0x93, 0x00, 0x07, 0x80, 0x01, 0xff, 0xff, 0x00, 0x00,
+            0x76, 0x8f, 0x80, 0x05, 0x00, 0x04, 0x00, 0x00, 0x76, 0x8f, 0x80, 0x06, 0x00, 0x01,
+            0x00, 0x00, 0x76, 0x8f, 0x80, 0x0a, 0xff, 0xff, 0x00, 0x00, 0x76, 0x8f, 0x80, 0x03,
+            0xff, 0xff, 0x00, 0x00, 0x76, 0x8f, 0x80, 0x0b, 0x00, 0x01, 0x00, 0x00, 0x76, 0x8f,
+            0x00, 0x96, 0x00, 0x04,
+        ];
+        assert_eq!(&packet[52..112], &sender_template);
+        let receiver_record = b"\x99\x92\x00\x1c\x05W9XYZ\x04EN37\x0aweaksignal\x00\x00";
+        assert_eq!(&packet[112..140], receiver_record);
+        let sender_record = b"\x99\x93\x00\x20\x05K1ABC\x00\xd6\xc5\x62\xf4\x03FT8\x04FN42\x01\x65\x53\xf1\x00\x00\x00\x00";
+        assert_eq!(&packet[140..], sender_record);
+
+        // Without templates, and split once a datagram would exceed the MTU
+        let spots = vec![spot; 100];
+        let (packet, count) = encode_packet(&receiver, &spots, false, 0, 0, 0);
+        assert!(packet.len() <= 1400 && packet.len() % 4 == 0);
+        assert!((40..100).contains(&count));
+        assert_eq!(&packet[16..18], &[0x99, 0x92]);
+    }
+
     #[test]
     fn callsign_hash_pj4k1abc() {
         let callsign = "PJ4/K1ABC".to_string();
@@ -1,24 +1,76 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
 use crate::{
     callsignmap::CallsignMap,
-    utils::{bool_slice_to_u32, bool_slice_to_u64, char_lookup},
+    utils::{
+        bool_slice_to_u32, bool_slice_to_u64, char_lookup, u32_to_bool_slice, u64_to_bool_slice,
+    },
 };
 
 use super::hashedcallsign::HashedCallsign;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallsignError {
+    Empty,
+    TooLong(String),
+    InvalidCharacter(String, char),
+    InvalidCqModifier(String),
+    InvalidSuffix(String),
+    NotStandard(String),
+    HashOutOfRange(u32),
+}
+impl Display for CallsignError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty callsign"),
+            Self::TooLong(call) => write!(f, "callsign {} is longer than 11 characters", call),
+            Self::InvalidCharacter(call, c) => {
+                write!(f, "callsign {} contains invalid character {:?}", call, c)
+            }
+            Self::InvalidCqModifier(modifier) => write!(
+                f,
+                "CQ modifier {} is neither 3 digits nor 1 to 4 letters",
+                modifier
+            ),
+            Self::InvalidSuffix(call) => {
+                write!(f, "callsign {} has a suffix other than /R or /P", call)
+            }
+            Self::NotStandard(call) => write!(f, "{} is not a standard callsign", call),
+            Self::HashOutOfRange(hash) => write!(f, "callsign hash {} is out of range", hash),
+        }
+    }
+}
+impl std::error::Error for CallsignError {}
+
+/// A standard callsign or one of the DE, QRZ and CQ tokens.
 #[derive(Debug, Clone)]
 pub struct Callsign28 {
     pub c28: u32,
     pub suffix: Option<char>,
 }
 
+/// A 28 bit callsign field, either a standard callsign or token or a 22 bit hash.
 #[derive(Debug, Clone)]
 pub enum Callsign {
     C28(Callsign28),
     H22(HashedCallsign<22>),
 }
 
+// c28 of the first standard callsign, the tokens and 22 bit hashes come before it
+pub(crate) const STANDARD_CALL_BASE: u32 = 6257896;
+
+// Whether a callsign field names a station, rather than CQ, QRZ, DE or a hash that
+// was never resolved, written with or without its angle brackets
+pub(crate) fn is_station(call: &str) -> bool {
+    let call = call.trim_matches(['<', '>']);
+    !(call.is_empty()
+        || call.starts_with("CQ")
+        || call == "QRZ"
+        || call == "DE"
+        || call == "..."
+        || call.chars().all(|c| c.is_ascii_digit()))
+}
+
 const ALPHANUMERIC: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
 const ALPHANUMERIC_SPACE: &str = " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
 const ALPHA_SPACE: &str = " ABCDEFGHIJKLMNOPQRSTUVWXYZ";
@@ -38,12 +90,70 @@ impl Callsign {
         Self::from_u32(num, suffix, callsign_map)
     }
     pub fn from_u32(n: u32, suffix: Option<char>, callsign_map: &CallsignMap) -> Self {
-        if n >= 2063592 && n < 6257896 {
+        if (2063592..STANDARD_CALL_BASE).contains(&n) {
             Self::H22(HashedCallsign::from_hash(n - 2063592, callsign_map))
         } else {
             Self::C28(Callsign28::from_u32(n, suffix))
         }
     }
+    // Unlike from_str, only callsigns written in angle brackets are hashed
+    pub(crate) fn parse_explicit(text: &str) -> Result<Self, CallsignError> {
+        if text.starts_with('<') {
+            text.parse().map(Self::H22)
+        } else {
+            text.parse().map(Self::C28)
+        }
+    }
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            Callsign::C28(n) => n.c28,
+            Callsign::H22(h) => h.hashed + 2063592,
+        }
+    }
+    pub fn suffix(&self) -> Option<char> {
+        match self {
+            Callsign::C28(n) => n.suffix,
+            Callsign::H22(_) => None,
+        }
+    }
+    pub fn to_bits(&self) -> [bool; 28] {
+        u32_to_bool_slice(self.to_u32())
+    }
+}
+impl FromStr for Callsign {
+    type Err = CallsignError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Self::parse_explicit(s) {
+            Err(CallsignError::NotStandard(_)) => s.parse::<Callsign58>().map(|_| {
+                // Calls that do not fit in 28 bits are sent as their 22 bit hash
+                Self::H22(HashedCallsign::from_callsign(s))
+            }),
+            result => result,
+        }
+    }
+}
+// Hashed calls keep their angle brackets, so a resolved hash of a standard call
+// does not come back as the full 28 bit call
+#[cfg(feature = "serde")]
+impl serde::Serialize for Callsign {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Callsign::C28(n) => serializer.collect_str(n),
+            Callsign::H22(HashedCallsign {
+                callsign: Some(callsign),
+                ..
+            }) => serializer.collect_str(&format_args!("<{}>", callsign)),
+            Callsign::H22(h) => serializer.collect_str(h),
+        }
+    }
+}
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Callsign {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = <String as serde::Deserialize>::deserialize(deserializer)?;
+        Self::parse_explicit(&text).map_err(serde::de::Error::custom)
+    }
 }
 impl Display for Callsign {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -74,6 +184,50 @@ impl Callsign28 {
         call.push(char_lookup((n % 37) as usize, ALPHANUMERIC_SPACE));
         call.trim().chars().rev().collect()
     }
+    fn pack_cq_modifier(modifier: &str) -> Result<u32, CallsignError> {
+        if modifier.len() == 3 && modifier.chars().all(|c| c.is_ascii_digit()) {
+            return Ok(modifier.parse::<u32>().unwrap_or(0) + 3);
+        }
+        if modifier.is_empty()
+            || modifier.len() > 4
+            || !modifier.chars().all(|c| c.is_ascii_uppercase())
+        {
+            return Err(CallsignError::InvalidCqModifier(modifier.to_string()));
+        }
+        let n = format!("{:<4}", modifier).chars().fold(0, |acc, c| {
+            acc * 27 + ALPHA_SPACE.find(c).unwrap_or(0) as u32
+        });
+        Ok(n + 1003)
+    }
+    pub(crate) fn pack_standard_call(call: &str) -> Result<u32, CallsignError> {
+        if call.is_empty() {
+            return Err(CallsignError::Empty);
+        }
+        if let Some(c) = call
+            .chars()
+            .find(|&c| !NONSTD_CHARSET.contains(c) || c == ' ')
+        {
+            return Err(CallsignError::InvalidCharacter(call.to_string(), c));
+        }
+        // The call area digit always sits in the third position
+        let padded = if call.as_bytes().get(1).is_some_and(|c| c.is_ascii_digit()) {
+            format!(" {:<5}", call)
+        } else {
+            format!("{:<6}", call)
+        };
+        let c: Vec<char> = padded.chars().collect();
+        let not_standard = || CallsignError::NotStandard(call.to_string());
+        if c.len() > 6 || c[3] == ' ' {
+            return Err(not_standard());
+        }
+        let mut n = ALPHANUMERIC_SPACE.find(c[0]).ok_or_else(not_standard)? as u32;
+        n = n * 36 + ALPHANUMERIC.find(c[1]).ok_or_else(not_standard)? as u32;
+        n = n * 10 + NUMERIC.find(c[2]).ok_or_else(not_standard)? as u32;
+        for &x in &c[3..] {
+            n = n * 27 + ALPHA_SPACE.find(x).ok_or_else(not_standard)? as u32;
+        }
+        Ok(n)
+    }
     pub fn num_to_str(n: u32) -> String {
         let mut s = String::new();
         let mut n = n;
@@ -99,13 +253,47 @@ impl Callsign28 {
         if n < 2063592 {
             return format!("CQ {}", Self::num_to_str(n - 1003));
         }
-        if n < 6257896 {
+        if n < STANDARD_CALL_BASE {
             return format!("<{}>", n - 2063592);
         }
-        Self::to_standard_call(n - 6257896)
+        Self::to_standard_call(n - STANDARD_CALL_BASE)
+    }
+}
+
+impl FromStr for Callsign28 {
+    type Err = CallsignError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let n = match s {
+            "DE" => 0,
+            "QRZ" => 1,
+            "CQ" => 2,
+            _ => match s.strip_prefix("CQ ") {
+                Some(modifier) => Self::pack_cq_modifier(modifier)?,
+                None => {
+                    let (call, suffix) = match s.split_once('/') {
+                        Some((call, "R")) => (call, Some('R')),
+                        Some((call, "P")) => (call, Some('P')),
+                        Some(_) => {
+                            // Compound callsigns such as PJ4/K1ABC are valid, just not here
+                            return Err(match s.parse::<Callsign58>() {
+                                Ok(_) => CallsignError::NotStandard(s.to_string()),
+                                Err(_) => CallsignError::InvalidSuffix(s.to_string()),
+                            });
+                        }
+                        None => (s, None),
+                    };
+                    let n = Self::pack_standard_call(call)?;
+                    return Ok(Self::from_u32(n + STANDARD_CALL_BASE, suffix));
+                }
+            },
+        };
+        Ok(Self::from_u32(n, None))
     }
 }
 
+#[cfg(feature = "serde")]
+crate::utils::serde_as_text!(Callsign28);
 impl Display for Callsign28 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(suffix) = self.suffix {
@@ -118,6 +306,7 @@ impl Display for Callsign28 {
 
 const NONSTD_CHARSET: &str = " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ/";
 
+/// A callsign of up to 11 characters sent in full.
 #[derive(Debug, Clone)]
 pub struct Callsign58 {
     pub c58: u64,
@@ -129,6 +318,9 @@ impl Callsign58 {
     pub fn from_u64(n: u64) -> Self {
         Self { c58: n }
     }
+    pub fn to_bits(&self) -> [bool; 58] {
+        u64_to_bool_slice(self.c58)
+    }
     pub fn to_call(&self) -> String {
         let mut c58 = self.c58;
         let mut text = String::new();
@@ -140,6 +332,27 @@ impl Callsign58 {
         text.trim().chars().rev().collect()
     }
 }
+impl FromStr for Callsign58 {
+    type Err = CallsignError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(CallsignError::Empty);
+        }
+        if let Some(c) = s.chars().find(|&c| !NONSTD_CHARSET.contains(c) || c == ' ') {
+            return Err(CallsignError::InvalidCharacter(s.to_string(), c));
+        }
+        if s.len() > 11 {
+            return Err(CallsignError::TooLong(s.to_string()));
+        }
+        let n = format!("{:<11}", s).chars().fold(0, |acc, c| {
+            acc * 38 + NONSTD_CHARSET.find(c).unwrap_or(0) as u64
+        });
+        Ok(Self::from_u64(n))
+    }
+}
+#[cfg(feature = "serde")]
+crate::utils::serde_as_text!(Callsign58);
 impl Display for Callsign58 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.to_call())
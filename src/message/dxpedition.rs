@@ -2,12 +2,14 @@ use std::fmt::Display;
 
 use crate::{
     callsignmap::CallsignMap,
-    utils::{bool_slice_to_u32, bool_slice_to_u8},
+    utils::{bit_field, bool_slice_to_u32, bool_slice_to_u8, u32_to_bool_slice, u8_to_bool_slice},
 };
 
 use super::{callsign::Callsign, hashedcallsign::HashedCallsign};
 
+/// RR73 to `callsign1` and a report to `callsign2`, sent by the DXpedition `hashed_callsign`.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dxpedition {
     pub callsign1: Callsign,
     pub callsign2: Callsign,
@@ -18,15 +20,43 @@ pub struct Dxpedition {
 impl Dxpedition {
     pub fn from_bits(message: &[bool; 77], callsign_map: &CallsignMap) -> Self {
         Self {
-            callsign1: Callsign::from_bits(&message[0..28].try_into().unwrap(), callsign_map),
-            callsign2: Callsign::from_bits(&message[28..56].try_into().unwrap(), callsign_map),
+            callsign1: Callsign::from_bits(bit_field(message, 0), callsign_map),
+            callsign2: Callsign::from_bits(bit_field(message, 28), callsign_map),
             hashed_callsign: HashedCallsign::from_hash(
-                bool_slice_to_u32::<10>(&message[56..66].try_into().unwrap()),
+                bool_slice_to_u32::<10>(bit_field(message, 56)),
                 callsign_map,
             ),
-            signal_strength: bool_slice_to_u8::<5>(&message[66..71].try_into().unwrap()),
+            signal_strength: bool_slice_to_u8::<5>(bit_field(message, 66)),
         }
     }
+    pub(crate) fn from_words(words: &[&str]) -> Option<Self> {
+        let [callsign1, "RR73;", callsign2, hashed_callsign, report] = *words else {
+            return None;
+        };
+        let report = report
+            .parse::<i8>()
+            .ok()
+            .filter(|report| (-30..=32).contains(report) && report % 2 == 0)?;
+        Some(Self {
+            callsign1: Callsign::parse_explicit(callsign1)
+                .ok()
+                .filter(|c| c.to_u32() >= 2063592)?,
+            callsign2: Callsign::parse_explicit(callsign2)
+                .ok()
+                .filter(|c| c.to_u32() >= 2063592)?,
+            hashed_callsign: hashed_callsign.parse().ok()?,
+            signal_strength: ((report + 30) / 2) as u8,
+        })
+    }
+    pub fn to_bits(&self) -> [bool; 77] {
+        let mut message = [false; 77];
+        message[0..28].copy_from_slice(&self.callsign1.to_bits());
+        message[28..56].copy_from_slice(&self.callsign2.to_bits());
+        message[56..66].copy_from_slice(&u32_to_bool_slice::<10>(self.hashed_callsign.hashed));
+        message[66..71].copy_from_slice(&u8_to_bool_slice::<5>(self.signal_strength));
+        message[71..74].copy_from_slice(&u8_to_bool_slice::<3>(1));
+        message
+    }
     pub fn callsigns(&self) -> Vec<String> {
         vec![
             self.callsign1.to_string(),
@@ -2,12 +2,18 @@ use std::fmt::Display;
 
 use crate::{
     callsignmap::CallsignMap,
-    utils::{bool_slice_to_u16, bool_slice_to_u32, bool_slice_to_u8},
+    utils::{
+        bit_field, bool_slice_to_u16, bool_slice_to_u32, bool_slice_to_u8, u16_to_bool_slice,
+        u32_to_bool_slice, u8_to_bool_slice,
+    },
+    Error,
 };
 
 use super::{grid::Grid6, hashedcallsign::HashedCallsign};
 
+/// EU VHF contest exchange with hashed callsigns and a 6 character locator.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct EUVHF {
     pub callsign1: HashedCallsign<12>,
     pub callsign2: HashedCallsign<22>,
@@ -17,21 +23,60 @@ pub struct EUVHF {
     pub grid: Grid6,
 }
 impl EUVHF {
-    pub fn from_bits(message: &[bool; 77], callsign_map: &CallsignMap) -> Self {
-        Self {
+    pub fn from_bits(message: &[bool; 77], callsign_map: &CallsignMap) -> Result<Self, Error> {
+        Ok(Self {
             callsign1: HashedCallsign::from_hash(
-                bool_slice_to_u32::<12>(&message[0..12].try_into().unwrap()),
+                bool_slice_to_u32::<12>(bit_field(message, 0)),
                 callsign_map,
             ),
             callsign2: HashedCallsign::from_hash(
-                bool_slice_to_u32::<22>(&message[12..34].try_into().unwrap()),
+                bool_slice_to_u32::<22>(bit_field(message, 12)),
                 callsign_map,
             ),
             has_r: message[34],
-            signal_strength: bool_slice_to_u8::<3>(&message[35..38].try_into().unwrap()),
-            serial_number: bool_slice_to_u16::<11>(&message[38..49].try_into().unwrap()),
-            grid: Grid6::from_bits(&message[49..74].try_into().unwrap()),
+            signal_strength: bool_slice_to_u8::<3>(bit_field(message, 35)),
+            serial_number: bool_slice_to_u16::<11>(bit_field(message, 38)),
+            grid: Grid6::from_bits(bit_field(message, 49))?,
+        })
+    }
+    pub(crate) fn from_words(words: &[&str]) -> Option<Self> {
+        let (callsign1, callsign2, has_r, exchange, grid) = match *words {
+            [callsign1, callsign2, exchange, grid] => (callsign1, callsign2, false, exchange, grid),
+            [callsign1, callsign2, "R", exchange, grid] => {
+                (callsign1, callsign2, true, exchange, grid)
+            }
+            _ => return None,
+        };
+        if !callsign1.starts_with('<') || !callsign2.starts_with('<') {
+            return None;
+        }
+        if exchange.len() != 6 || !exchange.chars().all(|c| c.is_ascii_digit()) {
+            return None;
         }
+        let report = exchange[..2]
+            .parse::<u8>()
+            .ok()
+            .filter(|r| (52..=59).contains(r))?;
+        let serial_number = exchange[2..].parse::<u16>().ok().filter(|&n| n < 2048)?;
+        Some(Self {
+            callsign1: callsign1.parse().ok()?,
+            callsign2: callsign2.parse().ok()?,
+            has_r,
+            signal_strength: report - 52,
+            serial_number,
+            grid: grid.parse().ok()?,
+        })
+    }
+    pub fn to_bits(&self) -> [bool; 77] {
+        let mut message = [false; 77];
+        message[0..12].copy_from_slice(&u32_to_bool_slice::<12>(self.callsign1.hashed));
+        message[12..34].copy_from_slice(&u32_to_bool_slice::<22>(self.callsign2.hashed));
+        message[34] = self.has_r;
+        message[35..38].copy_from_slice(&u8_to_bool_slice::<3>(self.signal_strength));
+        message[38..49].copy_from_slice(&u16_to_bool_slice::<11>(self.serial_number));
+        message[49..74].copy_from_slice(&self.grid.to_bits());
+        message[74..77].copy_from_slice(&u8_to_bool_slice::<3>(5));
+        message
     }
     pub fn callsigns(&self) -> Vec<String> {
         vec![self.callsign1.to_string(), self.callsign2.to_string()]
@@ -1,6 +1,10 @@
 use std::fmt::Display;
 
-use crate::{callsignmap::CallsignMap, utils::bool_slice_to_u8};
+use crate::{
+    callsignmap::CallsignMap,
+    utils::{bit_field, bool_slice_to_u8, u8_to_bool_slice},
+    Error,
+};
 
 use super::callsign::Callsign;
 
@@ -14,7 +18,9 @@ const RAC_SECTION: [&str; 84] = [
     "WPA", "WTX", "WV ", "WWA", "WY ", "DX ",
 ];
 
+/// ARRL Field Day exchange, `rac_section` indexes the ARRL/RAC section list from 0.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FieldDay {
     pub callsign1: Callsign,
     pub callsign2: Callsign,
@@ -28,38 +34,93 @@ impl FieldDay {
         message: &[bool; 77],
         transmitter_offset: u8,
         callsign_map: &CallsignMap,
-    ) -> Self {
-        let class = b'A' + bool_slice_to_u8::<3>(&message[61..64].try_into().unwrap());
-        Self {
-            callsign1: Callsign::from_bits(&message[0..28].try_into().unwrap(), callsign_map),
-            callsign2: Callsign::from_bits(&message[28..56].try_into().unwrap(), callsign_map),
+    ) -> Result<Self, Error> {
+        let class = b'A' + bool_slice_to_u8::<3>(bit_field(message, 61));
+        // Sections are sent 1 based, 0 and anything past the table are never sent
+        let section = bool_slice_to_u8::<7>(bit_field(message, 64));
+        let rac_section = section
+            .checked_sub(1)
+            .filter(|&n| (n as usize) < RAC_SECTION.len())
+            .ok_or(Error::InvalidField {
+                field: "RAC section",
+                value: section as u64,
+            })?;
+        let transmitters = bool_slice_to_u8::<4>(bit_field(message, 57));
+        Ok(Self {
+            callsign1: Callsign::from_bits(bit_field(message, 0), callsign_map),
+            callsign2: Callsign::from_bits(bit_field(message, 28), callsign_map),
             has_r: message[56],
-            transmitters: bool_slice_to_u8::<3>(&message[57..61].try_into().unwrap())
-                - transmitter_offset,
+            transmitters: transmitters.saturating_add(transmitter_offset),
             class: class as char,
-            rac_section: bool_slice_to_u8::<7>(&message[64..71].try_into().unwrap()),
-        }
+            rac_section,
+        })
+    }
+    pub(crate) fn from_words(words: &[&str]) -> Option<Self> {
+        let (callsign1, callsign2, has_r, class, section) = match *words {
+            [callsign1, callsign2, class, section] => (callsign1, callsign2, false, class, section),
+            [callsign1, callsign2, "R", class, section] => {
+                (callsign1, callsign2, true, class, section)
+            }
+            _ => return None,
+        };
+        let (transmitters, class) = class.split_at(class.len().saturating_sub(1));
+        let transmitters = transmitters
+            .parse::<u8>()
+            .ok()
+            .filter(|n| (1..=32).contains(n))?;
+        let class = class.chars().next().filter(|c| ('A'..='F').contains(c))?;
+        let rac_section = RAC_SECTION.iter().position(|&s| s.trim() == section)?;
+        Some(Self {
+            callsign1: Callsign::parse_explicit(callsign1)
+                .ok()
+                .filter(|c| c.to_u32() >= 2063592)?,
+            callsign2: Callsign::parse_explicit(callsign2)
+                .ok()
+                .filter(|c| c.to_u32() >= 2063592)?,
+            has_r,
+            transmitters,
+            class,
+            rac_section: rac_section as u8,
+        })
+    }
+    pub fn to_bits(&self) -> [bool; 77] {
+        let (n3, transmitter_offset) = if self.transmitters > 16 {
+            (4, 17)
+        } else {
+            (3, 1)
+        };
+        let mut message = [false; 77];
+        message[0..28].copy_from_slice(&self.callsign1.to_bits());
+        message[28..56].copy_from_slice(&self.callsign2.to_bits());
+        message[56] = self.has_r;
+        message[57..61].copy_from_slice(&u8_to_bool_slice::<4>(
+            self.transmitters.saturating_sub(transmitter_offset),
+        ));
+        message[61..64].copy_from_slice(&u8_to_bool_slice::<3>(
+            (self.class as u8).saturating_sub(b'A'),
+        ));
+        message[64..71].copy_from_slice(&u8_to_bool_slice::<7>(self.rac_section.saturating_add(1)));
+        message[71..74].copy_from_slice(&u8_to_bool_slice::<3>(n3));
+        message
     }
     pub fn callsigns(&self) -> Vec<String> {
         vec![self.callsign1.to_string(), self.callsign2.to_string()]
     }
+    /// Transmitters, class and section as sent, e.g. `3A WI`.
+    pub fn exchange(&self) -> String {
+        let rac_section = RAC_SECTION.get(self.rac_section as usize).unwrap_or(&"DX ");
+        format!("{}{} {}", self.transmitters, self.class, rac_section.trim())
+    }
 }
 impl Display for FieldDay {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let rac_section = if self.rac_section < 84 {
-            RAC_SECTION[self.rac_section as usize]
-        } else {
-            "DX "
-        };
         write!(
             f,
-            "{} {} {}{}{} {}",
+            "{} {} {}{}",
             self.callsign1.to_string(),
             self.callsign2.to_string(),
             if self.has_r { "R " } else { "" },
-            self.transmitters,
-            self.class,
-            rac_section.trim(),
+            self.exchange(),
         )
     }
 }
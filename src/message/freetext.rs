@@ -1,8 +1,10 @@
 use std::fmt::Display;
 
-use crate::utils::{bool_slice_to_u128, char_lookup};
+use crate::utils::{bit_field, bool_slice_to_u128, char_lookup, u128_to_bool_slice};
 
+/// Free text, right aligned to 13 characters.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FreeText {
     pub text: String,
 }
@@ -10,9 +12,25 @@ const FREETEXT_CHARSET: &str = " 0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ+-./?";
 impl FreeText {
     pub fn from_bits(message: &[bool; 77]) -> Self {
         Self {
-            text: Self::to_text(&message[..71].try_into().unwrap()),
+            text: Self::to_text(bit_field(message, 0)),
         }
     }
+    pub(crate) fn from_text(text: &str) -> Option<Self> {
+        if text.len() > 13 || !text.chars().all(|c| FREETEXT_CHARSET.contains(c)) {
+            return None;
+        }
+        Some(Self {
+            text: format!("{:>13}", text),
+        })
+    }
+    pub fn to_bits(&self) -> [bool; 77] {
+        let n = self.text.chars().fold(0u128, |acc, c| {
+            acc * 42 + FREETEXT_CHARSET.find(c).unwrap_or(0) as u128
+        });
+        let mut message = [false; 77];
+        message[..71].copy_from_slice(&u128_to_bool_slice::<71>(n));
+        message
+    }
     pub fn to_text(message: &[bool; 71]) -> String {
         let mut message = bool_slice_to_u128(message);
         let mut text = String::new();
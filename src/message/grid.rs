@@ -1,7 +1,41 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
-use crate::utils::{bool_slice_to_u16, bool_slice_to_u32};
+use crate::{
+    utils::{bool_slice_to_u16, bool_slice_to_u32, u16_to_bool_slice, u32_to_bool_slice},
+    Error,
+};
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GridError {
+    InvalidLocator(String),
+    InvalidReport(String),
+    ReportOutOfRange(i16),
+}
+impl Display for GridError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidLocator(text) => write!(f, "{} is not a valid locator", text),
+            Self::InvalidReport(text) => write!(f, "{} is not a valid grid or report", text),
+            Self::ReportOutOfRange(report) => {
+                write!(f, "signal report {} is outside -30 to +49", report)
+            }
+        }
+    }
+}
+impl std::error::Error for GridError {}
+
+/// What a [`Grid4`] holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Grid4Value {
+    Blank,
+    Locator(String),
+    Report(i16),
+    Roger,
+    RR73,
+    SeventyThree,
+}
+
+/// A 4 character locator, signal report or acknowledgement, see [`Grid4::value`].
 #[derive(Debug, Clone)]
 pub struct Grid4 {
     pub has_r: bool,
@@ -17,6 +51,53 @@ impl Grid4 {
             has_r: has_r,
         }
     }
+    fn pack_locator(text: &str) -> Result<u16, GridError> {
+        let c = text.as_bytes();
+        let valid = c.len() == 4
+            && (b'A'..=b'R').contains(&c[0])
+            && (b'A'..=b'R').contains(&c[1])
+            && c[2].is_ascii_digit()
+            && c[3].is_ascii_digit();
+        if !valid {
+            return Err(GridError::InvalidLocator(text.to_string()));
+        }
+        let n = (c[0] - b'A') as u16 * 18 + (c[1] - b'A') as u16;
+        let n = n * 10 + (c[2] - b'0') as u16;
+        Ok(n * 10 + (c[3] - b'0') as u16)
+    }
+    fn pack_report(text: &str) -> Result<u16, GridError> {
+        if !text.starts_with(['+', '-']) || text.len() < 2 {
+            return Err(GridError::InvalidReport(text.to_string()));
+        }
+        let report = text
+            .parse::<i16>()
+            .map_err(|_| GridError::InvalidReport(text.to_string()))?;
+        if !(-30..=49).contains(&report) {
+            return Err(GridError::ReportOutOfRange(report));
+        }
+        Ok((32435 + report) as u16)
+    }
+    fn pack_grid_or_report(text: &str) -> Result<u16, GridError> {
+        if text.starts_with(['+', '-']) {
+            Self::pack_report(text)
+        } else {
+            Self::pack_locator(text)
+        }
+    }
+    pub fn value(&self) -> Grid4Value {
+        match self.grid {
+            n if n < 32400 => Grid4Value::Locator(Self::to_grid(n)),
+            32401 => Grid4Value::Blank,
+            32402 => Grid4Value::Roger,
+            32403 => Grid4Value::RR73,
+            32404 => Grid4Value::SeventyThree,
+            n if n > 32404 => Grid4Value::Report(n as i16 - 32435),
+            _ => Grid4Value::Blank,
+        }
+    }
+    pub fn to_bits(&self) -> [bool; 15] {
+        u16_to_bool_slice(self.grid)
+    }
     fn to_grid(n: u16) -> String {
         let mut n = n;
         let mut grid = String::new();
@@ -50,6 +131,31 @@ impl Grid4 {
         return format!("+{:02}", n - 35);
     }
 }
+impl FromStr for Grid4 {
+    type Err = GridError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let n = match s {
+            "" => 32401,
+            "RRR" => 32402,
+            "RR73" => 32403,
+            "73" => 32404,
+            _ => match Self::pack_grid_or_report(s) {
+                Ok(n) => n,
+                // Locators in field R also start with R, so only strip it after they fail
+                Err(err) => match s.strip_prefix('R') {
+                    Some(rest) if !rest.is_empty() => {
+                        return Self::pack_grid_or_report(rest).map(|n| Self::from_u16(n, true));
+                    }
+                    _ => return Err(err),
+                },
+            },
+        };
+        Ok(Self::from_u16(n, false))
+    }
+}
+#[cfg(feature = "serde")]
+crate::utils::serde_as_text!(Grid4);
 impl Display for Grid4 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.has_r {
@@ -60,17 +166,30 @@ impl Display for Grid4 {
     }
 }
 
+// Number of 6 character locators, 25 bit values from here up are never sent
+const GRID6_COUNT: u32 = 18 * 18 * 10 * 10 * 24 * 24;
+
+/// A 6 character locator.
 #[derive(Debug, Clone)]
 pub struct Grid6 {
     pub grid: u32,
 }
 impl Grid6 {
-    pub fn from_bits(message: &[bool; 25]) -> Self {
-        Self::from_u32(bool_slice_to_u32(message))
+    pub fn from_bits(message: &[bool; 25]) -> Result<Self, Error> {
+        match bool_slice_to_u32(message) {
+            n if n < GRID6_COUNT => Ok(Self::from_u32(n)),
+            n => Err(Error::InvalidField {
+                field: "6 character grid",
+                value: n as u64,
+            }),
+        }
     }
     pub fn from_u32(n: u32) -> Self {
         Self { grid: n }
     }
+    pub fn to_bits(&self) -> [bool; 25] {
+        u32_to_bool_slice(self.grid)
+    }
     fn to_grid(n: u32) -> String {
         let mut n = n;
         let mut grid = String::new();
@@ -88,6 +207,30 @@ impl Grid6 {
         return grid.chars().rev().collect();
     }
 }
+impl FromStr for Grid6 {
+    type Err = GridError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let c = s.as_bytes();
+        let valid = c.len() == 6
+            && (b'A'..=b'R').contains(&c[0])
+            && (b'A'..=b'R').contains(&c[1])
+            && c[2].is_ascii_digit()
+            && c[3].is_ascii_digit()
+            && (b'A'..=b'X').contains(&c[4])
+            && (b'A'..=b'X').contains(&c[5]);
+        if !valid {
+            return Err(GridError::InvalidLocator(s.to_string()));
+        }
+        let n = (c[0] - b'A') as u32 * 18 + (c[1] - b'A') as u32;
+        let n = n * 10 + (c[2] - b'0') as u32;
+        let n = n * 10 + (c[3] - b'0') as u32;
+        let n = n * 24 + (c[4] - b'A') as u32;
+        Ok(Self::from_u32(n * 24 + (c[5] - b'A') as u32))
+    }
+}
+#[cfg(feature = "serde")]
+crate::utils::serde_as_text!(Grid6);
 impl Display for Grid6 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", Grid6::to_grid(self.grid))
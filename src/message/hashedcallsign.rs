@@ -1,7 +1,10 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
 use crate::callsignmap::CallsignMap;
 
+use super::callsign::{Callsign58, CallsignError};
+
+/// A callsign sent as a `SIZE` bit hash, `callsign` is set when the hash was resolved.
 #[derive(Debug, Clone)]
 pub struct HashedCallsign<const SIZE: usize> {
     pub callsign: Option<String>,
@@ -21,7 +24,39 @@ impl<const SIZE: usize> HashedCallsign<SIZE> {
             hashed: hash,
         }
     }
+    pub fn from_callsign(callsign: &str) -> Self {
+        let hash = CallsignMap::hash(&callsign.to_string());
+        Self {
+            callsign: Some(callsign.to_string()),
+            hashed: hash >> (22 - SIZE),
+        }
+    }
+}
+impl<const SIZE: usize> FromStr for HashedCallsign<SIZE> {
+    type Err = CallsignError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let inner = s.strip_prefix('<').unwrap_or(s);
+        let inner = inner.strip_suffix('>').unwrap_or(inner);
+        if !inner.is_empty() && inner.chars().all(|c| c.is_ascii_digit()) {
+            // Unresolved hashes are displayed as their numeric value
+            let hash = inner
+                .parse::<u32>()
+                .map_err(|_| CallsignError::HashOutOfRange(u32::MAX))?;
+            if hash >= 1 << SIZE {
+                return Err(CallsignError::HashOutOfRange(hash));
+            }
+            return Ok(Self {
+                callsign: None,
+                hashed: hash,
+            });
+        }
+        inner.parse::<Callsign58>()?;
+        Ok(Self::from_callsign(inner))
+    }
 }
+#[cfg(feature = "serde")]
+crate::utils::serde_as_text!(HashedCallsign<SIZE>, const SIZE);
 impl<const SIZE: usize> Display for HashedCallsign<SIZE> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if let Some(callsign) = &self.callsign {
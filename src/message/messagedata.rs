@@ -1,50 +1,162 @@
 use std::fmt::Display;
 
-use crate::{callsignmap::CallsignMap, utils::bool_slice_to_u8};
+use crate::{
+    callsignmap::CallsignMap,
+    jt65::Jt65Message,
+    utils::{bit_field, bool_slice_to_u8},
+    Error,
+};
 
 use super::{
-    dxpedition::Dxpedition, euvhf::EUVHF, fieldday::FieldDay, freetext::FreeText,
+    dxpedition::Dxpedition, euvhf::EUVHF, fieldday::FieldDay, freetext::FreeText, grid::Grid4Value,
     nonstdcall::NonStdCall, rttyru::RTTYRU, standard::Standard, telemetry::Telemetry,
     unknown::Unknown,
 };
 
+/// An unpacked payload, one variant per message type. Every type but JT65 is a 77 bit
+/// payload.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type"))]
 pub enum MessageData {
+    /// Up to 13 characters of free text.
     FreeText(FreeText),
+    /// DXpedition mode, one RR73 and one report in a single transmission.
     Dxpedition(Dxpedition),
+    /// ARRL Field Day exchange.
     FieldDay(FieldDay),
+    /// 71 bits of arbitrary data shown as hex.
     Telemetry(Telemetry),
+    /// Standard message, callsigns may carry /R.
     StandardR(Standard<'R'>),
+    /// Standard message, callsigns may carry /P.
     StandardP(Standard<'P'>),
+    /// ARRL RTTY Roundup exchange.
     RTTYRU(RTTYRU),
+    /// A callsign that does not fit the standard format, paired with a hashed one.
     NonStdCall(NonStdCall),
+    /// EU VHF contest exchange.
     EUVHF(EUVHF),
+    /// A message type this crate cannot unpack, holding the raw bits.
     Unknown(Unknown),
+    /// A JT65 message, with a 72 bit payload of its own.
+    Jt65(Jt65Message),
+}
+
+/// The type of a [`MessageData`], without its fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MessageKind {
+    FreeText,
+    Dxpedition,
+    FieldDay,
+    Telemetry,
+    StandardR,
+    StandardP,
+    RTTYRU,
+    NonStdCall,
+    EUVHF,
+    Unknown,
+    Jt65,
+}
+impl MessageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageKind::FreeText => "FreeText",
+            MessageKind::Dxpedition => "Dxpedition",
+            MessageKind::FieldDay => "FieldDay",
+            MessageKind::Telemetry => "Telemetry",
+            MessageKind::StandardR => "StandardR",
+            MessageKind::StandardP => "StandardP",
+            MessageKind::RTTYRU => "RTTYRU",
+            MessageKind::NonStdCall => "NonStdCall",
+            MessageKind::EUVHF => "EUVHF",
+            MessageKind::Unknown => "Unknown",
+            MessageKind::Jt65 => "Jt65",
+        }
+    }
+}
+impl Display for MessageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
 }
 
 impl MessageData {
-    pub fn from_bits(message: &[bool; 77], callsign_map: &CallsignMap) -> MessageData {
-        let i3 = bool_slice_to_u8::<3>(&message[74..77].try_into().unwrap());
-        match i3 {
+    /// Unpacks a 77 bit payload, hashed callsigns are looked up in `callsign_map`.
+    pub fn from_bits(
+        message: &[bool; 77],
+        callsign_map: &CallsignMap,
+    ) -> Result<MessageData, Error> {
+        let i3 = bool_slice_to_u8::<3>(bit_field(message, 74));
+        let data = match i3 {
             0 => {
-                let n3 = bool_slice_to_u8::<3>(&message[71..74].try_into().unwrap());
+                let n3 = bool_slice_to_u8::<3>(bit_field(message, 71));
                 match n3 {
                     0 => MessageData::FreeText(FreeText::from_bits(message)),
                     1 => MessageData::Dxpedition(Dxpedition::from_bits(message, callsign_map)),
-                    3 => MessageData::FieldDay(FieldDay::from_bits(message, 1, callsign_map)),
-                    4 => MessageData::FieldDay(FieldDay::from_bits(message, 17, callsign_map)),
+                    3 => MessageData::FieldDay(FieldDay::from_bits(message, 1, callsign_map)?),
+                    4 => MessageData::FieldDay(FieldDay::from_bits(message, 17, callsign_map)?),
                     5 => MessageData::Telemetry(Telemetry::from_bits(message)),
                     _ => MessageData::Unknown(Unknown::from_bits(message)),
                 }
             }
             1 => MessageData::StandardR(Standard::from_bits(message, callsign_map)),
             2 => MessageData::StandardP(Standard::from_bits(message, callsign_map)),
-            3 => MessageData::RTTYRU(RTTYRU::from_bits(message, callsign_map)),
+            3 => MessageData::RTTYRU(RTTYRU::from_bits(message, callsign_map)?),
             4 => MessageData::NonStdCall(NonStdCall::from_bits(message, callsign_map)),
-            5 => MessageData::EUVHF(EUVHF::from_bits(message, callsign_map)),
+            5 => MessageData::EUVHF(EUVHF::from_bits(message, callsign_map)?),
             _ => MessageData::Unknown(Unknown::from_bits(message)),
+        };
+        Ok(data)
+    }
+    /// Packs message text the way WSJT-X would, `None` if no message type can hold it.
+    pub fn from_text(text: &str) -> Option<MessageData> {
+        let text = text.trim().to_uppercase();
+        let mut words: Vec<&str> = text.split_whitespace().collect();
+        // "CQ DX" and "CQ 123" are packed together as the first callsign
+        let cq = match words[..] {
+            ["CQ", modifier, _, ..] if Self::is_cq_modifier(modifier) => {
+                Some(format!("CQ {}", words.remove(1)))
+            }
+            _ => None,
+        };
+        if let Some(cq) = &cq {
+            words[0] = cq;
         }
+        Dxpedition::from_words(&words)
+            .map(MessageData::Dxpedition)
+            .or_else(|| FieldDay::from_words(&words).map(MessageData::FieldDay))
+            .or_else(|| RTTYRU::from_words(&words).map(MessageData::RTTYRU))
+            .or_else(|| EUVHF::from_words(&words).map(MessageData::EUVHF))
+            .or_else(|| Standard::from_words(&words).map(MessageData::StandardR))
+            .or_else(|| Standard::from_words(&words).map(MessageData::StandardP))
+            .or_else(|| NonStdCall::from_words(&words).map(MessageData::NonStdCall))
+            .or_else(|| Telemetry::from_words(&words).map(MessageData::Telemetry))
+            .or_else(|| FreeText::from_text(&text).map(MessageData::FreeText))
     }
+    fn is_cq_modifier(word: &str) -> bool {
+        (word.len() == 3 && word.chars().all(|c| c.is_ascii_digit()))
+            || ((1..=4).contains(&word.len()) && word.chars().all(|c| c.is_ascii_uppercase()))
+    }
+    /// Packs the message into its 77 bit payload, `None` for JT65 messages.
+    pub fn to_bits(&self) -> Option<[bool; 77]> {
+        let bits = match self {
+            MessageData::FreeText(m) => m.to_bits(),
+            MessageData::Dxpedition(m) => m.to_bits(),
+            MessageData::FieldDay(m) => m.to_bits(),
+            MessageData::Telemetry(m) => m.to_bits(),
+            MessageData::StandardR(m) => m.to_bits(),
+            MessageData::StandardP(m) => m.to_bits(),
+            MessageData::RTTYRU(m) => m.to_bits(),
+            MessageData::NonStdCall(m) => m.to_bits(),
+            MessageData::EUVHF(m) => m.to_bits(),
+            MessageData::Unknown(m) => m.to_bits(),
+            MessageData::Jt65(_) => return None,
+        };
+        Some(bits)
+    }
+    /// Callsigns in the message, hashes that could not be resolved are shown as `<n>`.
     pub fn callsigns(&self) -> Vec<String> {
         match self {
             MessageData::Dxpedition(m) => m.callsigns(),
@@ -54,23 +166,57 @@ impl MessageData {
             MessageData::RTTYRU(m) => m.callsigns(),
             MessageData::NonStdCall(m) => m.callsigns(),
             MessageData::EUVHF(m) => m.callsigns(),
+            MessageData::Jt65(m) => m.callsigns(),
             _ => vec![],
         }
     }
-    pub fn message_type_as_string(&self) -> &str {
+    /// 4 or 6 character Maidenhead locator, for the message types that carry one.
+    pub fn grid(&self) -> Option<String> {
         match self {
-            MessageData::FreeText(_) => "FreeText",
-            MessageData::Dxpedition(_) => "Dxpedition",
-            MessageData::FieldDay(_) => "FieldDay",
-            MessageData::Telemetry(_) => "Telemetry",
-            MessageData::StandardR(_) => "StandardR",
-            MessageData::StandardP(_) => "StandardP",
-            MessageData::RTTYRU(_) => "RTTYRU",
-            MessageData::NonStdCall(_) => "NonStdCall",
-            MessageData::EUVHF(_) => "EUVHF",
-            MessageData::Unknown(_) => "Unknown",
+            MessageData::StandardR(Standard { grid, .. })
+            | MessageData::StandardP(Standard { grid, .. }) => match grid.value() {
+                Grid4Value::Locator(locator) => Some(locator),
+                _ => None,
+            },
+            MessageData::EUVHF(m) => Some(m.grid.to_string()),
+            MessageData::Jt65(m) => m.grid().map(str::to_string),
+            _ => None,
         }
     }
+    /// Signal report as sent: dB for standard and DXpedition messages, RST for the
+    /// RTTY Roundup and RS for EU VHF contest messages.
+    pub fn report(&self) -> Option<i16> {
+        match self {
+            MessageData::StandardR(Standard { grid, .. })
+            | MessageData::StandardP(Standard { grid, .. }) => match grid.value() {
+                Grid4Value::Report(report) => Some(report),
+                _ => None,
+            },
+            MessageData::Dxpedition(m) => Some(m.signal_strength as i16 * 2 - 30),
+            MessageData::RTTYRU(m) => Some(m.signal_strength as i16 * 10 + 529),
+            MessageData::EUVHF(m) => Some(m.signal_strength as i16 + 52),
+            MessageData::Jt65(m) => m.report(),
+            _ => None,
+        }
+    }
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            MessageData::FreeText(_) => MessageKind::FreeText,
+            MessageData::Dxpedition(_) => MessageKind::Dxpedition,
+            MessageData::FieldDay(_) => MessageKind::FieldDay,
+            MessageData::Telemetry(_) => MessageKind::Telemetry,
+            MessageData::StandardR(_) => MessageKind::StandardR,
+            MessageData::StandardP(_) => MessageKind::StandardP,
+            MessageData::RTTYRU(_) => MessageKind::RTTYRU,
+            MessageData::NonStdCall(_) => MessageKind::NonStdCall,
+            MessageData::EUVHF(_) => MessageKind::EUVHF,
+            MessageData::Unknown(_) => MessageKind::Unknown,
+            MessageData::Jt65(_) => MessageKind::Jt65,
+        }
+    }
+    pub fn message_type_as_string(&self) -> &str {
+        self.kind().as_str()
+    }
 }
 impl Display for MessageData {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -85,6 +231,7 @@ impl Display for MessageData {
             MessageData::NonStdCall(m) => write!(f, "{}", m.to_string()),
             MessageData::EUVHF(m) => write!(f, "{}", m.to_string()),
             MessageData::Unknown(m) => write!(f, "{}", m.to_string()),
+            MessageData::Jt65(m) => write!(f, "{}", m),
         }
     }
 }
@@ -97,17 +244,43 @@ const STATES_AND_PROVINCES: [&str; 65] = [
     "QC ", "ON ", "MB ", "SK ", "AB ", "BC ", "NWT", "NF ", "LB ", "NU ", "YT ", "PEI", "DC ",
 ];
 
+/// Serial number or US state and Canadian province of an RTTY Roundup exchange.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum S13 {
     SerialNumber(u16),
     StatesAndProvinces(u8),
 }
 impl S13 {
-    pub fn new(n: u16) -> Self {
-        if n < 8001 {
-            Self::SerialNumber(n)
-        } else {
-            Self::StatesAndProvinces((n - 8001) as u8)
+    pub fn new(n: u16) -> Result<Self, Error> {
+        match n {
+            0..8001 => Ok(Self::SerialNumber(n)),
+            _ if ((n - 8001) as usize) < STATES_AND_PROVINCES.len() => {
+                Ok(Self::StatesAndProvinces((n - 8001) as u8))
+            }
+            _ => Err(Error::InvalidField {
+                field: "state or province",
+                value: n as u64,
+            }),
+        }
+    }
+    pub(crate) fn parse(text: &str) -> Option<Self> {
+        if text.chars().all(|c| c.is_ascii_digit()) {
+            return text
+                .parse::<u16>()
+                .ok()
+                .filter(|n| (1..8001).contains(n))
+                .map(Self::SerialNumber);
+        }
+        STATES_AND_PROVINCES
+            .iter()
+            .position(|&s| s.trim() == text)
+            .map(|n| Self::StatesAndProvinces(n as u8))
+    }
+    pub fn to_u16(&self) -> u16 {
+        match self {
+            Self::SerialNumber(n) => *n,
+            Self::StatesAndProvinces(n) => *n as u16 + 8001,
         }
     }
 }
@@ -115,13 +288,10 @@ impl Display for S13 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::SerialNumber(n) => write!(f, "{:04}", n),
-            Self::StatesAndProvinces(n) => {
-                if *n < 65 {
-                    write!(f, "{}", STATES_AND_PROVINCES[*n as usize].to_owned())
-                } else {
-                    write!(f, "")
-                }
-            }
+            Self::StatesAndProvinces(n) => match STATES_AND_PROVINCES.get(*n as usize) {
+                Some(state) => write!(f, "{}", state),
+                None => write!(f, ""),
+            },
         }
     }
 }
@@ -1,15 +1,36 @@
-use std::{fmt::Display, collections::HashMap};
+//! Decoded messages of every mode.
+//!
+//! A [`Message`] pairs the unpacked payload, [`MessageData`], with what the decoder
+//! measured about the signal carrying it. The FT8, FT4 and JT65 decoders all return
+//! [`Message`]s. Each [`MessageData`] variant holds a typed struct with the fields of
+//! that message type, so callers can match on it instead of parsing the text form.
 
-use crate::callsignmap::CallsignMap;
+use std::{collections::HashMap, fmt::Display};
 
-use self::messagedata::MessageData;
+use crate::{callsignmap::CallsignMap, ft8::ApPass, Error};
 
-mod callsign;
+pub use self::{
+    callsign::{Callsign, Callsign28, Callsign58, CallsignError},
+    dxpedition::Dxpedition,
+    euvhf::EUVHF,
+    fieldday::FieldDay,
+    freetext::FreeText,
+    grid::{Grid4, Grid4Value, Grid6, GridError},
+    hashedcallsign::HashedCallsign,
+    messagedata::{MessageData, MessageKind, S13},
+    nonstdcall::NonStdCall,
+    rttyru::RTTYRU,
+    standard::Standard,
+    telemetry::Telemetry,
+    unknown::Unknown,
+};
+
+pub(crate) mod callsign;
 mod dxpedition;
 mod euvhf;
 mod fieldday;
 mod freetext;
-mod grid;
+pub(crate) mod grid;
 mod hashedcallsign;
 mod messagedata;
 mod nonstdcall;
@@ -18,33 +39,89 @@ mod standard;
 mod telemetry;
 mod unknown;
 
+// WSJT-X reports DT relative to a transmission starting this far into the cycle,
+// FT8 and FT4 start here and the slower modes later
+const NOMINAL_START_MS: i64 = 500;
+
+#[cfg(feature = "serde")]
+fn default_nominal_start_ms() -> i64 {
+    NOMINAL_START_MS
+}
+
+/// A message decoded from one signal.
+///
+/// The fields public since the first release stay public, everything else is read
+/// through methods.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Message {
-    snr: f32,
+    pub(crate) snr: f32,
+    /// Audio frequency of the lowest tone in Hz.
+    #[cfg_attr(feature = "serde", serde(rename = "freq_hz"))]
     pub freq_bin_hz: u32,
+    /// Start of the signal in milliseconds from the first sample given to the decoder.
     pub time_offset_ms: i64,
+    /// The unpacked payload.
     pub data: MessageData,
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_bits"))]
+    pub(crate) bits: Vec<bool>,
+    /// The a priori pass that decoded the message, if known bits had to be assumed.
+    pub ap_pass: Option<ApPass>,
+    #[cfg_attr(feature = "serde", serde(default = "default_nominal_start_ms"))]
+    pub(crate) nominal_start_ms: i64,
 }
 
 impl Message {
+    /// Unpacks a 77 bit payload received at the given SNR, audio frequency and time offset.
     pub fn from_bits(
         snr: f32,
         freq_bin_hz: u32,
         time_offset_ms: i64,
         message: &[bool; 77],
         callsign_map: &CallsignMap,
-    ) -> Message {
-        Message {
+    ) -> Result<Message, Error> {
+        Ok(Message {
             snr,
             freq_bin_hz,
             time_offset_ms,
-            data: MessageData::from_bits(message, callsign_map),
-        }
+            data: MessageData::from_bits(message, callsign_map)?,
+            bits: message.to_vec(),
+            ap_pass: None,
+            nominal_start_ms: NOMINAL_START_MS,
+        })
+    }
+    /// Signal to noise ratio in dB.
+    pub fn snr(&self) -> f32 {
+        self.snr
+    }
+    /// Time offset in seconds from the nominal start of the mode's transmissions, as
+    /// WSJT-X reports DT: 0.5 s into the cycle for FT8 and FT4, 1 s for JT65. Only
+    /// meaningful when the decoded audio starts on a cycle boundary.
+    pub fn dt_seconds(&self) -> f32 {
+        (self.time_offset_ms - self.nominal_start_ms) as f32 / 1000.0
+    }
+    /// The message type, the same as `self.data.kind()`.
+    pub fn kind(&self) -> MessageKind {
+        self.data.kind()
+    }
+    /// The payload as received, most significant bit first: 77 bits, or 72 for JT65.
+    pub fn bits(&self) -> &[bool] {
+        &self.bits
     }
+    /// Callsigns in the message, hashes that could not be resolved are shown as `<n>`.
     pub fn callsigns(&self) -> Vec<String> {
         self.data.callsigns()
     }
+    /// Locator in the message, if any. See [`MessageData::grid`].
+    pub fn grid(&self) -> Option<String> {
+        self.data.grid()
+    }
+    /// Signal report in the message, if any. See [`MessageData::report`].
+    pub fn report(&self) -> Option<i16> {
+        self.data.report()
+    }
 
+    /// Keeps the strongest copy of every distinct message.
     pub fn deduplicate_signals(messages: Vec<Message>) -> Vec<Message> {
         let mut map = HashMap::new();
         messages.iter().for_each(|message| {
@@ -68,18 +145,17 @@ impl Display for Message {
             self.time_offset_ms as f32 / 1000.0,
             self.freq_bin_hz,
             self.data.to_string()
-        )
+        )?;
+        match self.ap_pass {
+            Some(pass) => write!(f, " {}", pass),
+            None => Ok(()),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::message::{
-        callsign::Callsign58,
-        grid::{Grid4, Grid6}, freetext::FreeText,
-    };
-
-    use super::{callsign::Callsign, *};
+    use super::*;
 
     #[test]
     fn callsign28() {
@@ -154,6 +230,291 @@ mod tests {
         assert_eq!(freetext.to_string(), "TNX BOB 73 GL");
     }
 
+    fn pack_roundtrip(text: &str) -> String {
+        let mut callsign_map = CallsignMap::new();
+        ["W9XYZ", "KH1/KH7Z", "PA9XYZ", "G4ABC/P"]
+            .iter()
+            .for_each(|c| {
+                callsign_map.insert(&c.to_string());
+            });
+        let data = MessageData::from_text(text).unwrap();
+        MessageData::from_bits(&data.to_bits().unwrap(), &callsign_map)
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn pack_callsign28() {
+        let callsign = "K1ABC".parse::<Callsign>().unwrap();
+        assert_eq!(callsign.to_u32(), 10214965);
+    }
+
+    #[test]
+    fn pack_callsign58() {
+        let callsign = "PJ4/K1ABC".parse::<Callsign58>().unwrap();
+        assert_eq!(callsign.c58, 166563865821947300);
+        let callsign = "YW18FIFA".parse::<Callsign58>().unwrap();
+        assert_eq!(callsign.c58, 225199321060198248);
+        assert_eq!(
+            "YW18FIFA/ABCD".parse::<Callsign58>().unwrap_err(),
+            CallsignError::TooLong("YW18FIFA/ABCD".to_string())
+        );
+    }
+
+    #[test]
+    fn callsign28_from_str() {
+        let call = |s: &str| s.parse::<Callsign28>().map(|c| c.to_string());
+        assert_eq!(call("DE").unwrap(), "DE");
+        assert_eq!(call("QRZ").unwrap(), "QRZ");
+        assert_eq!(call("CQ").unwrap(), "CQ");
+        assert_eq!(call("CQ 145").unwrap(), "CQ 145");
+        assert_eq!(call("CQ TEST").unwrap(), "CQ TEST");
+        assert_eq!(call("9V1AA").unwrap(), "9V1AA");
+        assert_eq!(call("K1ABC/R").unwrap(), "K1ABC/R");
+        assert_eq!(call("W9XYZ/P").unwrap(), "W9XYZ/P");
+        assert_eq!(
+            call("CQ DX5"),
+            Err(CallsignError::InvalidCqModifier("DX5".to_string()))
+        );
+        assert_eq!(
+            call("K1ABC/Q"),
+            Err(CallsignError::NotStandard("K1ABC/Q".to_string()))
+        );
+        assert_eq!(
+            call("PJ4/K1ABC"),
+            Err(CallsignError::NotStandard("PJ4/K1ABC".to_string()))
+        );
+        assert_eq!(
+            call("K1A#C"),
+            Err(CallsignError::InvalidCharacter("K1A#C".to_string(), '#'))
+        );
+        assert_eq!(call(""), Err(CallsignError::Empty));
+    }
+
+    #[test]
+    fn callsign_hash_fallback() {
+        let callsign = "PJ4/K1ABC".parse::<Callsign>().unwrap();
+        assert!(matches!(callsign, Callsign::H22(_)));
+        assert_eq!(callsign.to_u32(), 2063592 + 1420834);
+        assert_eq!(callsign.to_string(), "PJ4/K1ABC");
+        let callsign = "<W9XYZ>".parse::<Callsign>().unwrap();
+        assert!(matches!(callsign, Callsign::H22(_)));
+        assert!(matches!("W9XYZ".parse::<Callsign>(), Ok(Callsign::C28(_))));
+    }
+
+    #[test]
+    fn pack_grid4() {
+        assert_eq!("FN20".parse::<Grid4>().unwrap().grid, 10320);
+        assert_eq!("-11".parse::<Grid4>().unwrap().grid, 32424);
+        assert_eq!("RRR".parse::<Grid4>().unwrap().grid, 32402);
+    }
+
+    #[test]
+    fn grid4_from_str() {
+        let grid = |s: &str| s.parse::<Grid4>().map(|g| (g.has_r, g.value()));
+        let locator = |s: &str| Grid4Value::Locator(s.to_string());
+        assert_eq!(grid("FN42"), Ok((false, locator("FN42"))));
+        assert_eq!(grid("RFN42"), Ok((true, locator("FN42"))));
+        assert_eq!(grid("RA12"), Ok((false, locator("RA12"))));
+        assert_eq!(grid("-12"), Ok((false, Grid4Value::Report(-12))));
+        assert_eq!(grid("R+05"), Ok((true, Grid4Value::Report(5))));
+        assert_eq!(grid("RRR"), Ok((false, Grid4Value::Roger)));
+        assert_eq!(grid("RR73"), Ok((false, Grid4Value::RR73)));
+        assert_eq!(grid("73"), Ok((false, Grid4Value::SeventyThree)));
+        assert_eq!(grid(""), Ok((false, Grid4Value::Blank)));
+        assert_eq!(
+            grid("R+05").unwrap().1,
+            Grid4::from_u16(32440, true).value()
+        );
+        assert_eq!(
+            grid("SN42"),
+            Err(GridError::InvalidLocator("SN42".to_string()))
+        );
+        assert_eq!(grid("-31"), Err(GridError::ReportOutOfRange(-31)));
+        assert_eq!(grid("R+50"), Err(GridError::ReportOutOfRange(50)));
+        assert_eq!(
+            grid("+5X"),
+            Err(GridError::InvalidReport("+5X".to_string()))
+        );
+    }
+
+    #[test]
+    fn grid6_from_str() {
+        assert_eq!("IO91NP".parse::<Grid6>().unwrap().grid, 9153543);
+        assert_eq!(
+            "IO91NZ".parse::<Grid6>().unwrap_err(),
+            GridError::InvalidLocator("IO91NZ".to_string())
+        );
+    }
+
+    #[test]
+    fn pack_standard() {
+        let data = MessageData::from_text("CQ K1ABC FN42").unwrap();
+        assert!(matches!(data, MessageData::StandardR(_)));
+        assert_eq!(pack_roundtrip("CQ K1ABC FN42"), "CQ K1ABC FN42");
+        assert_eq!(pack_roundtrip("K1ABC W9XYZ R-12"), "K1ABC W9XYZ R-12");
+        assert_eq!(pack_roundtrip("CQ DX K1ABC/R FN42"), "CQ DX K1ABC/R FN42");
+        assert_eq!(pack_roundtrip("K1ABC/P W9XYZ RR73"), "K1ABC/P W9XYZ RR73");
+        assert_eq!(pack_roundtrip("W9XYZ <PA9XYZ> 73"), "W9XYZ PA9XYZ 73");
+    }
+
+    #[test]
+    fn pack_nonstdcall() {
+        let data = MessageData::from_text("PJ4/K1ABC <W9XYZ> RR73").unwrap();
+        assert!(matches!(data, MessageData::NonStdCall(_)));
+        assert_eq!(
+            pack_roundtrip("PJ4/K1ABC <W9XYZ> RR73"),
+            "PJ4/K1ABC W9XYZ RR73"
+        );
+        assert_eq!(pack_roundtrip("<W9XYZ> YW18FIFA"), "W9XYZ YW18FIFA ");
+    }
+
+    #[test]
+    fn pack_freetext() {
+        let data = MessageData::from_text("TNX BOB 73 GL").unwrap();
+        assert!(matches!(data, MessageData::FreeText(_)));
+        let bits = data.to_bits().unwrap();
+        let freetext = FreeText::from_bits(&bits);
+        assert_eq!(freetext.to_string(), "TNX BOB 73 GL");
+        assert_eq!(pack_roundtrip("hello"), "        HELLO");
+    }
+
+    #[test]
+    fn pack_fieldday() {
+        let data = MessageData::from_text("K1ABC W9XYZ 6A WI").unwrap();
+        assert!(matches!(data, MessageData::FieldDay(_)));
+        assert_eq!(data.to_string(), "K1ABC W9XYZ 6A WI");
+        let data = MessageData::from_text("K1ABC W9XYZ R 17B EMA").unwrap();
+        assert_eq!(data.to_string(), "K1ABC W9XYZ R 17B EMA");
+    }
+
+    #[test]
+    fn fieldday_section_is_one_based() {
+        let mut bits = MessageData::from_text("K1ABC W9XYZ 6A WI")
+            .unwrap()
+            .to_bits()
+            .unwrap();
+        let section = bits[64..71].iter().fold(0, |acc, &x| (acc << 1) | x as u8);
+        assert_eq!(section, 76);
+        bits[64..71].fill(false);
+        assert!(matches!(
+            MessageData::from_bits(&bits, &CallsignMap::new()),
+            Err(Error::InvalidField { value: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn unpack_arbitrary_bits() {
+        // Every payload either unpacks or is rejected, none of them may panic
+        let mut state = 0x9e3779b97f4a7c15u64;
+        for _ in 0..10000 {
+            let mut bits = [false; 77];
+            for bit in bits.iter_mut() {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                *bit = state & 1 == 1;
+            }
+            if let Ok(data) = MessageData::from_bits(&bits, &CallsignMap::new()) {
+                data.to_string();
+            }
+        }
+        let mut bits = MessageData::from_text("TU; K1ABC W9XYZ 579 WI")
+            .unwrap()
+            .to_bits()
+            .unwrap();
+        bits[61..74].fill(true);
+        assert!(MessageData::from_bits(&bits, &CallsignMap::new()).is_err());
+    }
+
+    #[test]
+    fn pack_contest_messages() {
+        let data = MessageData::from_text("TU; K1ABC W9XYZ 579 WI").unwrap();
+        assert!(matches!(data, MessageData::RTTYRU(_)));
+        assert_eq!(
+            pack_roundtrip("TU; K1ABC W9XYZ 579 WI"),
+            "TU K1ABC W9XYZ 579 WI "
+        );
+        assert_eq!(
+            pack_roundtrip("K1ABC W9XYZ 559 0013"),
+            "K1ABC W9XYZ 559 0013"
+        );
+        assert_eq!(
+            pack_roundtrip("<PA9XYZ> <G4ABC/P> R 590003 IO91NP"),
+            "PA9XYZ G4ABC/P R 590003 IO91NP"
+        );
+        assert_eq!(
+            pack_roundtrip("K1ABC RR73; W9XYZ <KH1/KH7Z> -08"),
+            "K1ABC W9XYZ KH1/KH7Z -8"
+        );
+    }
+
+    #[test]
+    fn pack_telemetry() {
+        let data = MessageData::from_text("123456789ABCDEF012").unwrap();
+        assert!(matches!(data, MessageData::Telemetry(_)));
+        assert_eq!(pack_roundtrip("123456789ABCDEF012"), "123456789ABCDEF012");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let mut callsign_map = CallsignMap::new();
+        callsign_map.insert(&"PA9XYZ".to_string());
+        let callsign_map: CallsignMap =
+            serde_json::from_str(&serde_json::to_string(&callsign_map).unwrap()).unwrap();
+        for (text, kind) in [
+            ("CQ K1ABC FN42", "StandardR"),
+            ("W9XYZ <PA9XYZ> 73", "StandardR"),
+            ("PJ4/K1ABC <W9XYZ> RR73", "NonStdCall"),
+            ("K1ABC W9XYZ R 17B EMA", "FieldDay"),
+            ("TU; K1ABC W9XYZ 579 WI", "RTTYRU"),
+            ("<PA9XYZ> <G4ABC/P> R 590003 IO91NP", "EUVHF"),
+            ("123456789ABCDEF012", "Telemetry"),
+        ] {
+            let bits = MessageData::from_text(text).unwrap().to_bits().unwrap();
+            let message = Message::from_bits(-12.0, 1234, 500, &bits, &callsign_map).unwrap();
+            let json = serde_json::to_value(&message).unwrap();
+            assert_eq!(json["data"]["type"], kind);
+            assert_eq!(json["freq_hz"], 1234);
+            let message: Message = serde_json::from_value(json).unwrap();
+            assert_eq!(message.bits(), bits);
+            assert_eq!(message.data.to_bits(), Some(bits));
+        }
+        assert_eq!(
+            MessageData::from_text("W9XYZ <PA9XYZ> 73")
+                .and_then(
+                    |data| MessageData::from_bits(&data.to_bits().unwrap(), &callsign_map).ok()
+                )
+                .unwrap()
+                .to_string(),
+            "W9XYZ PA9XYZ 73"
+        );
+        // Callsigns and grids are written as text, not as their packed values
+        for (text, json) in [
+            (
+                "CQ K1ABC FN42",
+                r#"{"type":"StandardR","callsign1":"CQ","callsign2":"K1ABC","grid":"FN42"}"#,
+            ),
+            (
+                "W9XYZ <PA9XYZ> R-07",
+                r#"{"type":"StandardR","callsign1":"W9XYZ","callsign2":"<PA9XYZ>","grid":"R-07"}"#,
+            ),
+        ] {
+            let data = MessageData::from_text(text).unwrap();
+            assert_eq!(serde_json::to_string(&data).unwrap(), json);
+            let parsed: MessageData = serde_json::from_str(json).unwrap();
+            assert_eq!(parsed.to_bits(), data.to_bits());
+        }
+        let unknown = MessageData::Unknown(Unknown { bits: [true; 77] });
+        let json = serde_json::to_string(&unknown).unwrap();
+        assert_eq!(
+            json,
+            format!(r#"{{"type":"Unknown","bits":"{}"}}"#, "1".repeat(77))
+        );
+        assert!(serde_json::from_str::<MessageData>(&json.replace("11\"", "12\"")).is_err());
+    }
+
     #[test]
     fn test_stuff() {}
 }
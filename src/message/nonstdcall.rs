@@ -2,12 +2,17 @@ use std::fmt::Display;
 
 use crate::{
     callsignmap::CallsignMap,
-    utils::{bool_slice_to_u32, bool_slice_to_u8},
+    utils::{bit_field, bool_slice_to_u32, bool_slice_to_u8, u32_to_bool_slice, u8_to_bool_slice},
 };
 
-use super::{callsign::Callsign58, hashedcallsign::HashedCallsign};
+use super::{
+    callsign::{Callsign28, Callsign58},
+    hashedcallsign::HashedCallsign,
+};
 
+/// A nonstandard callsign sent in full and a second callsign sent as a 12 bit hash.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NonStdCall {
     pub callsign1: HashedCallsign<12>,
     pub callsign2: Callsign58,
@@ -19,15 +24,63 @@ impl NonStdCall {
     pub fn from_bits(message: &[bool; 77], callsign_map: &CallsignMap) -> Self {
         Self {
             callsign1: HashedCallsign::from_hash(
-                bool_slice_to_u32::<12>(&message[0..12].try_into().unwrap()),
+                bool_slice_to_u32::<12>(bit_field(message, 0)),
                 callsign_map,
             ),
-            callsign2: Callsign58::from_bits(&message[12..70].try_into().unwrap()),
+            callsign2: Callsign58::from_bits(bit_field(message, 12)),
             callsign_swap: message[70],
-            message_word: bool_slice_to_u8::<2>(&message[71..73].try_into().unwrap()),
+            message_word: bool_slice_to_u8::<2>(bit_field(message, 71)),
             has_cq: message[73],
         }
     }
+    pub(crate) fn from_words(words: &[&str]) -> Option<Self> {
+        if let ["CQ", callsign] = *words {
+            return Some(Self {
+                callsign1: HashedCallsign::from_hash(0, &CallsignMap::new()),
+                callsign2: callsign.parse().ok()?,
+                callsign_swap: false,
+                message_word: 0,
+                has_cq: true,
+            });
+        }
+        let (callsign1, callsign2, message_word) = match *words {
+            [callsign1, callsign2] => (callsign1, callsign2, 0),
+            [callsign1, callsign2, word] => (
+                callsign1,
+                callsign2,
+                (1..4).find(|&r2| Self::message_word_to_text(r2) == word)?,
+            ),
+            _ => return None,
+        };
+        let is_standard = |call: &str| Callsign28::pack_standard_call(call).is_ok();
+        // One callsign is sent in full and the other as a 12 bit hash
+        let (hashed, full, callsign_swap) = if callsign1.starts_with('<') {
+            (callsign1, callsign2, false)
+        } else if callsign2.starts_with('<') || !is_standard(callsign1) {
+            (callsign2, callsign1, true)
+        } else if !is_standard(callsign2) {
+            (callsign1, callsign2, false)
+        } else {
+            return None;
+        };
+        Some(Self {
+            callsign1: hashed.parse().ok()?,
+            callsign2: full.parse().ok()?,
+            callsign_swap,
+            message_word,
+            has_cq: false,
+        })
+    }
+    pub fn to_bits(&self) -> [bool; 77] {
+        let mut message = [false; 77];
+        message[0..12].copy_from_slice(&u32_to_bool_slice::<12>(self.callsign1.hashed));
+        message[12..70].copy_from_slice(&self.callsign2.to_bits());
+        message[70] = self.callsign_swap;
+        message[71..73].copy_from_slice(&u8_to_bool_slice::<2>(self.message_word));
+        message[73] = self.has_cq;
+        message[74..77].copy_from_slice(&u8_to_bool_slice::<3>(4));
+        message
+    }
     fn message_word_to_text(r2: u8) -> &'static str {
         match r2 {
             0 => "",
@@ -1,10 +1,16 @@
 use std::fmt::Display;
 
-use crate::{utils::{bool_slice_to_u16, bool_slice_to_u8}, callsignmap::CallsignMap};
+use crate::{
+    callsignmap::CallsignMap,
+    utils::{bit_field, bool_slice_to_u16, bool_slice_to_u8, u16_to_bool_slice, u8_to_bool_slice},
+    Error,
+};
 
 use super::{callsign::Callsign, messagedata::S13};
 
+/// ARRL RTTY Roundup exchange, `signal_strength` is the middle digit of the RST minus 2.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RTTYRU {
     pub has_tu: bool,
     pub callsign1: Callsign,
@@ -14,17 +20,57 @@ pub struct RTTYRU {
     pub s13: S13,
 }
 impl RTTYRU {
-    pub fn from_bits(message: &[bool; 77], callsign_map: &CallsignMap) -> Self {
-        Self {
+    pub fn from_bits(message: &[bool; 77], callsign_map: &CallsignMap) -> Result<Self, Error> {
+        Ok(Self {
             has_tu: message[0],
-            callsign1: Callsign::from_bits(&message[1..29].try_into().unwrap(), callsign_map),
-            callsign2: Callsign::from_bits(&message[29..57].try_into().unwrap(), callsign_map),
+            callsign1: Callsign::from_bits(bit_field(message, 1), callsign_map),
+            callsign2: Callsign::from_bits(bit_field(message, 29), callsign_map),
             has_r: message[57],
-            signal_strength: bool_slice_to_u8::<3>(&message[58..61].try_into().unwrap()),
-            s13: S13::new(bool_slice_to_u16::<13>(
-                &message[61..74].try_into().unwrap(),
-            )),
+            signal_strength: bool_slice_to_u8::<3>(bit_field(message, 58)),
+            s13: S13::new(bool_slice_to_u16::<13>(bit_field(message, 61)))?,
+        })
+    }
+    pub(crate) fn from_words(words: &[&str]) -> Option<Self> {
+        let (has_tu, words) = match words {
+            ["TU;" | "TU", rest @ ..] => (true, rest),
+            _ => (false, words),
+        };
+        let (callsign1, callsign2, has_r, report, s13) = match *words {
+            [callsign1, callsign2, report, s13] => (callsign1, callsign2, false, report, s13),
+            [callsign1, callsign2, "R", report, s13] => (callsign1, callsign2, true, report, s13),
+            _ => return None,
+        };
+        let report = report.as_bytes();
+        let valid = report.len() == 3
+            && report[0] == b'5'
+            && (b'2'..=b'9').contains(&report[1])
+            && report[2] == b'9';
+        if !valid {
+            return None;
         }
+        Some(Self {
+            has_tu,
+            callsign1: Callsign::parse_explicit(callsign1)
+                .ok()
+                .filter(|c| c.to_u32() >= 2063592)?,
+            callsign2: Callsign::parse_explicit(callsign2)
+                .ok()
+                .filter(|c| c.to_u32() >= 2063592)?,
+            has_r,
+            signal_strength: report[1] - b'2',
+            s13: S13::parse(s13)?,
+        })
+    }
+    pub fn to_bits(&self) -> [bool; 77] {
+        let mut message = [false; 77];
+        message[0] = self.has_tu;
+        message[1..29].copy_from_slice(&self.callsign1.to_bits());
+        message[29..57].copy_from_slice(&self.callsign2.to_bits());
+        message[57] = self.has_r;
+        message[58..61].copy_from_slice(&u8_to_bool_slice::<3>(self.signal_strength));
+        message[61..74].copy_from_slice(&u16_to_bool_slice::<13>(self.s13.to_u16()));
+        message[74..77].copy_from_slice(&u8_to_bool_slice::<3>(3));
+        message
     }
     pub fn callsigns(&self) -> Vec<String> {
         vec![self.callsign1.to_string(), self.callsign2.to_string()]
@@ -1,10 +1,18 @@
 use std::fmt::Display;
 
-use crate::callsignmap::CallsignMap;
+use crate::{
+    callsignmap::CallsignMap,
+    utils::{bit_field, u8_to_bool_slice},
+};
 
-use super::{callsign::Callsign, grid::Grid4};
+use super::{
+    callsign::Callsign,
+    grid::{Grid4, Grid4Value},
+};
 
+/// Two callsigns and a grid, report or acknowledgement, `SUFFIX` is the /R or /P callsigns may carry.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Standard<const SUFFIX: char> {
     pub callsign1: Callsign,
     pub callsign2: Callsign,
@@ -17,18 +25,59 @@ impl<const SUFFIX: char> Standard<SUFFIX> {
         let has_r = message[58];
         Self {
             callsign1: Callsign::from_bits_with_suffix(
-                &message[0..28].try_into().unwrap(),
+                bit_field(message, 0),
                 callsign1_suffix,
                 callsign_map,
             ),
             callsign2: Callsign::from_bits_with_suffix(
-                &message[29..57].try_into().unwrap(),
+                bit_field(message, 29),
                 callsign2_suffix,
                 callsign_map,
             ),
-            grid: Grid4::from_bits_with_r(&message[59..74].try_into().unwrap(), has_r),
+            grid: Grid4::from_bits_with_r(bit_field(message, 59), has_r),
         }
     }
+    pub(crate) fn from_words(words: &[&str]) -> Option<Self> {
+        let (callsign1, callsign2, grid) = match *words {
+            [callsign1, callsign2] => (callsign1, callsign2, "".parse().ok()?),
+            [callsign1, callsign2, grid] => (callsign1, callsign2, grid.parse().ok()?),
+            [callsign1, callsign2, "R", grid] => {
+                let grid = grid
+                    .parse::<Grid4>()
+                    .ok()
+                    .filter(|grid| !grid.has_r && matches!(grid.value(), Grid4Value::Locator(_)))?;
+                (callsign1, callsign2, Grid4::from_u16(grid.grid, true))
+            }
+            _ => return None,
+        };
+        let callsign1 = Callsign::parse_explicit(callsign1).ok()?;
+        let callsign2 = Callsign::parse_explicit(callsign2)
+            .ok()
+            .filter(|c| c.to_u32() >= 2063592)?;
+        if [&callsign1, &callsign2]
+            .iter()
+            .any(|c| c.suffix().is_some_and(|suffix| suffix != SUFFIX))
+        {
+            return None;
+        }
+        Some(Self {
+            callsign1,
+            callsign2,
+            grid,
+        })
+    }
+    pub fn to_bits(&self) -> [bool; 77] {
+        let i3 = if SUFFIX == 'P' { 2 } else { 1 };
+        let mut message = [false; 77];
+        message[0..28].copy_from_slice(&self.callsign1.to_bits());
+        message[28] = self.callsign1.suffix().is_some();
+        message[29..57].copy_from_slice(&self.callsign2.to_bits());
+        message[57] = self.callsign2.suffix().is_some();
+        message[58] = self.grid.has_r;
+        message[59..74].copy_from_slice(&self.grid.to_bits());
+        message[74..77].copy_from_slice(&u8_to_bool_slice::<3>(i3));
+        message
+    }
     pub fn callsigns(&self) -> Vec<String> {
         vec![self.callsign1.to_string(), self.callsign2.to_string()]
     }
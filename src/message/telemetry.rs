@@ -1,8 +1,12 @@
 use std::fmt::Display;
 
-use crate::utils::{bool_slice_to_u128, char_lookup};
+use crate::utils::{
+    bit_field, bool_slice_to_u128, char_lookup, u128_to_bool_slice, u8_to_bool_slice,
+};
 
+/// 71 bits of telemetry, shown as 18 hex digits.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Telemetry {
     pub text: String,
 }
@@ -10,9 +14,30 @@ const TELEMETRY_CHARSET: &str = "0123456789ABCDEF";
 impl Telemetry {
     pub fn from_bits(message: &[bool; 77]) -> Self {
         Self {
-            text: Self::to_text(&message[..71].try_into().unwrap()),
+            text: Self::to_text(bit_field(message, 0)),
         }
     }
+    pub(crate) fn from_words(words: &[&str]) -> Option<Self> {
+        let [text] = *words else {
+            return None;
+        };
+        if text.is_empty() || text.len() > 18 {
+            return None;
+        }
+        let n = u128::from_str_radix(text, 16)
+            .ok()
+            .filter(|&n| n < 1 << 71)?;
+        Some(Self {
+            text: format!("{:018X}", n),
+        })
+    }
+    pub fn to_bits(&self) -> [bool; 77] {
+        let n = u128::from_str_radix(&self.text, 16).unwrap_or(0);
+        let mut message = [false; 77];
+        message[..71].copy_from_slice(&u128_to_bool_slice::<71>(n));
+        message[71..74].copy_from_slice(&u8_to_bool_slice::<3>(5));
+        message
+    }
     pub fn to_text(message: &[bool; 71]) -> String {
         let mut message = bool_slice_to_u128(message);
         let mut text = String::new();
@@ -1,14 +1,20 @@
 
 use std::fmt::Display;
 
+/// A payload of a message type this crate cannot unpack.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Unknown {
+    #[cfg_attr(feature = "serde", serde(with = "crate::utils::serde_bits"))]
     pub bits: [bool; 77],
 }
 impl Unknown {
     pub fn from_bits(message: &[bool; 77]) -> Self {
         Self { bits: *message }
     }
+    pub fn to_bits(&self) -> [bool; 77] {
+        self.bits
+    }
 }
 impl Display for Unknown {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
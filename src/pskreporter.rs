@@ -0,0 +1,359 @@
+//! Spot reports to PSK Reporter.
+//!
+//! Reports are IPFIX (RFC 7011) messages over UDP in the layout described at
+//! <https://pskreporter.info/pskdev.html>: an options template describing the receiver,
+//! a template describing each spot, then one receiver record and a record per spot.
+//! [`PskReporter`] batches spots and follows the guidelines there: at most one report
+//! every five minutes, each callsign reported once per band per hour, and the templates
+//! sent in the first three packets and then hourly.
+
+use std::{
+    collections::HashMap,
+    net::{ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    jt65::Jt65Message,
+    message::{callsign::STANDARD_CALL_BASE, Callsign, Grid4Value, Message, MessageData},
+    Error,
+};
+
+pub const DEFAULT_HOST: &str = "report.pskreporter.info:4739";
+const IPFIX_VERSION: u16 = 10;
+const RECEIVER_TEMPLATE_ID: u16 = 0x9992;
+const SENDER_TEMPLATE_ID: u16 = 0x9993;
+// Enterprise number of PSK Reporter's private information elements
+const ENTERPRISE: u32 = 30351;
+const VARIABLE_LENGTH: u16 = 0xffff;
+// Keeps datagrams below the common path MTU
+const MAX_PACKET_LEN: usize = 1400;
+const TEMPLATE_PACKETS: u32 = 3;
+const TEMPLATE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const INFORMATION_SOURCE_AUTOMATIC: u8 = 1;
+
+// Options template: receiverCallsign, receiverLocator, decodingSoftware
+const RECEIVER_FIELDS: [(u16, u16); 3] = [
+    (0x8002, VARIABLE_LENGTH),
+    (0x8004, VARIABLE_LENGTH),
+    (0x8008, VARIABLE_LENGTH),
+];
+// Template: senderCallsign, frequency, sNR, mode, senderLocator, informationSource,
+// and the IANA flowStartSeconds
+const SENDER_FIELDS: [(u16, u16); 7] = [
+    (0x8001, VARIABLE_LENGTH),
+    (0x8005, 4),
+    (0x8006, 1),
+    (0x800a, VARIABLE_LENGTH),
+    (0x8003, VARIABLE_LENGTH),
+    (0x800b, 1),
+    (150, 4),
+];
+
+/// The station hearing the spots.
+#[derive(Debug, Clone)]
+pub struct Receiver {
+    pub callsign: String,
+    pub locator: String,
+    pub decoding_software: String,
+}
+
+/// One station heard.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spot {
+    pub callsign: String,
+    // RF frequency in Hz, the dial frequency plus the audio frequency
+    pub frequency: u32,
+    pub snr: i8,
+    pub mode: String,
+    pub locator: Option<String>,
+    // Start of the transmission in seconds since the Unix epoch
+    pub flow_start: u32,
+}
+
+pub struct PskReporterParams {
+    pub receiver: Receiver,
+    pub host: String,
+    // Minimum time between reports, spots are held until it passes
+    pub report_interval: Duration,
+    // A callsign is reported at most once per band within this time
+    pub dedupe_interval: Duration,
+}
+
+impl Default for PskReporterParams {
+    fn default() -> Self {
+        PskReporterParams {
+            receiver: Receiver {
+                callsign: String::new(),
+                locator: String::new(),
+                decoding_software: format!("weaksignal {}", env!("CARGO_PKG_VERSION")),
+            },
+            host: DEFAULT_HOST.to_string(),
+            report_interval: Duration::from_secs(5 * 60),
+            dedupe_interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+pub struct PskReporter {
+    socket: UdpSocket,
+    receiver: Receiver,
+    report_interval: Duration,
+    dedupe_interval: Duration,
+    observation_domain: u32,
+    sequence: u32,
+    packets_sent: u32,
+    templates_sent: Option<Instant>,
+    last_report: Option<Instant>,
+    pending: Vec<Spot>,
+    // Last time each callsign was queued, keyed by callsign and band in MHz
+    reported: HashMap<(String, u32), Instant>,
+}
+
+impl Spot {
+    /// The spot for a message decoded in `mode`, such as "FT8" or "JT65", heard on
+    /// `dial_frequency` in the cycle starting at `cycle_start`, if the message names
+    /// its sender and the RF frequency fits the 4 byte field PSK Reporter takes.
+    pub fn from_message(
+        message: &Message,
+        mode: &str,
+        dial_frequency: u32,
+        cycle_start: SystemTime,
+    ) -> Option<Self> {
+        let (callsign, locator) = sender(&message.data)?;
+        Some(Self {
+            callsign,
+            frequency: dial_frequency.checked_add(message.freq_bin_hz)?,
+            snr: message.snr().round().clamp(-128.0, 127.0) as i8,
+            mode: mode.to_string(),
+            locator,
+            flow_start: cycle_start
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |t| t.as_secs() as u32),
+        })
+    }
+}
+
+// The transmitting station and its locator, only calls that were sent in full count
+fn sender(data: &MessageData) -> Option<(String, Option<String>)> {
+    let (callsign, grid) = match data {
+        MessageData::StandardR(m) => (&m.callsign2, &m.grid),
+        MessageData::StandardP(m) => (&m.callsign2, &m.grid),
+        MessageData::NonStdCall(m) if !m.callsign_swap => {
+            return Some((m.callsign2.to_string(), None));
+        }
+        // Prefixes and suffixes from WSJT's list are only known by number
+        MessageData::Jt65(m @ Jt65Message::Standard { callsign2, .. }) => {
+            return (!callsign2.contains('<'))
+                .then(|| (callsign2.clone(), m.grid().map(str::to_string)));
+        }
+        _ => return None,
+    };
+    let Callsign::C28(callsign) = callsign else {
+        return None;
+    };
+    if callsign.c28 < STANDARD_CALL_BASE {
+        return None;
+    }
+    let locator = match grid.value() {
+        Grid4Value::Locator(locator) if !grid.has_r => Some(locator),
+        _ => None,
+    };
+    Some((callsign.to_string(), locator))
+}
+
+impl PskReporter {
+    pub fn new(params: &PskReporterParams) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(params.host.to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, params.host.clone())
+        })?)?;
+        // The domain identifies this session to the collector, it only needs to differ
+        // between sessions
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let observation_domain = now.subsec_nanos() ^ (now.as_secs() as u32) ^ std::process::id();
+        Ok(Self {
+            socket,
+            receiver: params.receiver.clone(),
+            report_interval: params.report_interval,
+            dedupe_interval: params.dedupe_interval,
+            observation_domain,
+            sequence: 0,
+            packets_sent: 0,
+            templates_sent: None,
+            last_report: None,
+            pending: Vec::new(),
+            reported: HashMap::new(),
+        })
+    }
+
+    /// Queues the senders of `messages`, decoded in `mode`, and sends a report if one
+    /// is due.
+    pub fn report(
+        &mut self,
+        messages: &[Message],
+        mode: &str,
+        dial_frequency: u32,
+        cycle_start: SystemTime,
+    ) -> Result<(), Error> {
+        let now = Instant::now();
+        let dedupe_interval = self.dedupe_interval;
+        self.reported
+            .retain(|_, queued| now.duration_since(*queued) < dedupe_interval);
+        for message in messages {
+            let Some(spot) = Spot::from_message(message, mode, dial_frequency, cycle_start) else {
+                continue;
+            };
+            let key = (spot.callsign.clone(), spot.frequency / 1_000_000);
+            if self.reported.contains_key(&key) {
+                continue;
+            }
+            self.reported.insert(key, now);
+            self.pending.push(spot);
+        }
+        let due = self
+            .last_report
+            .is_none_or(|last| now.duration_since(last) >= self.report_interval);
+        if due && !self.pending.is_empty() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Sends every queued spot now, regardless of the report interval.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let export_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_secs() as u32);
+        let spots = std::mem::take(&mut self.pending);
+        let mut start = 0;
+        while start < spots.len() {
+            let templates = self.packets_sent < TEMPLATE_PACKETS
+                || self
+                    .templates_sent
+                    .is_none_or(|sent| now.duration_since(sent) >= TEMPLATE_INTERVAL);
+            let (packet, count) = encode_packet(
+                &self.receiver,
+                &spots[start..],
+                templates,
+                export_time,
+                self.sequence,
+                self.observation_domain,
+            );
+            self.socket.send(&packet)?;
+            // The sequence number counts data records sent in the session
+            self.sequence = self.sequence.wrapping_add(count as u32 + 1);
+            self.packets_sent += 1;
+            if templates {
+                self.templates_sent = Some(now);
+            }
+            start += count;
+        }
+        self.last_report = Some(now);
+        Ok(())
+    }
+}
+
+impl Drop for PskReporter {
+    fn drop(&mut self) {
+        // Best effort, the spots are lost if the collector cannot be reached
+        let _ = self.flush();
+    }
+}
+
+/// Encodes one IPFIX message with the receiver record and as many of `spots` as fit,
+/// returning it with the number of spots it holds.
+pub fn encode_packet(
+    receiver: &Receiver,
+    spots: &[Spot],
+    templates: bool,
+    export_time: u32,
+    sequence: u32,
+    observation_domain: u32,
+) -> (Vec<u8>, usize) {
+    let mut packet = Vec::with_capacity(MAX_PACKET_LEN);
+    packet.extend(IPFIX_VERSION.to_be_bytes());
+    // Length is filled in last
+    packet.extend([0, 0]);
+    packet.extend(export_time.to_be_bytes());
+    packet.extend(sequence.to_be_bytes());
+    packet.extend(observation_domain.to_be_bytes());
+
+    if templates {
+        let mut set = Vec::new();
+        set.extend(RECEIVER_TEMPLATE_ID.to_be_bytes());
+        set.extend((RECEIVER_FIELDS.len() as u16).to_be_bytes());
+        // No scope fields
+        set.extend(0u16.to_be_bytes());
+        put_fields(&mut set, &RECEIVER_FIELDS);
+        push_set(&mut packet, 3, &set);
+
+        let mut set = Vec::new();
+        set.extend(SENDER_TEMPLATE_ID.to_be_bytes());
+        set.extend((SENDER_FIELDS.len() as u16).to_be_bytes());
+        put_fields(&mut set, &SENDER_FIELDS);
+        push_set(&mut packet, 2, &set);
+    }
+
+    let mut set = Vec::new();
+    put_string(&mut set, &receiver.callsign);
+    put_string(&mut set, &receiver.locator);
+    put_string(&mut set, &receiver.decoding_software);
+    push_set(&mut packet, RECEIVER_TEMPLATE_ID, &set);
+
+    let mut set = Vec::new();
+    let mut count = 0;
+    for spot in spots {
+        let mut record = Vec::new();
+        put_string(&mut record, &spot.callsign);
+        record.extend(spot.frequency.to_be_bytes());
+        record.push(spot.snr as u8);
+        put_string(&mut record, &spot.mode);
+        put_string(&mut record, spot.locator.as_deref().unwrap_or(""));
+        record.push(INFORMATION_SOURCE_AUTOMATIC);
+        record.extend(spot.flow_start.to_be_bytes());
+        // Set header and up to 3 bytes of padding
+        if packet.len() + set.len() + record.len() + 7 > MAX_PACKET_LEN && count > 0 {
+            break;
+        }
+        set.extend(record);
+        count += 1;
+    }
+    if count > 0 {
+        push_set(&mut packet, SENDER_TEMPLATE_ID, &set);
+    }
+
+    let len = packet.len() as u16;
+    packet[2..4].copy_from_slice(&len.to_be_bytes());
+    (packet, count)
+}
+
+fn put_fields(set: &mut Vec<u8>, fields: &[(u16, u16)]) {
+    for &(id, len) in fields {
+        set.extend(id.to_be_bytes());
+        set.extend(len.to_be_bytes());
+        // Enterprise specific elements have the top bit set and carry the enterprise number
+        if id & 0x8000 != 0 {
+            set.extend(ENTERPRISE.to_be_bytes());
+        }
+    }
+}
+
+// Short strings are a length byte then the bytes, which is all PSK Reporter accepts
+fn put_string(record: &mut Vec<u8>, text: &str) {
+    let bytes = &text.as_bytes()[..text.len().min(254)];
+    record.push(bytes.len() as u8);
+    record.extend(bytes);
+}
+
+// Sets are padded to a multiple of 4 bytes
+fn push_set(packet: &mut Vec<u8>, set_id: u16, contents: &[u8]) {
+    let padding = (4 - (contents.len() + 4) % 4) % 4;
+    packet.extend(set_id.to_be_bytes());
+    packet.extend(((contents.len() + 4 + padding) as u16).to_be_bytes());
+    packet.extend(contents);
+    packet.extend(std::iter::repeat_n(0, padding));
+}
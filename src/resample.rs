@@ -0,0 +1,88 @@
+use std::{f64::consts::PI, iter::zip};
+
+// Taps per polyphase branch for every factor of decimation
+const TAPS_PER_RATIO: usize = 24;
+
+// Rational polyphase resampler, upsamples by `up`, low-pass filters and keeps
+// every `down`th sample. State is kept between calls so audio can be streamed
+#[derive(Clone)]
+pub(crate) struct Resampler {
+    up: usize,
+    down: usize,
+    taps: usize,
+    filter: Vec<f32>,
+    history: Vec<f32>,
+    // Upsampled index of the next output sample, relative to history[0]
+    next: usize,
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl Resampler {
+    pub(crate) fn new(input_rate: usize, output_rate: usize) -> Resampler {
+        let divisor = gcd(input_rate, output_rate);
+        let up = output_rate / divisor;
+        let down = input_rate / divisor;
+        let taps = TAPS_PER_RATIO * down.div_ceil(up);
+        // An odd length puts the centre of the filter on a sample
+        let len = taps * up - 1;
+
+        // Blackman windowed sinc at the upsampled rate, cut off below the lower Nyquist
+        let cutoff = 0.45 * input_rate.min(output_rate) as f64 / (input_rate * up) as f64;
+        let centre = (len - 1) as f64 / 2.0;
+        let filter = (0..len)
+            .map(|i| {
+                let x = i as f64 - centre;
+                let sinc = if x == 0.0 {
+                    2.0 * cutoff
+                } else {
+                    (2.0 * PI * cutoff * x).sin() / (PI * x)
+                };
+                let phase = 2.0 * PI * i as f64 / (len - 1) as f64;
+                let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+                (sinc * window * up as f64) as f32
+            })
+            .collect();
+        Resampler {
+            up,
+            down,
+            taps,
+            filter,
+            history: Vec::new(),
+            // Start half a filter in so the output is not delayed
+            next: (len - 1) / 2,
+        }
+    }
+
+    pub(crate) fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        self.history.extend_from_slice(input);
+        let mut output = Vec::new();
+        while self.next / self.up < self.history.len() {
+            let phase = self.next % self.up;
+            let base = self.next / self.up;
+            let branch = self.filter[phase..].iter().step_by(self.up);
+            let sample = zip(branch, self.history[..=base].iter().rev())
+                .map(|(h, x)| h * x)
+                .sum::<f32>();
+            output.push(sample);
+            self.next += self.down;
+        }
+
+        // Only the samples still under the filter are needed for the next call
+        let consumed = (self.next / self.up).saturating_sub(self.taps);
+        self.history.drain(..consumed);
+        self.next -= consumed * self.up;
+        output
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.history.clear();
+        self.next = (self.filter.len() - 1) / 2;
+    }
+}
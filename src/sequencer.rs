@@ -0,0 +1,518 @@
+//! Automatic QSO sequencing.
+//!
+//! [`Sequencer`] picks the next message to transmit from the decodes addressed to the
+//! station, stepping through the usual exchange: grid, report, R and report, RR73 and
+//! 73. It follows the DX station's even or odd slot, repeats a message until the reply
+//! moves the contact on, gives up after too many repeats and stops transmitting when
+//! its watchdog runs out. Completed contacts are returned as [`Qso`] records.
+//!
+//! Decodes only reach the sequencer as addressed to the station if hashed callsigns
+//! resolve, so the station's callsign should be in the decoder's callsign map.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    adif::Qso,
+    cabrillo::Contest,
+    message::{callsign::is_station, Callsign, Grid4Value, Message, MessageData, Standard},
+};
+
+/// Even or odd transmit period, counting cycles from the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    Even,
+    Odd,
+}
+
+impl Slot {
+    pub fn of(cycle: u64) -> Self {
+        if cycle.is_multiple_of(2) {
+            Slot::Even
+        } else {
+            Slot::Odd
+        }
+    }
+    pub fn other(&self) -> Self {
+        match self {
+            Slot::Even => Slot::Odd,
+            Slot::Odd => Slot::Even,
+        }
+    }
+}
+
+/// The message the sequencer sends next, named after the WSJT-X Tx1 to Tx6 messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Tx6, calling CQ.
+    Cq,
+    /// Tx1, calling a station with the locator.
+    Call,
+    /// Tx2, the report, or the exchange in contests.
+    Report,
+    /// Tx3, R and the report or exchange.
+    RogerReport,
+    /// Tx4, RR73.
+    Rr73,
+    /// Tx5, 73, sent once to finish.
+    SeventyThree,
+}
+
+pub struct SequencerParams {
+    pub my_call: String,
+    // 4 characters, or 6 for EU VHF contests
+    pub my_grid: String,
+    // Contest exchanges replace the report, `None` for standard contacts
+    pub contest: Option<Contest>,
+    // Field Day class and section such as "2B EMA", or the RTTY Roundup state, province
+    // or serial number
+    pub exchange: String,
+    // Serial number sent in the next EU VHF contact
+    pub serial: u16,
+    // Times a message is sent without the reply moving the contact on
+    pub max_repeats: u32,
+    // Transmissions without operator action or a completed contact before stopping
+    pub watchdog: u32,
+    pub cycle_length: Duration,
+    // Mode contacts are logged under, "FT8" or "FT4" to match the cycle length
+    pub mode: String,
+}
+
+impl Default for SequencerParams {
+    fn default() -> Self {
+        SequencerParams {
+            my_call: String::new(),
+            my_grid: String::new(),
+            contest: None,
+            exchange: String::new(),
+            serial: 1,
+            max_repeats: 4,
+            watchdog: 24,
+            cycle_length: Duration::from_secs(15),
+            mode: "FT8".to_string(),
+        }
+    }
+}
+
+// What a decode addressed to the station says
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Heard {
+    Grid,
+    Report,
+    RogerReport,
+    Roger,
+    SeventyThree,
+}
+
+struct Contact {
+    call: String,
+    snr: f32,
+    exchanges: Vec<(SystemTime, MessageData)>,
+}
+
+pub struct Sequencer {
+    params: SequencerParams,
+    step: Option<Step>,
+    slot: Slot,
+    contact: Option<Contact>,
+    // Whether to go back to calling CQ after a contact
+    cq: bool,
+    repeats: u32,
+    transmissions: u32,
+    logged: Vec<Qso>,
+}
+
+impl Sequencer {
+    pub fn new(params: SequencerParams) -> Self {
+        Self {
+            params: SequencerParams {
+                my_call: params.my_call.to_uppercase(),
+                my_grid: params.my_grid.to_uppercase(),
+                ..params
+            },
+            step: None,
+            slot: Slot::Even,
+            contact: None,
+            cq: false,
+            repeats: 0,
+            transmissions: 0,
+            logged: Vec::new(),
+        }
+    }
+
+    /// The next message to send, `None` when idle.
+    pub fn step(&self) -> Option<Step> {
+        self.step
+    }
+    /// The station being worked.
+    pub fn dx_call(&self) -> Option<&str> {
+        self.contact.as_ref().map(|contact| contact.call.as_str())
+    }
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+    /// The cycle `time` falls in.
+    pub fn cycle_at(&self, time: SystemTime) -> u64 {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        (since_epoch.as_millis() / self.params.cycle_length.as_millis().max(1)) as u64
+    }
+    /// Start of `cycle`.
+    pub fn cycle_start(&self, cycle: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.params.cycle_length.as_millis() as u64 * cycle)
+    }
+
+    /// Calls CQ in `slot`, answering the first station to reply.
+    pub fn call_cq(&mut self, slot: Slot) {
+        self.cq = true;
+        self.slot = slot;
+        self.contact = None;
+        self.set_step(Step::Cq);
+        self.transmissions = 0;
+    }
+
+    /// Calls the station that sent `message`, decoded in `cycle`. A message already
+    /// addressed to this station picks the contact up where it is. Returns false if
+    /// the message does not name its sender or the contact cannot be sent.
+    pub fn answer(&mut self, message: &Message, cycle: u64) -> bool {
+        let Some((to, from, heard)) = heard(&message.data) else {
+            return false;
+        };
+        if !is_station(&from) || from == self.params.my_call || !self.can_work(&from) {
+            return false;
+        }
+        self.cq = false;
+        self.slot = Slot::of(cycle).other();
+        let mut contact = Contact {
+            call: from,
+            snr: message.snr(),
+            exchanges: Vec::new(),
+        };
+        let step = if to == self.params.my_call {
+            contact
+                .exchanges
+                .push((self.cycle_start(cycle), message.data.clone()));
+            self.next_step(Step::Cq, heard).unwrap_or(Step::Call)
+        } else if self.params.contest.is_some() {
+            // Contest contacts skip the locator and start with the exchange
+            Step::Report
+        } else {
+            Step::Call
+        };
+        self.contact = Some(contact);
+        self.set_step(step);
+        self.transmissions = 0;
+        true
+    }
+
+    /// Stops transmitting and drops the contact in progress.
+    pub fn halt(&mut self) {
+        self.step = None;
+        self.contact = None;
+        self.cq = false;
+    }
+
+    /// Moves the contact on from the messages decoded in `cycle`.
+    pub fn receive(&mut self, messages: &[Message], cycle: u64) {
+        let Some(step) = self.step else {
+            return;
+        };
+        // Nothing is heard while transmitting
+        if Slot::of(cycle) == self.slot {
+            return;
+        }
+        let time = self.cycle_start(cycle);
+        let mut replies: Vec<_> = messages
+            .iter()
+            .filter_map(|message| {
+                let (to, from, heard) = heard(&message.data)?;
+                let expected = match &self.contact {
+                    Some(contact) => from == contact.call,
+                    None => is_station(&from) && self.can_work(&from),
+                };
+                (to == self.params.my_call && expected).then_some((message, from, heard))
+            })
+            .collect();
+        // Of several stations answering a CQ, the strongest is worked
+        replies.sort_by(|(a, ..), (b, ..)| b.snr().total_cmp(&a.snr()));
+        let Some((message, from, heard)) = replies.into_iter().next() else {
+            return;
+        };
+        let Some(next) = self.next_step(step, heard) else {
+            return;
+        };
+        let contact = self.contact.get_or_insert_with(|| Contact {
+            call: from,
+            snr: message.snr(),
+            exchanges: Vec::new(),
+        });
+        contact.snr = message.snr();
+        contact.exchanges.push((time, message.data.clone()));
+        if next == Step::Cq {
+            // The DX sent 73, nothing more to send
+            self.complete();
+        } else if next != step {
+            self.set_step(next);
+        }
+    }
+
+    /// The message to send in `cycle`, `None` when idle or when `cycle` is in the
+    /// other station's slot.
+    pub fn transmit(&mut self, cycle: u64) -> Option<MessageData> {
+        if self.step.is_none() || Slot::of(cycle) != self.slot {
+            return None;
+        }
+        if self.transmissions >= self.params.watchdog {
+            self.halt();
+            return None;
+        }
+        if self.repeats >= self.params.max_repeats && self.step != Some(Step::Cq) {
+            // RR73 needs no reply to count, anything earlier is abandoned
+            if self.step == Some(Step::Rr73) {
+                self.complete();
+            } else {
+                self.abandon();
+            }
+        }
+        let step = self.step?;
+        let data = self
+            .text(step)
+            .and_then(|text| MessageData::from_text(&text));
+        let Some(data) = data else {
+            self.halt();
+            return None;
+        };
+        let time = self.cycle_start(cycle);
+        if let Some(contact) = &mut self.contact {
+            contact.exchanges.push((time, data.clone()));
+        }
+        self.repeats += 1;
+        self.transmissions += 1;
+        if step == Step::SeventyThree {
+            self.complete();
+        }
+        Some(data)
+    }
+
+    /// Contacts completed since the last call.
+    pub fn take_logged(&mut self) -> Vec<Qso> {
+        std::mem::take(&mut self.logged)
+    }
+
+    fn set_step(&mut self, step: Step) {
+        self.step = Some(step);
+        self.repeats = 0;
+    }
+
+    fn complete(&mut self) {
+        if let Some(contact) = self.contact.take() {
+            if let Some(qso) =
+                Qso::from_exchanges(&self.params.my_call, &self.params.mode, &contact.exchanges)
+            {
+                self.logged.push(qso);
+            }
+            if self.params.contest == Some(Contest::EuVhf) {
+                self.params.serial = self.params.serial.wrapping_add(1);
+            }
+        }
+        self.transmissions = 0;
+        self.after_contact();
+    }
+
+    fn abandon(&mut self) {
+        self.contact = None;
+        self.after_contact();
+    }
+
+    fn after_contact(&mut self) {
+        if self.cq {
+            self.set_step(Step::Cq);
+        } else {
+            self.step = None;
+        }
+    }
+
+    // The step after hearing `heard` at `step`, `Step::Cq` once the contact is over and
+    // `None` when the message does not fit the contact
+    fn next_step(&self, step: Step, heard: Heard) -> Option<Step> {
+        let next = match (step, heard) {
+            (Step::Cq, Heard::Grid) => Step::Report,
+            (Step::Cq | Step::Call | Step::Report, Heard::Report) => Step::RogerReport,
+            (Step::Call | Step::Report | Step::RogerReport | Step::Rr73, Heard::RogerReport) => {
+                Step::Rr73
+            }
+            (Step::RogerReport, Heard::Roger) => Step::SeventyThree,
+            (Step::RogerReport | Step::Rr73 | Step::SeventyThree, Heard::SeventyThree) => Step::Cq,
+            (Step::Call | Step::Report | Step::Rr73 | Step::SeventyThree, _) => step,
+            _ => return None,
+        };
+        Some(next)
+    }
+
+    // Contests need both callsigns in standard form, other contacts one of them
+    fn can_work(&self, call: &str) -> bool {
+        match self.params.contest {
+            Some(Contest::EuVhf) => true,
+            Some(_) => is_standard(call) && is_standard(&self.params.my_call),
+            None => is_standard(call) || is_standard(&self.params.my_call),
+        }
+    }
+
+    fn text(&self, step: Step) -> Option<String> {
+        let me = &self.params.my_call;
+        let grid4 = self.params.my_grid.get(..4).unwrap_or(&self.params.my_grid);
+        if step == Step::Cq {
+            let modifier = match self.params.contest {
+                Some(Contest::FieldDay) => "FD ",
+                Some(Contest::RttyRoundup) => "RU ",
+                Some(Contest::EuVhf) => "TEST ",
+                None => "",
+            };
+            return Some(if is_standard(me) {
+                format!("CQ {}{} {}", modifier, me, grid4)
+            } else {
+                format!("CQ {}", me)
+            });
+        }
+        let contact = self.contact.as_ref()?;
+        let dx = &contact.call;
+        let word = match step {
+            Step::Rr73 => "RR73",
+            Step::SeventyThree => "73",
+            _ => "",
+        };
+        if self.params.contest == Some(Contest::EuVhf) {
+            let exchange = format!(
+                "{}{:04} {}",
+                rst_digit(contact.snr) + 50,
+                self.params.serial,
+                self.params.my_grid
+            );
+            return Some(match step {
+                Step::Report => format!("<{}> <{}> {}", dx, me, exchange),
+                Step::RogerReport => format!("<{}> <{}> R {}", dx, me, exchange),
+                _ => format!("<{}> <{}> {}", dx, me, word),
+            });
+        }
+        if !is_standard(dx) || !is_standard(me) {
+            // The full nonstandard call goes with the other call hashed in the messages
+            // without a grid or report, and hashed itself in the ones with a report
+            let hashed = |call: &String, plain: bool| {
+                if plain {
+                    call.clone()
+                } else {
+                    format!("<{}>", call)
+                }
+            };
+            let (dx_std, me_std) = (is_standard(dx), is_standard(me));
+            let report = format!("{:+03}", report(contact.snr));
+            return Some(match step {
+                Step::Report => format!("{} {} {}", hashed(dx, dx_std), hashed(me, me_std), report),
+                Step::RogerReport => {
+                    format!("{} {} R{}", hashed(dx, dx_std), hashed(me, me_std), report)
+                }
+                _ => format!("{} {} {}", hashed(dx, !dx_std), hashed(me, !me_std), word)
+                    .trim_end()
+                    .to_string(),
+            });
+        }
+        let exchange = match self.params.contest {
+            Some(Contest::FieldDay) => self.params.exchange.clone(),
+            Some(Contest::RttyRoundup) => {
+                format!("5{}9 {}", rst_digit(contact.snr), self.params.exchange)
+            }
+            _ => format!("{:+03}", report(contact.snr)),
+        };
+        Some(match step {
+            Step::Call => format!("{} {} {}", dx, me, grid4),
+            Step::Report => format!("{} {} {}", dx, me, exchange),
+            Step::RogerReport if self.params.contest.is_some() => {
+                format!("{} {} R {}", dx, me, exchange)
+            }
+            Step::RogerReport => format!("{} {} R{}", dx, me, exchange),
+            _ => format!("{} {} {}", dx, me, word),
+        })
+    }
+}
+
+// Who a message is to and from and what it says, for the message types a contact
+// is made with
+pub(crate) fn heard(data: &MessageData) -> Option<(String, String, Heard)> {
+    let plain = |call: String| call.trim_matches(['<', '>']).to_string();
+    let (to, from, heard) = match data {
+        MessageData::StandardR(Standard {
+            callsign1,
+            callsign2,
+            grid,
+        })
+        | MessageData::StandardP(Standard {
+            callsign1,
+            callsign2,
+            grid,
+        }) => {
+            let heard = match grid.value() {
+                Grid4Value::Blank | Grid4Value::Locator(_) => Heard::Grid,
+                Grid4Value::Report(_) if grid.has_r => Heard::RogerReport,
+                Grid4Value::Report(_) => Heard::Report,
+                Grid4Value::Roger | Grid4Value::RR73 => Heard::Roger,
+                Grid4Value::SeventyThree => Heard::SeventyThree,
+            };
+            (callsign1.to_string(), callsign2.to_string(), heard)
+        }
+        MessageData::NonStdCall(m) => {
+            let heard = match m.message_word {
+                0 => Heard::Grid,
+                3 => Heard::SeventyThree,
+                _ => Heard::Roger,
+            };
+            let (hashed, full) = (m.callsign1.to_string(), m.callsign2.to_string());
+            if m.has_cq {
+                ("CQ".to_string(), full, heard)
+            } else if m.callsign_swap {
+                (full, hashed, heard)
+            } else {
+                (hashed, full, heard)
+            }
+        }
+        MessageData::FieldDay(m) => (
+            m.callsign1.to_string(),
+            m.callsign2.to_string(),
+            if m.has_r {
+                Heard::RogerReport
+            } else {
+                Heard::Report
+            },
+        ),
+        MessageData::RTTYRU(m) => (
+            m.callsign1.to_string(),
+            m.callsign2.to_string(),
+            if m.has_r {
+                Heard::RogerReport
+            } else {
+                Heard::Report
+            },
+        ),
+        MessageData::EUVHF(m) => (
+            m.callsign1.to_string(),
+            m.callsign2.to_string(),
+            if m.has_r {
+                Heard::RogerReport
+            } else {
+                Heard::Report
+            },
+        ),
+        _ => return None,
+    };
+    Some((plain(to), plain(from), heard))
+}
+
+pub(crate) fn is_standard(call: &str) -> bool {
+    Callsign::parse_explicit(call).is_ok()
+}
+
+pub(crate) fn report(snr: f32) -> i16 {
+    (snr.round() as i16).clamp(-30, 49)
+}
+
+// S meter digit for RST and RS reports, S9 from 0 dB down to S3 at -30 dB
+fn rst_digit(snr: f32) -> u8 {
+    ((snr.round() as i32 + 30) / 6 + 3).clamp(2, 9) as u8
+}
@@ -1,3 +1,14 @@
+use std::{
+    f32::consts::PI,
+    iter::zip,
+    ops::Range,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use realfft::RealFftPlanner;
+
+use crate::Error;
+
 macro_rules! slice_to_val {
     ($name:ident, $type:ident) => {
         pub(crate) fn $name<const SIZE: usize>(bits: &[bool; SIZE]) -> $type {
@@ -13,13 +24,196 @@ slice_to_val!(bool_slice_to_u32, u32);
 slice_to_val!(bool_slice_to_u16, u16);
 slice_to_val!(bool_slice_to_u8, u8);
 
+macro_rules! val_to_slice {
+    ($name:ident, $type:ident) => {
+        pub(crate) fn $name<const SIZE: usize>(n: $type) -> [bool; SIZE] {
+            let mut bits = [false; SIZE];
+            bits.iter_mut()
+                .enumerate()
+                .for_each(|(i, x)| *x = (n >> (SIZE - 1 - i)) & 1 == 1);
+            bits
+        }
+    };
+}
+
+val_to_slice!(u128_to_bool_slice, u128);
+val_to_slice!(u64_to_bool_slice, u64);
+val_to_slice!(u32_to_bool_slice, u32);
+val_to_slice!(u16_to_bool_slice, u16);
+val_to_slice!(u8_to_bool_slice, u8);
+
+// Borrows a fixed width field of a fixed size payload
+pub(crate) fn bit_field<const SIZE: usize, const LEN: usize>(
+    bits: &[bool; LEN],
+    start: usize,
+) -> &[bool; SIZE] {
+    bits[start..start + SIZE].try_into().unwrap()
+}
+
+// Bits are written as strings of 0 and 1, serde has no impls for arrays this long
+#[cfg(feature = "serde")]
+pub(crate) mod serde_bits {
+    use serde::{
+        de::{Error, Unexpected},
+        Deserialize, Deserializer, Serializer,
+    };
+
+    pub(crate) fn serialize<S: Serializer, B: AsRef<[bool]>>(
+        bits: &B,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let text = bits
+            .as_ref()
+            .iter()
+            .map(|&x| if x { '1' } else { '0' })
+            .collect::<String>();
+        serializer.serialize_str(&text)
+    }
+
+    // Arrays take only their own length, vectors any
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>, B: TryFrom<Vec<bool>>>(
+        deserializer: D,
+    ) -> Result<B, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if !text.chars().all(|c| c == '0' || c == '1') {
+            return Err(D::Error::invalid_value(
+                Unexpected::Str(&text),
+                &"bits written as 0 and 1",
+            ));
+        }
+        let bits = text.chars().map(|c| c == '1').collect::<Vec<_>>();
+        B::try_from(bits).map_err(|_| D::Error::invalid_length(text.len(), &"a 77 bit payload"))
+    }
+}
+
+// Serializes a type as its Display text and deserializes it with FromStr, so
+// JSON holds "K1ABC" and "FN42" rather than their packed values
+#[cfg(feature = "serde")]
+macro_rules! serde_as_text {
+    ($type:ty $(, const $size:ident)?) => {
+        impl<$(const $size: usize)?> serde::Serialize for $type {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+        impl<'de, $(const $size: usize)?> serde::Deserialize<'de> for $type {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let text = <String as serde::Deserialize>::deserialize(deserializer)?;
+                text.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+#[cfg(feature = "serde")]
+pub(crate) use serde_as_text;
+
+pub(crate) fn unix_ms(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_millis() as i64,
+        Err(before) => -(before.duration().as_millis() as i64),
+    }
+}
+
+pub(crate) fn from_unix_ms(ms: i64) -> Option<SystemTime> {
+    if ms >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_millis(ms as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_millis(ms.unsigned_abs()))
+    }
+}
+
+// Proleptic Gregorian (year, month, day) of a day counted from 1970-01-01
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
 pub(crate) fn char_lookup(x: usize, charset: &str) -> char {
-    if x > charset.len() {
+    if x >= charset.len() {
         return '?';
     }
     return charset.as_bytes()[x] as char;
 }
 
+pub(crate) fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+// Abramowitz and Stegun 7.1.26, absolute error below 1.5e-7
+pub(crate) fn erf(x: f32) -> f32 {
+    let x = x as f64;
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let y = 1.0 - poly * (-x * x).exp();
+    (if x < 0.0 { -y } else { y }) as f32
+}
+
+pub(crate) fn write_wav<P: AsRef<std::path::Path>>(
+    path: P,
+    samples: &[f32],
+    sample_rate: u32,
+) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for &sample in samples {
+        writer.write_sample((sample.clamp(-1.0, 1.0) * 32767.0) as i16)?;
+    }
+    writer.finalize()
+}
+
+// Power of `bins` in Hann windowed frames of `fft_size` samples, `hop` apart
+pub(crate) fn power_spectrogram(
+    samples: &[f32],
+    fft_size: usize,
+    hop: usize,
+    bins: Range<usize>,
+) -> Result<Vec<Vec<f32>>, Error> {
+    let mut planner = RealFftPlanner::<f32>::new();
+    let plan = planner.plan_fft_forward(fft_size);
+    let mut input = plan.make_input_vec();
+    let mut spectrum = plan.make_output_vec();
+    let window = (0..fft_size)
+        .map(|i| 0.5 * (1.0 - (2.0 * PI * (i as f32) / ((fft_size - 1) as f32)).cos()))
+        .collect::<Vec<_>>();
+    let mut pwr = Vec::new();
+    for frame in samples.windows(fft_size).step_by(hop) {
+        zip(&mut input, zip(frame, &window)).for_each(|(i, (x, w))| *i = x * w);
+        plan.process(&mut input, &mut spectrum)?;
+        pwr.push(
+            spectrum[bins.clone()]
+                .iter()
+                .map(|x| x.norm_sqr())
+                .collect::<Vec<_>>(),
+        );
+    }
+    Ok(pwr)
+}
+
 pub(crate) fn optimal_leave_one_out_multiplication_7(mults: &[f32; 7]) -> [f32; 7] {
     let mut ret = [0.0; 7];
     let suffix_6 = mults[6];
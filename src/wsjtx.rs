@@ -0,0 +1,680 @@
+//! The WSJT-X UDP message protocol.
+//!
+//! WSJT-X publishes its state and decodes as UDP datagrams to companion applications
+//! such as GridTracker and JTAlert. [`UdpServer`] sends the same datagrams so those
+//! applications can follow a weaksignal decoder, and turns the commands they send
+//! back into [`Command`]s for the application. Every datagram is a Qt `QDataStream`:
+//! big endian integers, doubles, and strings as a `u32` byte length and UTF-8, with
+//! `0xffffffff` for a null string.
+
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::SystemTime,
+};
+
+use crate::{
+    message::Message,
+    utils::{from_unix_ms, unix_ms},
+    Error,
+};
+
+const MAGIC: u32 = 0xadbccbda;
+// Schema written into every datagram and announced in heartbeats, the one WSJT-X 2.x
+// uses. Status carries its trailing fields, so older schemas are only read
+const SCHEMA: u32 = 3;
+// Julian day of 1970-01-01, QDate is sent as a Julian day
+const UNIX_EPOCH_JULIAN_DAY: i64 = 2440588;
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+// WSJT-X uses these in place of the mode name in decodes
+pub const FT8_MODE: &str = "~";
+pub const FT4_MODE: &str = "+";
+pub const JT65_MODE: &str = "#";
+pub const DEFAULT_PORT: u16 = 2237;
+// Published decodes remembered so replies can be matched to their message
+const MAX_RECENT_DECODES: usize = 1000;
+
+/// Periodic announcement that the sender is alive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    pub max_schema: u32,
+    pub version: String,
+    pub revision: String,
+}
+
+/// Station state, sent whenever it changes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Status {
+    pub dial_frequency: u64,
+    pub mode: String,
+    pub dx_call: String,
+    pub report: String,
+    pub tx_mode: String,
+    pub tx_enabled: bool,
+    pub transmitting: bool,
+    pub decoding: bool,
+    pub rx_df: u32,
+    pub tx_df: u32,
+    pub de_call: String,
+    pub de_grid: String,
+    pub dx_grid: String,
+    pub tx_watchdog: bool,
+    pub sub_mode: String,
+    pub fast_mode: bool,
+    pub special_operation_mode: u8,
+    pub frequency_tolerance: u32,
+    pub tr_period: u32,
+    pub configuration_name: String,
+    pub tx_message: String,
+}
+
+/// One decoded message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decode {
+    // False when the decode is replayed rather than from the current cycle
+    pub new: bool,
+    // Start of the cycle in milliseconds since midnight UTC
+    pub time: u32,
+    pub snr: i32,
+    pub delta_time: f64,
+    pub delta_frequency: u32,
+    pub mode: String,
+    pub message: String,
+    pub low_confidence: bool,
+    pub off_air: bool,
+}
+
+/// Asks the receiver to clear its band activity (0), Rx frequency (1) or both (2) windows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clear {
+    pub window: Option<u8>,
+}
+
+/// Answers a decode as if it had been double clicked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub time: u32,
+    pub snr: i32,
+    pub delta_time: f64,
+    pub delta_frequency: u32,
+    pub mode: String,
+    pub message: String,
+    pub low_confidence: bool,
+    // Qt keyboard modifiers held while double clicking
+    pub modifiers: u8,
+}
+
+/// A completed QSO.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QsoLogged {
+    pub time_off: SystemTime,
+    pub dx_call: String,
+    pub dx_grid: String,
+    pub tx_frequency: u64,
+    pub mode: String,
+    pub report_sent: String,
+    pub report_received: String,
+    pub tx_power: String,
+    pub comments: String,
+    pub name: String,
+    pub time_on: SystemTime,
+    pub operator_call: String,
+    pub my_call: String,
+    pub my_grid: String,
+    pub exchange_sent: String,
+    pub exchange_received: String,
+    pub propagation_mode: String,
+}
+
+/// Stops transmitting now, or only disables auto Tx.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HaltTx {
+    pub auto_tx_only: bool,
+}
+
+/// Sets the free text message, and sends it next if `send` is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FreeText {
+    pub text: String,
+    pub send: bool,
+}
+
+/// Sets the station's Maidenhead locator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub location: String,
+}
+
+/// Changes the operating setup. Empty strings and `u32::MAX` leave a setting unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Configure {
+    pub mode: String,
+    pub frequency_tolerance: u32,
+    pub sub_mode: String,
+    pub fast_mode: bool,
+    pub tr_period: u32,
+    pub rx_df: u32,
+    pub dx_call: String,
+    pub dx_grid: String,
+    pub generate_messages: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Packet {
+    Heartbeat(Heartbeat),
+    Status(Status),
+    Decode(Decode),
+    Clear(Clear),
+    Reply(Reply),
+    QsoLogged(QsoLogged),
+    Close,
+    HaltTx(HaltTx),
+    FreeText(FreeText),
+    Location(Location),
+    Configure(Configure),
+}
+
+/// A command sent to the station by a companion application.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Answer a decode. `message` is the published message the reply refers to, `None`
+    /// when it was not published by this server or has been forgotten.
+    Reply {
+        reply: Reply,
+        message: Option<Message>,
+    },
+    Clear(Clear),
+    HaltTx(HaltTx),
+    FreeText(FreeText),
+    Location(Location),
+    Configure(Configure),
+}
+
+impl Decode {
+    /// Fills a decode packet from a message decoded in `mode`, such as [`FT8_MODE`],
+    /// found in the cycle starting at `cycle_start`.
+    pub fn from_message(message: &Message, mode: &str, cycle_start: SystemTime) -> Self {
+        Self {
+            new: true,
+            time: time_of_day_ms(cycle_start),
+            snr: message.snr().round() as i32,
+            delta_time: message.dt_seconds() as f64,
+            delta_frequency: message.freq_bin_hz,
+            mode: mode.to_string(),
+            message: message.data.to_string().trim().to_string(),
+            // WSJT-X flags decodes that needed assumed bits the same way
+            low_confidence: message.ap_pass.is_some(),
+            off_air: false,
+        }
+    }
+}
+
+impl Packet {
+    fn type_id(&self) -> u32 {
+        match self {
+            Packet::Heartbeat(_) => 0,
+            Packet::Status(_) => 1,
+            Packet::Decode(_) => 2,
+            Packet::Clear(_) => 3,
+            Packet::Reply(_) => 4,
+            Packet::QsoLogged(_) => 5,
+            Packet::Close => 6,
+            Packet::HaltTx(_) => 8,
+            Packet::FreeText(_) => 9,
+            Packet::Location(_) => 11,
+            Packet::Configure(_) => 15,
+        }
+    }
+
+    /// Serializes the packet as sent by the application named `id`.
+    pub fn to_bytes(&self, id: &str) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.u32(MAGIC);
+        w.u32(SCHEMA);
+        w.u32(self.type_id());
+        w.string(id);
+        match self {
+            Packet::Heartbeat(p) => {
+                w.u32(p.max_schema);
+                w.string(&p.version);
+                w.string(&p.revision);
+            }
+            Packet::Status(p) => {
+                w.u64(p.dial_frequency);
+                w.string(&p.mode);
+                w.string(&p.dx_call);
+                w.string(&p.report);
+                w.string(&p.tx_mode);
+                w.bool(p.tx_enabled);
+                w.bool(p.transmitting);
+                w.bool(p.decoding);
+                w.u32(p.rx_df);
+                w.u32(p.tx_df);
+                w.string(&p.de_call);
+                w.string(&p.de_grid);
+                w.string(&p.dx_grid);
+                w.bool(p.tx_watchdog);
+                w.string(&p.sub_mode);
+                w.bool(p.fast_mode);
+                w.u8(p.special_operation_mode);
+                w.u32(p.frequency_tolerance);
+                w.u32(p.tr_period);
+                w.string(&p.configuration_name);
+                w.string(&p.tx_message);
+            }
+            Packet::Decode(p) => {
+                w.bool(p.new);
+                w.u32(p.time);
+                w.i32(p.snr);
+                w.f64(p.delta_time);
+                w.u32(p.delta_frequency);
+                w.string(&p.mode);
+                w.string(&p.message);
+                w.bool(p.low_confidence);
+                w.bool(p.off_air);
+            }
+            Packet::Clear(p) => {
+                if let Some(window) = p.window {
+                    w.u8(window);
+                }
+            }
+            Packet::Reply(p) => {
+                w.u32(p.time);
+                w.i32(p.snr);
+                w.f64(p.delta_time);
+                w.u32(p.delta_frequency);
+                w.string(&p.mode);
+                w.string(&p.message);
+                w.bool(p.low_confidence);
+                w.u8(p.modifiers);
+            }
+            Packet::QsoLogged(p) => {
+                w.date_time(p.time_off);
+                w.string(&p.dx_call);
+                w.string(&p.dx_grid);
+                w.u64(p.tx_frequency);
+                w.string(&p.mode);
+                w.string(&p.report_sent);
+                w.string(&p.report_received);
+                w.string(&p.tx_power);
+                w.string(&p.comments);
+                w.string(&p.name);
+                w.date_time(p.time_on);
+                w.string(&p.operator_call);
+                w.string(&p.my_call);
+                w.string(&p.my_grid);
+                w.string(&p.exchange_sent);
+                w.string(&p.exchange_received);
+                w.string(&p.propagation_mode);
+            }
+            Packet::Close => {}
+            Packet::HaltTx(p) => w.bool(p.auto_tx_only),
+            Packet::FreeText(p) => {
+                w.string(&p.text);
+                w.bool(p.send);
+            }
+            Packet::Location(p) => w.string(&p.location),
+            Packet::Configure(p) => {
+                w.string(&p.mode);
+                w.u32(p.frequency_tolerance);
+                w.string(&p.sub_mode);
+                w.bool(p.fast_mode);
+                w.u32(p.tr_period);
+                w.u32(p.rx_df);
+                w.string(&p.dx_call);
+                w.string(&p.dx_grid);
+                w.bool(p.generate_messages);
+            }
+        }
+        w.0
+    }
+
+    /// Parses a datagram into the sender id and the packet.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(String, Packet), Error> {
+        let mut r = Reader { bytes, pos: 0 };
+        if r.u32("magic")? != MAGIC {
+            return Err(Error::MalformedPacket("not a WSJT-X datagram".to_string()));
+        }
+        let schema = r.u32("schema")?;
+        if schema > SCHEMA {
+            return Err(Error::MalformedPacket(format!(
+                "unsupported schema {}",
+                schema
+            )));
+        }
+        let type_id = r.u32("message type")?;
+        let id = r.string("id")?;
+        let packet = match type_id {
+            0 => Packet::Heartbeat(Heartbeat {
+                max_schema: r.u32("maximum schema")?,
+                version: r.string("version")?,
+                revision: r.string("revision")?,
+            }),
+            1 => Packet::Status(Status {
+                dial_frequency: r.u64("dial frequency")?,
+                mode: r.string("mode")?,
+                dx_call: r.string("DX call")?,
+                report: r.string("report")?,
+                tx_mode: r.string("Tx mode")?,
+                tx_enabled: r.bool("Tx enabled")?,
+                transmitting: r.bool("transmitting")?,
+                decoding: r.bool("decoding")?,
+                rx_df: r.u32("Rx DF")?,
+                tx_df: r.u32("Tx DF")?,
+                de_call: r.string("DE call")?,
+                de_grid: r.string("DE grid")?,
+                dx_grid: r.string("DX grid")?,
+                tx_watchdog: r.bool("Tx watchdog")?,
+                sub_mode: r.string("sub-mode")?,
+                fast_mode: r.bool("fast mode")?,
+                special_operation_mode: r.u8("special operation mode")?,
+                frequency_tolerance: r.u32("frequency tolerance")?,
+                tr_period: r.u32("T/R period")?,
+                configuration_name: r.string("configuration name")?,
+                tx_message: r.string("Tx message")?,
+            }),
+            2 => Packet::Decode(Decode {
+                new: r.bool("new")?,
+                time: r.u32("time")?,
+                snr: r.i32("SNR")?,
+                delta_time: r.f64("delta time")?,
+                delta_frequency: r.u32("delta frequency")?,
+                mode: r.string("mode")?,
+                message: r.string("message")?,
+                low_confidence: r.bool("low confidence")?,
+                off_air: r.bool("off air")?,
+            }),
+            // The window is only sent to WSJT-X, older senders omit it
+            3 => Packet::Clear(Clear {
+                window: r.remaining().then(|| r.u8("window")).transpose()?,
+            }),
+            4 => Packet::Reply(Reply {
+                time: r.u32("time")?,
+                snr: r.i32("SNR")?,
+                delta_time: r.f64("delta time")?,
+                delta_frequency: r.u32("delta frequency")?,
+                mode: r.string("mode")?,
+                message: r.string("message")?,
+                low_confidence: r.bool("low confidence")?,
+                modifiers: r.u8("modifiers")?,
+            }),
+            5 => Packet::QsoLogged(QsoLogged {
+                time_off: r.date_time("time off")?,
+                dx_call: r.string("DX call")?,
+                dx_grid: r.string("DX grid")?,
+                tx_frequency: r.u64("Tx frequency")?,
+                mode: r.string("mode")?,
+                report_sent: r.string("report sent")?,
+                report_received: r.string("report received")?,
+                tx_power: r.string("Tx power")?,
+                comments: r.string("comments")?,
+                name: r.string("name")?,
+                time_on: r.date_time("time on")?,
+                operator_call: r.string("operator call")?,
+                my_call: r.string("my call")?,
+                my_grid: r.string("my grid")?,
+                exchange_sent: r.string("exchange sent")?,
+                exchange_received: r.string("exchange received")?,
+                propagation_mode: r.string("propagation mode")?,
+            }),
+            6 => Packet::Close,
+            8 => Packet::HaltTx(HaltTx {
+                auto_tx_only: r.bool("auto Tx only")?,
+            }),
+            9 => Packet::FreeText(FreeText {
+                text: r.string("text")?,
+                send: r.bool("send")?,
+            }),
+            11 => Packet::Location(Location {
+                location: r.string("location")?,
+            }),
+            15 => Packet::Configure(Configure {
+                mode: r.string("mode")?,
+                frequency_tolerance: r.u32("frequency tolerance")?,
+                sub_mode: r.string("sub-mode")?,
+                fast_mode: r.bool("fast mode")?,
+                tr_period: r.u32("T/R period")?,
+                rx_df: r.u32("Rx DF")?,
+                dx_call: r.string("DX call")?,
+                dx_grid: r.string("DX grid")?,
+                generate_messages: r.bool("generate messages")?,
+            }),
+            n => {
+                return Err(Error::MalformedPacket(format!(
+                    "unknown message type {}",
+                    n
+                )))
+            }
+        };
+        Ok((id, packet))
+    }
+}
+
+/// Publishes packets to a WSJT-X protocol listener under the application name `id`.
+pub struct UdpServer {
+    socket: UdpSocket,
+    id: String,
+    recent: VecDeque<(Decode, Message)>,
+}
+
+impl UdpServer {
+    /// Sends from an ephemeral local port to `target`, usually `127.0.0.1:2237`.
+    pub fn new<A: ToSocketAddrs>(id: &str, target: A) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(target)?;
+        // Commands are polled between decodes, so the socket never blocks
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            id: id.to_string(),
+            recent: VecDeque::new(),
+        })
+    }
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    /// The address commands are received on.
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.socket.local_addr()?)
+    }
+    pub fn send(&self, packet: &Packet) -> Result<(), Error> {
+        self.socket.send(&packet.to_bytes(&self.id))?;
+        Ok(())
+    }
+    pub fn heartbeat(&self) -> Result<(), Error> {
+        self.send(&Packet::Heartbeat(Heartbeat {
+            max_schema: SCHEMA,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            revision: String::new(),
+        }))
+    }
+    /// Sends a decode packet for every message decoded in `mode` in the cycle starting
+    /// at `cycle_start`.
+    pub fn publish(
+        &mut self,
+        messages: &[Message],
+        mode: &str,
+        cycle_start: SystemTime,
+    ) -> Result<(), Error> {
+        for message in messages {
+            let decode = Decode::from_message(message, mode, cycle_start);
+            self.send(&Packet::Decode(decode.clone()))?;
+            if self.recent.len() == MAX_RECENT_DECODES {
+                self.recent.pop_front();
+            }
+            self.recent.push_back((decode, message.clone()));
+        }
+        Ok(())
+    }
+    /// Returns the next pending command, `None` once there are none left. Datagrams
+    /// addressed to another id, packets that are not commands and message types this
+    /// crate does not parse are skipped, only socket failures are errors.
+    pub fn poll(&mut self) -> Result<Option<Command>, Error> {
+        let mut buf = [0; 2048];
+        loop {
+            let len = match self.socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            // GridTracker and JTAlert also send Replay, LoggedADIF, HighlightCallsign and
+            // SwitchConfiguration, which must not end the caller's polling loop
+            let Ok((id, packet)) = Packet::from_bytes(&buf[..len]) else {
+                continue;
+            };
+            if id != self.id {
+                continue;
+            }
+            let command = match packet {
+                Packet::Reply(reply) => {
+                    let message = self.find_decode(&reply).cloned();
+                    Command::Reply { reply, message }
+                }
+                Packet::Clear(clear) => Command::Clear(clear),
+                Packet::HaltTx(halt) => Command::HaltTx(halt),
+                Packet::FreeText(text) => Command::FreeText(text),
+                Packet::Location(location) => Command::Location(location),
+                Packet::Configure(configure) => Command::Configure(configure),
+                _ => continue,
+            };
+            return Ok(Some(command));
+        }
+    }
+    // Replies echo the decode they answer, the newest matching decode wins
+    fn find_decode(&self, reply: &Reply) -> Option<&Message> {
+        self.recent
+            .iter()
+            .rev()
+            .find(|(decode, _)| {
+                decode.time == reply.time
+                    && decode.delta_frequency == reply.delta_frequency
+                    && decode.message == reply.message
+            })
+            .map(|(_, message)| message)
+    }
+}
+
+impl Drop for UdpServer {
+    fn drop(&mut self) {
+        // Listeners drop the sender from their lists on close, nothing to do if it is gone
+        let _ = self.send(&Packet::Close);
+    }
+}
+
+/// Milliseconds since midnight UTC, the QTime WSJT-X sends with decodes.
+pub fn time_of_day_ms(time: SystemTime) -> u32 {
+    unix_ms(time).rem_euclid(DAY_MS) as u32
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, x: u8) {
+        self.0.push(x);
+    }
+    fn bool(&mut self, x: bool) {
+        self.u8(x as u8);
+    }
+    fn u32(&mut self, x: u32) {
+        self.0.extend(x.to_be_bytes());
+    }
+    fn i32(&mut self, x: i32) {
+        self.0.extend(x.to_be_bytes());
+    }
+    fn u64(&mut self, x: u64) {
+        self.0.extend(x.to_be_bytes());
+    }
+    fn i64(&mut self, x: i64) {
+        self.0.extend(x.to_be_bytes());
+    }
+    fn f64(&mut self, x: f64) {
+        self.0.extend(x.to_be_bytes());
+    }
+    fn string(&mut self, x: &str) {
+        self.u32(x.len() as u32);
+        self.0.extend(x.as_bytes());
+    }
+    // QDateTime as a Julian day, milliseconds into the day and a UTC time spec
+    fn date_time(&mut self, time: SystemTime) {
+        let ms = unix_ms(time);
+        self.i64(ms.div_euclid(DAY_MS) + UNIX_EPOCH_JULIAN_DAY);
+        self.u32(ms.rem_euclid(DAY_MS) as u32);
+        self.u8(1);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self, field: &str) -> Result<[u8; N], Error> {
+        let bytes = self
+            .bytes
+            .get(self.pos..)
+            .and_then(|rest| rest.first_chunk::<N>())
+            .ok_or_else(|| Error::MalformedPacket(format!("truncated at {}", field)))?;
+        self.pos += N;
+        Ok(*bytes)
+    }
+    fn remaining(&self) -> bool {
+        self.pos < self.bytes.len()
+    }
+    fn u8(&mut self, field: &str) -> Result<u8, Error> {
+        Ok(self.take::<1>(field)?[0])
+    }
+    fn bool(&mut self, field: &str) -> Result<bool, Error> {
+        Ok(self.u8(field)? != 0)
+    }
+    fn u32(&mut self, field: &str) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.take(field)?))
+    }
+    fn i32(&mut self, field: &str) -> Result<i32, Error> {
+        Ok(i32::from_be_bytes(self.take(field)?))
+    }
+    fn u64(&mut self, field: &str) -> Result<u64, Error> {
+        Ok(u64::from_be_bytes(self.take(field)?))
+    }
+    fn i64(&mut self, field: &str) -> Result<i64, Error> {
+        Ok(i64::from_be_bytes(self.take(field)?))
+    }
+    fn f64(&mut self, field: &str) -> Result<f64, Error> {
+        Ok(f64::from_be_bytes(self.take(field)?))
+    }
+    // Null strings read as empty
+    fn string(&mut self, field: &str) -> Result<String, Error> {
+        let len = self.u32(field)?;
+        if len == u32::MAX {
+            return Ok(String::new());
+        }
+        let bytes = self
+            .bytes
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len as usize))
+            .ok_or_else(|| Error::MalformedPacket(format!("truncated at {}", field)))?;
+        self.pos += len as usize;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| Error::MalformedPacket(format!("{} is not UTF-8", field)))
+    }
+    fn date_time(&mut self, field: &str) -> Result<SystemTime, Error> {
+        let day = self.i64(field)?;
+        let ms = self.u32(field)? as i64;
+        // Local time is taken as UTC, WSJT-X always sends UTC
+        let offset_s = match self.u8(field)? {
+            2 => self.i32(field)? as i64,
+            3 => {
+                self.string(field)?;
+                0
+            }
+            _ => 0,
+        };
+        day.checked_sub(UNIX_EPOCH_JULIAN_DAY)
+            .and_then(|days| days.checked_mul(DAY_MS))
+            .and_then(|days_ms| days_ms.checked_add(ms - offset_s * 1000))
+            .and_then(from_unix_ms)
+            .ok_or_else(|| Error::MalformedPacket(format!("{} is out of range", field)))
+    }
+}
@@ -0,0 +1,130 @@
+// The K=32, rate 1/2 convolutional code WSPR uses, decoded with Karn's Fano
+// sequential decoder since a Viterbi decoder would need 2^31 states
+
+// Generator polynomials, the first gives the first bit of each pair
+const POLY1: u32 = 0xf2d05351;
+const POLY2: u32 = 0xe4613c47;
+// Zero bits flushing the encoder after the message
+pub(crate) const TAIL_BITS: usize = 31;
+// Branch metrics are scaled to integers, the bias is just under the code rate
+const METRIC_SCALE: f32 = 10.0;
+const METRIC_BIAS: f32 = 0.45;
+// Step the threshold moves by
+const DELTA: i64 = 60;
+
+// The two code bits for the register, newest bit lowest
+fn code_bits(state: u32) -> usize {
+    (((state & POLY1).count_ones() & 1) << 1 | (state & POLY2).count_ones() & 1) as usize
+}
+
+pub(crate) fn conv_encode(bits: &[bool]) -> Vec<bool> {
+    let mut state = 0u32;
+    bits.iter()
+        .flat_map(|&bit| {
+            state = (state << 1) | bit as u32;
+            let code = code_bits(state);
+            [code & 2 != 0, code & 1 != 0]
+        })
+        .collect()
+}
+
+#[derive(Clone, Default)]
+struct Node {
+    state: u32,
+    gamma: i64,
+    // Metric of each pair of code bits, indexed like code_bits
+    metrics: [i32; 4],
+    // Metrics of the better and worse branch, and which one is being followed
+    branches: [i32; 2],
+    branch: usize,
+}
+
+// Fano metric of a code bit, `p` is the probability that it is set
+fn bit_metric(p: f32, bit: bool) -> i32 {
+    let p = if bit { p } else { 1.0 - p }.clamp(0.005, 0.995);
+    (METRIC_SCALE * ((2.0 * p).log2() - METRIC_BIAS)).round() as i32
+}
+
+// Decodes the bits behind `probabilities`, two per bit and ending with the tail.
+// Gives up after `max_cycles` node visits per bit
+pub(crate) fn fano_decode(probabilities: &[f32], max_cycles: usize) -> Option<Vec<bool>> {
+    let nbits = probabilities.len() / 2;
+    if nbits <= TAIL_BITS {
+        return None;
+    }
+    let tail = nbits - TAIL_BITS;
+    let mut nodes = probabilities
+        .chunks_exact(2)
+        .map(|p| {
+            let mut node = Node::default();
+            for (code, metric) in node.metrics.iter_mut().enumerate() {
+                *metric = bit_metric(p[0], code & 2 != 0) + bit_metric(p[1], code & 1 != 0);
+            }
+            node
+        })
+        .collect::<Vec<_>>();
+    nodes.push(Node::default());
+
+    // Orders the branches out of node n, the state's low bit is set when 1 is better
+    let start = |node: &mut Node, n: usize| {
+        let code = code_bits(node.state);
+        if n >= tail {
+            node.branches = [node.metrics[code], 0];
+        } else {
+            let (m0, m1) = (node.metrics[code], node.metrics[3 ^ code]);
+            if m0 > m1 {
+                node.branches = [m0, m1];
+            } else {
+                node.branches = [m1, m0];
+                node.state += 1;
+            }
+        }
+        node.branch = 0;
+    };
+    let mut n = 0;
+    let mut threshold = 0;
+    start(&mut nodes[0], 0);
+    for _ in 0..max_cycles * nbits {
+        let gamma = nodes[n].gamma + nodes[n].branches[nodes[n].branch] as i64;
+        if gamma >= threshold {
+            // Tighten the threshold on the first visit to a node
+            if nodes[n].gamma < threshold + DELTA {
+                while gamma >= threshold + DELTA {
+                    threshold += DELTA;
+                }
+            }
+            nodes[n + 1].gamma = gamma;
+            nodes[n + 1].state = nodes[n].state << 1;
+            n += 1;
+            if n == nbits {
+                return Some(
+                    nodes[..nbits]
+                        .iter()
+                        .map(|node| node.state & 1 == 1)
+                        .collect(),
+                );
+            }
+            start(&mut nodes[n], n);
+            continue;
+        }
+        loop {
+            if n == 0 || nodes[n - 1].gamma < threshold {
+                // Nowhere to back up to, loosen the threshold and try again
+                threshold -= DELTA;
+                if nodes[n].branch != 0 {
+                    nodes[n].branch = 0;
+                    nodes[n].state ^= 1;
+                }
+                break;
+            }
+            n -= 1;
+            // Tail bits are known to be zero so have no second branch
+            if n < tail && nodes[n].branch != 1 {
+                nodes[n].branch += 1;
+                nodes[n].state ^= 1;
+                break;
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,426 @@
+use std::fmt::Display;
+
+use crate::{
+    callsignmap::CallsignMap,
+    message::{CallsignError, GridError},
+    utils::char_lookup,
+    Error,
+};
+
+pub(crate) const CHARS37: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ ";
+const CHARS36: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const CHARS27: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ ";
+// Packed callsigns at or above this are not callsigns
+pub(crate) const MAX_CALLSIGN: u32 = 37 * 36 * 10 * 27 * 27 * 27;
+// Type 2 messages send a prefix below this and a suffix above it
+const SUFFIX_BASE: u32 = 60000;
+
+/// A 50 bit WSPR message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WsprMessage {
+    /// Type 1: a standard callsign, 4 character locator and power.
+    Standard {
+        callsign: String,
+        grid: String,
+        power_dbm: u8,
+    },
+    /// Type 2: a callsign with a prefix or suffix and power, sent without a locator.
+    Compound { callsign: String, power_dbm: u8 },
+    /// Type 3: a 15 bit callsign hash, 6 character locator and power. `callsign` is
+    /// set when the hash was resolved.
+    Hashed {
+        callsign: Option<String>,
+        hash: u16,
+        grid: String,
+        power_dbm: u8,
+    },
+}
+
+impl WsprMessage {
+    pub fn from_bits(message: &[bool; 50], callsign_map: &CallsignMap) -> Result<Self, Error> {
+        let value = |range: std::ops::Range<usize>| {
+            message[range]
+                .iter()
+                .fold(0u32, |acc, &bit| (acc << 1) | bit as u32)
+        };
+        let n = value(0..28);
+        let grid = value(28..43);
+        let ntype = value(43..50) as i32 - 64;
+        let invalid_power = |value: i32| Error::InvalidField {
+            field: "power",
+            value: value as u64,
+        };
+        if ntype < 0 {
+            let power_dbm = -(ntype + 1);
+            if !is_power(power_dbm) {
+                return Err(invalid_power(power_dbm));
+            }
+            // The locator is sent rotated one place left in the callsign field
+            let rotated = unpack_callsign(n, false)?;
+            let grid6 = format!("{}{}", &rotated[5..], &rotated[..5]);
+            if !is_grid6(&grid6) {
+                return Err(Error::InvalidField {
+                    field: "locator",
+                    value: n as u64,
+                });
+            }
+            let hash = grid as u16;
+            return Ok(Self::Hashed {
+                callsign: callsign_map.get_15(hash).cloned(),
+                hash,
+                grid: grid6,
+                power_dbm: power_dbm as u8,
+            });
+        }
+        let callsign = unpack_callsign(n, true)?;
+        if is_power(ntype) {
+            return Ok(Self::Standard {
+                callsign,
+                grid: unpack_grid(grid)?,
+                power_dbm: ntype as u8,
+            });
+        }
+        // The power is offset by which 32768 block the prefix or suffix is in
+        let nadd = match ntype % 10 {
+            nu @ 1..=2 => nu,
+            nu @ 4..=5 => nu - 3,
+            nu @ 8..=9 => nu - 7,
+            _ => return Err(invalid_power(ntype)),
+        };
+        let power_dbm = ntype - nadd;
+        if !is_power(power_dbm) {
+            return Err(invalid_power(ntype));
+        }
+        let affix = grid + 32768 * (nadd as u32 - 1);
+        let callsign = if affix < SUFFIX_BASE {
+            let prefix = (0..3)
+                .rev()
+                .map(|i| char_lookup((affix / 37u32.pow(i) % 37) as usize, CHARS37))
+                .collect::<String>();
+            format!("{}/{}", prefix.trim(), callsign)
+        } else {
+            let suffix = match affix - SUFFIX_BASE {
+                nc @ 0..=35 => char_lookup(nc as usize, CHARS36).to_string(),
+                nc @ 36..=125 => (nc - 26).to_string(),
+                _ => {
+                    return Err(Error::InvalidField {
+                        field: "suffix",
+                        value: affix as u64,
+                    })
+                }
+            };
+            format!("{}/{}", callsign, suffix)
+        };
+        Ok(Self::Compound {
+            callsign,
+            power_dbm: power_dbm as u8,
+        })
+    }
+
+    /// Parses the text form, as in `K1ABC FN42 37`, `PJ4/K1ABC 37` or
+    /// `<PJ4/K1ABC> FN42AX 37`.
+    pub fn from_text(text: &str) -> Option<Self> {
+        let text = text.trim().to_uppercase();
+        let words = text.split_whitespace().collect::<Vec<_>>();
+        let power_dbm = words.last()?.parse::<u8>().ok()?;
+        let message = match words[..] {
+            [callsign, grid, _] if callsign.starts_with('<') => {
+                let callsign = callsign.strip_prefix('<')?.strip_suffix('>')?;
+                Self::Hashed {
+                    callsign: Some(callsign.to_string()),
+                    hash: CallsignMap::hash15(callsign),
+                    grid: grid.to_string(),
+                    power_dbm,
+                }
+            }
+            [callsign, grid, _] => Self::Standard {
+                callsign: callsign.to_string(),
+                grid: grid.to_string(),
+                power_dbm,
+            },
+            [callsign, _] => Self::Compound {
+                callsign: callsign.to_string(),
+                power_dbm,
+            },
+            _ => return None,
+        };
+        message.to_bits().is_ok().then_some(message)
+    }
+
+    /// The messages a beacon sends in turn. A standard callsign with a 4 character
+    /// locator fits one type 1 message, a 6 character locator or a compound callsign
+    /// adds a type 3 message carrying the 6 character locator.
+    pub fn beacon(callsign: &str, grid: &str, power_dbm: u8) -> Result<Vec<Self>, Error> {
+        let (callsign, grid) = (callsign.to_uppercase(), grid.to_uppercase());
+        let hashed = || Self::Hashed {
+            callsign: Some(callsign.clone()),
+            hash: CallsignMap::hash15(&callsign),
+            grid: grid.clone(),
+            power_dbm,
+        };
+        let messages = match (callsign.contains('/'), grid.len()) {
+            (false, 4) => vec![Self::Standard {
+                callsign: callsign.clone(),
+                grid: grid.clone(),
+                power_dbm,
+            }],
+            (false, 6) => vec![
+                Self::Standard {
+                    callsign: callsign.clone(),
+                    grid: grid[..4].to_string(),
+                    power_dbm,
+                },
+                hashed(),
+            ],
+            (true, 6) => vec![
+                Self::Compound {
+                    callsign: callsign.clone(),
+                    power_dbm,
+                },
+                hashed(),
+            ],
+            // The locator of a compound callsign goes in a type 3 message
+            _ => return Err(GridError::InvalidLocator(grid.clone()).into()),
+        };
+        for message in &messages {
+            message.to_bits()?;
+        }
+        Ok(messages)
+    }
+
+    pub fn to_bits(&self) -> Result<[bool; 50], Error> {
+        let invalid_power = |power_dbm: u8| Error::InvalidField {
+            field: "power",
+            value: power_dbm as u64,
+        };
+        if !is_power(self.power_dbm() as i32) {
+            return Err(invalid_power(self.power_dbm()));
+        }
+        let (n, m) = match self {
+            Self::Standard {
+                callsign,
+                grid,
+                power_dbm,
+            } => (
+                pack_callsign(callsign)?,
+                pack_grid(grid)? * 128 + *power_dbm as u32 + 64,
+            ),
+            Self::Compound {
+                callsign,
+                power_dbm,
+            } => {
+                let (base, affix) = pack_compound(callsign)?;
+                // Which 32768 block the prefix or suffix is in is added to the power
+                let nadd = affix / 32768 + 1;
+                (
+                    pack_callsign(base)?,
+                    (affix % 32768) * 128 + *power_dbm as u32 + nadd + 64,
+                )
+            }
+            Self::Hashed {
+                hash,
+                grid,
+                power_dbm,
+                ..
+            } => {
+                if !is_grid6(grid) {
+                    return Err(GridError::InvalidLocator(grid.clone()).into());
+                }
+                let rotated = format!("{}{}", &grid[1..], &grid[..1]);
+                (
+                    pack_callsign(&rotated)?,
+                    (*hash as u32 & 0x7fff) * 128 + 64 - (*power_dbm as u32 + 1),
+                )
+            }
+        };
+        let packed = (n as u64) << 22 | m as u64;
+        let mut bits = [false; 50];
+        bits.iter_mut()
+            .enumerate()
+            .for_each(|(i, bit)| *bit = packed >> (49 - i) & 1 == 1);
+        Ok(bits)
+    }
+
+    /// The sender, `None` for a hash that was not resolved.
+    pub fn callsign(&self) -> Option<&str> {
+        match self {
+            Self::Standard { callsign, .. } | Self::Compound { callsign, .. } => Some(callsign),
+            Self::Hashed { callsign, .. } => callsign.as_deref(),
+        }
+    }
+
+    pub fn grid(&self) -> Option<&str> {
+        match self {
+            Self::Standard { grid, .. } | Self::Hashed { grid, .. } => Some(grid),
+            Self::Compound { .. } => None,
+        }
+    }
+
+    pub fn power_dbm(&self) -> u8 {
+        match self {
+            Self::Standard { power_dbm, .. }
+            | Self::Compound { power_dbm, .. }
+            | Self::Hashed { power_dbm, .. } => *power_dbm,
+        }
+    }
+}
+
+impl Display for WsprMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Standard {
+                callsign,
+                grid,
+                power_dbm,
+            } => write!(f, "{} {} {}", callsign, grid, power_dbm),
+            Self::Compound {
+                callsign,
+                power_dbm,
+            } => write!(f, "{} {}", callsign, power_dbm),
+            Self::Hashed {
+                callsign,
+                grid,
+                power_dbm,
+                ..
+            } => write!(
+                f,
+                "<{}> {} {}",
+                callsign.as_deref().unwrap_or("..."),
+                grid,
+                power_dbm
+            ),
+        }
+    }
+}
+
+// WSPR only sends powers ending in 0, 3 or 7 dBm
+fn is_power(dbm: i32) -> bool {
+    (0..=60).contains(&dbm) && matches!(dbm % 10, 0 | 3 | 7)
+}
+
+fn is_grid6(grid: &str) -> bool {
+    let bytes = grid.as_bytes();
+    bytes.len() == 6
+        && bytes[..2].iter().all(|c| (b'A'..=b'R').contains(c))
+        && bytes[2..4].iter().all(u8::is_ascii_digit)
+        && bytes[4..].iter().all(|c| (b'A'..=b'X').contains(c))
+}
+
+// Six characters with a digit third, a space is added in front of calls like K1ABC
+pub(crate) fn pack_callsign(callsign: &str) -> Result<u32, Error> {
+    let not_standard = || Error::from(CallsignError::NotStandard(callsign.to_string()));
+    let bytes = callsign.as_bytes();
+    let padded = match bytes {
+        [_, _, c, ..] if c.is_ascii_digit() => format!("{:<6}", callsign),
+        [_, c, ..] if c.is_ascii_digit() => format!(" {:<5}", callsign),
+        _ => return Err(not_standard()),
+    };
+    if padded.len() != 6 {
+        return Err(not_standard());
+    }
+    let index = |c: char, charset: &str| charset.find(c).ok_or_else(not_standard);
+    let mut chars = padded.chars();
+    let mut n = index(chars.next().unwrap(), CHARS37)? as u32;
+    n = n * 36 + index(chars.next().unwrap(), CHARS36)? as u32;
+    let digit = chars.next().unwrap();
+    if !digit.is_ascii_digit() {
+        return Err(not_standard());
+    }
+    n = n * 10 + index(digit, CHARS36)? as u32;
+    for c in chars {
+        n = n * 27 + index(c, CHARS27)? as u32;
+    }
+    Ok(n)
+}
+
+// The base callsign and the value sent for a 1 to 3 character prefix or a
+// 1 or 2 character suffix
+fn pack_compound(callsign: &str) -> Result<(&str, u32), Error> {
+    let not_standard = || Error::from(CallsignError::NotStandard(callsign.to_string()));
+    let (first, second) = callsign.split_once('/').ok_or_else(not_standard)?;
+    let is_alphanumeric = |text: &str| text.bytes().all(|c| c.is_ascii_alphanumeric());
+    if !is_alphanumeric(first) || !is_alphanumeric(second) {
+        return Err(not_standard());
+    }
+    let suffix = match second.as_bytes() {
+        [c] => Some(CHARS36.find(*c as char).ok_or_else(not_standard)? as u32),
+        [a, b] if a.is_ascii_digit() && b.is_ascii_digit() => {
+            Some(second.parse::<u32>().unwrap() + 26)
+        }
+        _ => None,
+    };
+    match suffix {
+        // A one character prefix would read as a suffix if it were on the right
+        Some(suffix) if first.len() > 3 || pack_callsign(first).is_ok() => {
+            Ok((first, SUFFIX_BASE + suffix))
+        }
+        _ if (1..=3).contains(&first.len()) => {
+            let prefix = format!("{:>3}", first)
+                .chars()
+                .try_fold(0, |acc, c| Some(acc * 37 + CHARS37.find(c)? as u32))
+                .ok_or_else(not_standard)?;
+            Ok((second, prefix))
+        }
+        _ => Err(not_standard()),
+    }
+}
+
+pub(crate) fn pack_grid(grid: &str) -> Result<u32, Error> {
+    let c = grid.as_bytes();
+    let valid = c.len() == 4
+        && (b'A'..=b'R').contains(&c[0])
+        && (b'A'..=b'R').contains(&c[1])
+        && c[2].is_ascii_digit()
+        && c[3].is_ascii_digit();
+    if !valid {
+        return Err(GridError::InvalidLocator(grid.to_string()).into());
+    }
+    let longitude = (c[0] - b'A') as u32 * 10 + (c[2] - b'0') as u32;
+    let latitude = (c[1] - b'A') as u32 * 10 + (c[3] - b'0') as u32;
+    Ok((179 - longitude) * 180 + latitude)
+}
+
+// Six characters with a digit third, spaces trimmed unless `trim` is false
+pub(crate) fn unpack_callsign(n: u32, trim: bool) -> Result<String, Error> {
+    if n >= MAX_CALLSIGN {
+        return Err(Error::InvalidField {
+            field: "callsign",
+            value: n as u64,
+        });
+    }
+    let mut n = n;
+    let mut chars = [' '; 6];
+    for i in (3..6).rev() {
+        chars[i] = char_lookup((n % 27) as usize, CHARS27);
+        n /= 27;
+    }
+    chars[2] = char_lookup((n % 10) as usize, CHARS36);
+    n /= 10;
+    chars[1] = char_lookup((n % 36) as usize, CHARS36);
+    chars[0] = char_lookup((n / 36) as usize, CHARS37);
+    let callsign = chars.iter().collect::<String>();
+    Ok(if trim {
+        callsign.trim().to_string()
+    } else {
+        callsign
+    })
+}
+
+pub(crate) fn unpack_grid(grid: u32) -> Result<String, Error> {
+    if grid >= 180 * 180 {
+        return Err(Error::InvalidField {
+            field: "locator",
+            value: grid as u64,
+        });
+    }
+    let (longitude, latitude) = (179 - grid / 180, grid % 180);
+    Ok([
+        (b'A' + (longitude / 10) as u8) as char,
+        (b'A' + (latitude / 10) as u8) as char,
+        (b'0' + (longitude % 10) as u8) as char,
+        (b'0' + (latitude % 10) as u8) as char,
+    ]
+    .iter()
+    .collect())
+}
@@ -0,0 +1,489 @@
+//! WSPR-2 and WSPR-15 encoding and decoding.
+//!
+//! A WSPR transmission is 162 4-FSK symbols, each the sum of a sync bit from a fixed
+//! pseudo-random vector and twice a data bit. The data bits are a 50 bit
+//! [`WsprMessage`] and a zero tail, convolutionally encoded at rate 1/2 with K=32
+//! and interleaved in bit reversed order. WSPR-15 is the same signal eight times
+//! slower.
+
+use std::{collections::HashMap, f32::consts::PI, fmt::Display, iter::zip, path::Path};
+
+use realfft::RealFftPlanner;
+
+use crate::{
+    callsignmap::CallsignMap,
+    constants::WSPR_SYNC,
+    utils::{sigmoid, write_wav},
+    Error,
+};
+
+use self::fano::{conv_encode, fano_decode, TAIL_BITS};
+
+pub use self::message::WsprMessage;
+
+mod fano;
+pub(crate) mod message;
+
+const WSPR_SYMBOLS: usize = 162;
+// WSPR reports DT relative to a transmission starting this long into the cycle
+const NOMINAL_START: f32 = 1.0;
+// Scales normalised tone amplitude differences to bit log likelihoods
+const SOFT_SCALE: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WsprMode {
+    /// 2 minute cycles at 1.46 baud.
+    Wspr2,
+    /// 15 minute cycles at 0.18 baud.
+    Wspr15,
+}
+
+impl WsprMode {
+    pub fn cycle_seconds(&self) -> f32 {
+        match self {
+            WsprMode::Wspr2 => 120.0,
+            WsprMode::Wspr15 => 900.0,
+        }
+    }
+
+    // Samples per symbol at 12 kHz
+    fn symbol_samples(&self) -> usize {
+        match self {
+            WsprMode::Wspr2 => 8192,
+            WsprMode::Wspr15 => 8192 * 8,
+        }
+    }
+
+    /// Symbol rate and tone spacing in Hz.
+    pub fn tone_spacing(&self) -> f32 {
+        12000.0 / self.symbol_samples() as f32
+    }
+}
+
+pub struct WsprDecodeParams {
+    pub sr: f32,
+    pub mode: WsprMode,
+    // Range searched for the centre of the signal
+    pub from_freq: f32,
+    pub to_freq: f32,
+    // Largest frequency change over the transmission searched, in Hz
+    pub max_drift: f32,
+    pub sync_threshold: f32,
+    pub max_candidates: usize,
+    // Fano decoder node visits per bit before a candidate is given up
+    pub fano_cycles: usize,
+}
+
+impl Default for WsprDecodeParams {
+    fn default() -> Self {
+        WsprDecodeParams {
+            sr: 12000.0,
+            mode: WsprMode::Wspr2,
+            from_freq: 1400.0,
+            to_freq: 1600.0,
+            max_drift: 4.0,
+            sync_threshold: 0.1,
+            max_candidates: 50,
+            fano_cycles: 10000,
+        }
+    }
+}
+
+/// A decoded WSPR transmission.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WsprSpot {
+    /// Signal to noise ratio in 2500 Hz, in dB.
+    pub snr: f32,
+    /// Start of the signal in seconds from 1 s after the first sample, which is the
+    /// nominal start when the audio begins on the cycle boundary.
+    pub dt: f32,
+    /// Audio frequency of the centre of the signal in Hz.
+    pub freq_hz: f32,
+    /// Frequency change over the transmission in Hz.
+    pub drift_hz: f32,
+    pub message: WsprMessage,
+}
+
+impl Display for WsprSpot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:5.1} {:.1} {:6.1} {:+.1} {}",
+            self.snr, self.dt, self.freq_hz, self.drift_hz, self.message
+        )
+    }
+}
+
+// Channel position of each code bit: bit reversed byte indices, skipping those past the end
+fn interleave_order() -> [usize; WSPR_SYMBOLS] {
+    let mut order = [0; WSPR_SYMBOLS];
+    (0..=255u8)
+        .map(|i| i.reverse_bits() as usize)
+        .filter(|&j| j < WSPR_SYMBOLS)
+        .enumerate()
+        .for_each(|(p, j)| order[p] = j);
+    order
+}
+
+fn channel_symbols(message: &[bool; 50]) -> [u8; WSPR_SYMBOLS] {
+    let mut bits = message.to_vec();
+    bits.extend([false; TAIL_BITS]);
+    let coded = conv_encode(&bits);
+    let mut symbols = WSPR_SYNC;
+    zip(interleave_order(), coded).for_each(|(j, bit)| symbols[j] += 2 * bit as u8);
+    symbols
+}
+
+struct Candidate {
+    frame: usize,
+    bin: usize,
+    drift: f32,
+    score: f32,
+}
+
+/// Decodes whole WSPR cycles.
+pub struct WsprDecoder {
+    sr: f32,
+    symbol_samples: usize,
+    fft_size: usize,
+    bin_hz: f32,
+    // Bins kept from the spectrum, and the range searched for the lowest tone within them
+    first_bin: usize,
+    last_bin: usize,
+    from_bin: usize,
+    to_bin: usize,
+    max_drift: f32,
+    sync_threshold: f32,
+    max_candidates: usize,
+    fano_cycles: usize,
+    callsign_map: CallsignMap,
+}
+
+impl WsprDecoder {
+    pub fn new(params: &WsprDecodeParams) -> WsprDecoder {
+        let symbol_samples =
+            (params.sr * params.mode.symbol_samples() as f32 / 12000.0).round() as usize;
+        // Two symbols per FFT gives bins at half the tone spacing
+        let fft_size = symbol_samples * 2;
+        let bin_hz = params.sr / fft_size as f32;
+        // Room for the drift and for moving a bin either way when refining
+        let margin = (params.max_drift / 2.0 / bin_hz).ceil() as usize + 1;
+        let from_bin = ((params.from_freq / bin_hz).ceil() as usize).saturating_sub(3);
+        let to_bin = ((params.to_freq / bin_hz).floor() as usize).saturating_sub(3);
+        let first_bin = from_bin.saturating_sub(margin);
+        let last_bin = (to_bin + 6 + margin + 1).min(fft_size / 2 + 1);
+        WsprDecoder {
+            sr: params.sr,
+            symbol_samples,
+            fft_size,
+            bin_hz,
+            first_bin,
+            last_bin,
+            from_bin: from_bin.max(first_bin + margin) - first_bin,
+            to_bin: (to_bin + 1)
+                .min(last_bin.saturating_sub(6 + margin))
+                .saturating_sub(first_bin),
+            max_drift: params.max_drift,
+            sync_threshold: params.sync_threshold,
+            max_candidates: params.max_candidates,
+            fano_cycles: params.fano_cycles,
+            callsign_map: CallsignMap::new(),
+        }
+    }
+
+    /// Decodes one cycle of audio, best started on the cycle boundary.
+    pub fn decode(&mut self, samples: &[f32]) -> Result<Vec<WsprSpot>, Error> {
+        let amp = self.spectrogram(samples)?;
+        let noise = Self::noise_power(&amp);
+        let mut spots: HashMap<String, WsprSpot> = HashMap::new();
+        for candidate in self.candidates(&amp) {
+            let Some(spot) = self.decode_candidate(&amp, &candidate, noise) else {
+                continue;
+            };
+            let key = spot.message.to_string();
+            if spots.get(&key).is_none_or(|other| other.snr < spot.snr) {
+                spots.insert(key, spot);
+            }
+        }
+        let mut spots = spots.into_values().collect::<Vec<_>>();
+        spots.sort_by(|a, b| a.freq_hz.total_cmp(&b.freq_hz));
+        for spot in &spots {
+            if let WsprMessage::Standard { callsign, .. } | WsprMessage::Compound { callsign, .. } =
+                &spot.message
+            {
+                self.callsign_map.insert_wspr(callsign);
+            }
+        }
+        Ok(spots)
+    }
+
+    pub fn insert_callsign(&mut self, callsign: &str) -> u16 {
+        self.callsign_map.insert_wspr(callsign)
+    }
+
+    // Magnitude of the kept bins, in frames half a symbol apart
+    fn spectrogram(&self, samples: &[f32]) -> Result<Vec<Vec<f32>>, Error> {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let plan = planner.plan_fft_forward(self.fft_size);
+        let mut input = plan.make_input_vec();
+        let mut spectrum = plan.make_output_vec();
+        let window = (0..self.fft_size)
+            .map(|i| 0.5 * (1.0 - (2.0 * PI * (i as f32) / ((self.fft_size - 1) as f32)).cos()))
+            .collect::<Vec<_>>();
+        let mut amp = Vec::new();
+        for frame in samples
+            .windows(self.fft_size)
+            .step_by(self.symbol_samples / 2)
+        {
+            zip(&mut input, zip(frame, &window)).for_each(|(i, (x, w))| *i = x * w);
+            plan.process(&mut input, &mut spectrum)?;
+            amp.push(
+                spectrum[self.first_bin..self.last_bin]
+                    .iter()
+                    .map(|x| x.norm())
+                    .collect::<Vec<_>>(),
+            );
+        }
+        Ok(amp)
+    }
+
+    // Median bin power, most bins only hold noise
+    fn noise_power(amp: &[Vec<f32>]) -> f32 {
+        let mut powers = amp.iter().flatten().map(|a| a * a).collect::<Vec<_>>();
+        if powers.is_empty() {
+            return 1.0;
+        }
+        let mid = powers.len() / 2;
+        *powers.select_nth_unstable_by(mid, f32::total_cmp).1
+    }
+
+    // Amplitudes of the four tones of `symbol`, the lowest tone starts at `bin`
+    // and moves by `drift` Hz over the transmission
+    fn tones(
+        &self,
+        amp: &[Vec<f32>],
+        frame: usize,
+        bin: usize,
+        drift: f32,
+        symbol: usize,
+    ) -> [f32; 4] {
+        let shift = (drift * (symbol as f32 - 81.0) / 162.0 / self.bin_hz).round() as isize;
+        let row = &amp[frame + symbol * 2];
+        let j = (bin as isize + shift) as usize;
+        [row[j], row[j + 2], row[j + 4], row[j + 6]]
+    }
+
+    // Correlation of the low bit of the strongest tone with the sync vector, from -1 to 1
+    fn sync_score(&self, amp: &[Vec<f32>], frame: usize, bin: usize, drift: f32) -> f32 {
+        let (sync, total) = (0..WSPR_SYMBOLS)
+            .map(|i| {
+                let a = self.tones(amp, frame, bin, drift, i);
+                let d = (a[1] + a[3]) - (a[0] + a[2]);
+                (
+                    if WSPR_SYNC[i] == 1 { d } else { -d },
+                    a.iter().sum::<f32>(),
+                )
+            })
+            .fold((0.0, 0.0), |(s, t), (s2, t2)| (s + s2, t + t2));
+        sync / (total + 1e-12)
+    }
+
+    // Peaks of the sync score without drift, then refined in time, frequency and drift
+    fn candidates(&self, amp: &[Vec<f32>]) -> Vec<Candidate> {
+        let span = (WSPR_SYMBOLS - 1) * 2;
+        if amp.len() <= span || self.from_bin >= self.to_bin {
+            return Vec::new();
+        }
+        let frames = amp.len() - span;
+        let scores = (0..frames)
+            .map(|frame| {
+                (self.from_bin..self.to_bin)
+                    .map(|bin| self.sync_score(amp, frame, bin, 0.0))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let score = |frame: isize, i: isize| {
+            scores
+                .get(frame as usize)
+                .and_then(|row| row.get(i as usize))
+                .copied()
+                .unwrap_or(f32::NEG_INFINITY)
+        };
+        let mut peaks = Vec::new();
+        for (frame, row) in scores.iter().enumerate() {
+            for (i, &s) in row.iter().enumerate() {
+                let (frame, i) = (frame as isize, i as isize);
+                // Ties go to the earliest frame and lowest bin
+                let peak = s >= self.sync_threshold
+                    && (-1..=1)
+                        .flat_map(|df| (-1..=1).map(move |di| (df, di)))
+                        .filter(|&offset| offset != (0, 0))
+                        .all(|(df, di)| {
+                            let other = score(frame + df, i + di);
+                            if (df, di) < (0, 0) {
+                                s > other
+                            } else {
+                                s >= other
+                            }
+                        });
+                if peak {
+                    peaks.push((frame as usize, self.from_bin + i as usize, s));
+                }
+            }
+        }
+        peaks.sort_by(|a, b| b.2.total_cmp(&a.2));
+        peaks.truncate(self.max_candidates);
+
+        let drift_steps = (self.max_drift / 0.5).round() as i32;
+        peaks
+            .into_iter()
+            .map(|(frame, bin, score)| {
+                let mut best = Candidate {
+                    frame,
+                    bin,
+                    drift: 0.0,
+                    score,
+                };
+                let frames = frame.saturating_sub(1)..(frame + 2).min(frames);
+                for frame in frames {
+                    for bin in bin - 1..=bin + 1 {
+                        for drift in (-drift_steps..=drift_steps).map(|k| k as f32 * 0.5) {
+                            let score = self.sync_score(amp, frame, bin, drift);
+                            if score > best.score {
+                                best = Candidate {
+                                    frame,
+                                    bin,
+                                    drift,
+                                    score,
+                                };
+                            }
+                        }
+                    }
+                }
+                best
+            })
+            .collect()
+    }
+
+    fn decode_candidate(
+        &self,
+        amp: &[Vec<f32>],
+        candidate: &Candidate,
+        noise: f32,
+    ) -> Option<WsprSpot> {
+        let tones = |symbol: usize| {
+            self.tones(amp, candidate.frame, candidate.bin, candidate.drift, symbol)
+        };
+        // The sync bit picks the pair of tones the data bit chooses between
+        let soft = (0..WSPR_SYMBOLS)
+            .map(|i| {
+                let a = tones(i);
+                let sync = WSPR_SYNC[i] as usize;
+                a[sync + 2] - a[sync]
+            })
+            .collect::<Vec<_>>();
+        let rms = (soft.iter().map(|d| d * d).sum::<f32>() / WSPR_SYMBOLS as f32).sqrt() + 1e-12;
+        let mut probabilities = [0.0; WSPR_SYMBOLS];
+        zip(&mut probabilities, interleave_order())
+            .for_each(|(p, j)| *p = sigmoid(SOFT_SCALE * soft[j] / rms));
+
+        let bits = fano_decode(&probabilities, self.fano_cycles)?;
+        let data = bits.first_chunk::<50>()?;
+        let message = WsprMessage::from_bits(data, &self.callsign_map).ok()?;
+
+        // Signal power on the tones actually sent, against the noise in 2500 Hz
+        let signal = zip(0..WSPR_SYMBOLS, channel_symbols(data))
+            .map(|(i, symbol)| tones(i)[symbol as usize].powi(2))
+            .sum::<f32>()
+            / WSPR_SYMBOLS as f32;
+        let snr = 10.0 * ((signal - noise).max(noise * 1e-4) / noise).log10()
+            + 10.0 * (1.5 * self.bin_hz / 2500.0).log10();
+        let start = candidate.frame * self.symbol_samples / 2 + self.symbol_samples / 2;
+        Some(WsprSpot {
+            snr,
+            dt: start as f32 / self.sr - NOMINAL_START,
+            freq_hz: (self.first_bin + candidate.bin + 3) as f32 * self.bin_hz,
+            drift_hz: candidate.drift,
+            message,
+        })
+    }
+}
+
+pub struct WsprEncodeParams {
+    pub sr: f32,
+    pub mode: WsprMode,
+    // Audio frequency of the centre of the signal
+    pub freq: f32,
+    pub amplitude: f32,
+}
+
+impl Default for WsprEncodeParams {
+    fn default() -> Self {
+        WsprEncodeParams {
+            sr: 12000.0,
+            mode: WsprMode::Wspr2,
+            freq: 1500.0,
+            amplitude: 1.0,
+        }
+    }
+}
+
+/// Generates WSPR audio.
+pub struct WsprEncoder {
+    sr: f32,
+    mode: WsprMode,
+    freq: f32,
+    amplitude: f32,
+}
+
+impl WsprEncoder {
+    pub fn new(params: &WsprEncodeParams) -> WsprEncoder {
+        WsprEncoder {
+            sr: params.sr,
+            mode: params.mode,
+            freq: params.freq,
+            amplitude: params.amplitude,
+        }
+    }
+
+    /// The transmission alone, 162 symbols long.
+    pub fn encode(&self, message: &WsprMessage) -> Result<Vec<f32>, Error> {
+        Ok(self.synthesize(&Self::encode_symbols(message)?))
+    }
+
+    /// A whole cycle with the transmission starting 1 s in, as a beacon sends it.
+    pub fn encode_cycle(&self, message: &WsprMessage) -> Result<Vec<f32>, Error> {
+        let wave = self.encode(message)?;
+        let mut samples = vec![0.0; (self.mode.cycle_seconds() * self.sr) as usize];
+        let start = (NOMINAL_START * self.sr) as usize;
+        zip(&mut samples[start..], wave).for_each(|(s, w)| *s = w);
+        Ok(samples)
+    }
+
+    /// Continuous phase 4-FSK, tone 0 is 1.5 tone spacings below the centre frequency.
+    pub fn synthesize(&self, symbols: &[u8; WSPR_SYMBOLS]) -> Vec<f32> {
+        let symbol_samples = self.sr / self.mode.tone_spacing();
+        let num_samples = (WSPR_SYMBOLS as f32 * symbol_samples).round() as usize;
+        let mut phase = 0.0f64;
+        (0..num_samples)
+            .map(|n| {
+                let symbol = symbols[((n as f32 / symbol_samples) as usize).min(WSPR_SYMBOLS - 1)];
+                let freq = self.freq + (symbol as f32 - 1.5) * self.mode.tone_spacing();
+                let x = self.amplitude * phase.sin() as f32;
+                phase = (phase + 2.0 * std::f64::consts::PI * freq as f64 / self.sr as f64)
+                    % (2.0 * std::f64::consts::PI);
+                x
+            })
+            .collect()
+    }
+
+    pub fn write_wav<P: AsRef<Path>>(&self, path: P, samples: &[f32]) -> Result<(), hound::Error> {
+        write_wav(path, samples, self.sr as u32)
+    }
+
+    pub fn encode_symbols(message: &WsprMessage) -> Result<[u8; WSPR_SYMBOLS], Error> {
+        Ok(channel_symbols(&message.to_bits()?))
+    }
+}
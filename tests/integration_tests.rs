@@ -1,4 +1,32 @@
-use weaksignal::ft8;
+use std::{
+    iter::zip,
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use weaksignal::{
+    adif::{self, Qso, WorkedBefore},
+    cabrillo::{self, Contest},
+    foxhound::{self, Fox, FoxParams, Hound, HoundParams, HoundState},
+    ft4, ft8,
+    jt65::{
+        self, Jt65DecodeParams, Jt65Decoder, Jt65EncodeParams, Jt65Encoder, Jt65Message,
+        Jt65Submode,
+    },
+    message::{Grid4Value, Message, MessageData, MessageKind},
+    pskreporter::{PskReporter, PskReporterParams, Receiver, Spot},
+    sequencer::{Sequencer, SequencerParams, Slot, Step},
+    wsjtx::{self, Command, Decode, HaltTx, Packet, Reply},
+    wspr::{WsprDecoder, WsprEncodeParams, WsprEncoder, WsprMessage},
+    CallsignMap,
+};
+
+// A WAV path in the temp directory that no other test or concurrent run uses
+fn temp_wav(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("weaksignal_{}_{}.wav", name, std::process::id()))
+}
 
 fn read_wav(filename: &str) -> Result<Vec<f32>, hound::Error> {
     let mut reader = hound::WavReader::open(filename)?;
@@ -30,3 +58,1061 @@ fn it_works() {
             decoder.insert_callsign(&c);
         });
 }
+
+fn freetext_tnx_bob() -> [bool; 77] {
+    let f71 = [
+        0, 1, 1, 0, 0, 0, 1, 1, 1, 1, 1, 0, 1, 1, 0, 1, 1, 1, 0, 0, 1, 1, 1, 0, 1, 1, 1, 0, 0, 0,
+        1, 0, 1, 0, 1, 0, 0, 1, 0, 0, 1, 0, 1, 0, 1, 1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1,
+        0, 1, 0, 1, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let mut message = [false; 77];
+    zip(&mut message, f71).for_each(|(m, x)| *m = x == 1);
+    message
+}
+
+#[test]
+fn synthesized_signal() {
+    let encoder = ft8::FT8Encoder::new(&ft8::EncodeParams {
+        freq: 1000.0,
+        amplitude: 0.1,
+        ..Default::default()
+    });
+    let mut samples = vec![0.0; 12000 * 15];
+    let wave = encoder.encode(&freetext_tnx_bob());
+    assert_eq!(wave.len(), 79 * 1920);
+    zip(&mut samples[6000..], wave).for_each(|(s, w)| *s += w);
+    let filename = temp_wav("synthesized_signal");
+    encoder.write_wav(&filename, &samples).unwrap();
+    let samples = read_wav(filename.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+
+    let mut decoder = ft8::FT8Decoder::new(&ft8::DecodeParams {
+        from_freq: 900.0,
+        to_freq: 1100.0,
+        ..Default::default()
+    });
+    let messages = decoder.decode(samples.as_slice()).unwrap();
+    messages.iter().for_each(|f| println!("{}", f));
+    let message = messages
+        .iter()
+        .find(|m| m.to_string().ends_with("TNX BOB 73 GL"))
+        .unwrap();
+    assert!((message.freq_bin_hz as i32 - 1000).abs() <= 4);
+    assert!((message.time_offset_ms - 500).abs() <= 80);
+}
+
+#[test]
+fn ft4_synthesized_signals() {
+    let cq = MessageData::from_text("CQ TEST K1ABC FN42").unwrap();
+    let reply = MessageData::from_text("K1ABC W9XYZ R-07").unwrap();
+    let mut samples = vec![0.0; (12000.0 * ft4::CYCLE_SECONDS) as usize];
+    for (data, freq, start) in [(&cq, 1000.0, 6000), (&reply, 1600.0, 7200)] {
+        let encoder = ft4::FT4Encoder::new(&ft8::EncodeParams {
+            freq,
+            amplitude: 0.1,
+            ..Default::default()
+        });
+        let wave = encoder.encode(&data.to_bits().unwrap());
+        assert_eq!(wave.len(), 105 * 576);
+        zip(&mut samples[start..], wave).for_each(|(s, w)| *s += w);
+    }
+
+    let mut decoder = ft4::FT4Decoder::new(&Default::default());
+    let messages = decoder.decode_cycle(&samples).unwrap();
+    messages.iter().for_each(|f| println!("{}", f));
+    assert_eq!(messages.len(), 2);
+    let cq = messages
+        .iter()
+        .find(|m| m.bits() == cq.to_bits().unwrap())
+        .unwrap();
+    assert!((cq.freq_bin_hz as i32 - 1000).abs() <= 11);
+    assert!((cq.time_offset_ms - 500).abs() <= 30);
+    let reply = messages
+        .iter()
+        .find(|m| m.bits() == reply.to_bits().unwrap())
+        .unwrap();
+    assert!((reply.freq_bin_hz as i32 - 1600).abs() <= 11);
+    assert!((reply.time_offset_ms - 600).abs() <= 30);
+}
+
+#[test]
+fn subtraction_uncovers_weak_signal() {
+    let strong = ft8::FT8Encoder::new(&ft8::EncodeParams {
+        freq: 1000.0,
+        amplitude: 0.5,
+        ..Default::default()
+    });
+    let weak = ft8::FT8Encoder::new(&ft8::EncodeParams {
+        freq: 1006.0,
+        amplitude: 0.02,
+        ..Default::default()
+    });
+    let mut weak_message = freetext_tnx_bob();
+    weak_message[60] ^= true;
+    let mut samples = vec![0.0; 12000 * 15];
+    zip(&mut samples[6000..], strong.encode(&freetext_tnx_bob())).for_each(|(s, w)| *s += w);
+    zip(&mut samples[6600..], weak.encode(&weak_message)).for_each(|(s, w)| *s += w);
+
+    let params = ft8::DecodeParams {
+        from_freq: 900.0,
+        to_freq: 1100.0,
+        ..Default::default()
+    };
+    let single_pass = ft8::FT8Decoder::new(&params).decode(&samples).unwrap();
+    assert_eq!(single_pass.len(), 1);
+
+    let messages = ft8::FT8Decoder::new(&params)
+        .decode_cycle(&samples)
+        .unwrap();
+    messages.iter().for_each(|f| println!("{}", f));
+    assert_eq!(messages.len(), 2);
+    assert!(messages
+        .iter()
+        .any(|m| m.to_string().ends_with("TNX BOB 73 GL")));
+    let weak_decode = messages
+        .iter()
+        .find(|m| !m.to_string().ends_with("TNX BOB 73 GL"))
+        .unwrap();
+    assert!((weak_decode.freq_bin_hz as i32 - 1006).abs() <= 4);
+    assert!((weak_decode.time_offset_ms - 550).abs() <= 80);
+}
+
+#[test]
+fn decode_cycle_between_streamed_chunks() {
+    let encoder = ft8::FT8Encoder::new(&ft8::EncodeParams {
+        sr: 48000.0,
+        freq: 1000.0,
+        amplitude: 0.1,
+    });
+    let mut samples = vec![0.0; 48000 * 15];
+    zip(&mut samples[24000..], encoder.encode(&freetext_tnx_bob())).for_each(|(s, w)| *s += w);
+
+    let mut decoder = ft8::FT8Decoder::new(&ft8::DecodeParams {
+        sr: 48000.0,
+        from_freq: 900.0,
+        to_freq: 1100.0,
+        resample: true,
+        ..Default::default()
+    });
+    // The cycle decoded in the middle must not disturb the audio already streamed
+    let (first, second) = samples.split_at(48000 * 7);
+    let mut messages = decoder.decode(first).unwrap();
+    assert_eq!(decoder.decode_cycle(&samples).unwrap().len(), 1);
+    messages.extend(decoder.decode(second).unwrap());
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0].to_string().ends_with("TNX BOB 73 GL"));
+    assert!((messages[0].time_offset_ms - 500).abs() <= 80);
+}
+
+// Frequency and time offset of the decoded test signal
+fn decode_at_rate(sr: f32, resample: bool) -> (u32, i64) {
+    let encoder = ft8::FT8Encoder::new(&ft8::EncodeParams {
+        sr,
+        freq: 1000.0,
+        amplitude: 0.1,
+    });
+    let start = (sr * 0.5) as usize;
+    let mut samples = vec![0.0; (sr * 15.0) as usize];
+    zip(&mut samples[start..], encoder.encode(&freetext_tnx_bob())).for_each(|(s, w)| *s += w);
+
+    let mut decoder = ft8::FT8Decoder::new(&ft8::DecodeParams {
+        sr,
+        from_freq: 900.0,
+        to_freq: 1100.0,
+        resample,
+        ..Default::default()
+    });
+    let messages = decoder.decode(&samples).unwrap();
+    let message = messages
+        .iter()
+        .find(|m| m.to_string().ends_with("TNX BOB 73 GL"))
+        .unwrap();
+    (message.freq_bin_hz, message.time_offset_ms)
+}
+
+#[test]
+fn decode_messages_frames_like_decode() {
+    let encoder = ft8::FT8Encoder::new(&ft8::EncodeParams {
+        freq: 1000.0,
+        amplitude: 0.1,
+        ..Default::default()
+    });
+    let mut samples = vec![0.0; 12000 * 15];
+    zip(&mut samples[6000..], encoder.encode(&freetext_tnx_bob())).for_each(|(s, w)| *s += w);
+    let decode = |batch: bool| {
+        let mut decoder = ft8::FT8Decoder::new(&ft8::DecodeParams {
+            from_freq: 900.0,
+            to_freq: 1100.0,
+            ..Default::default()
+        });
+        if batch {
+            decoder.decode_messages(&samples).unwrap()
+        } else {
+            decoder.decode(&samples).unwrap()
+        }
+    };
+    let (batch, streamed) = (decode(true), decode(false));
+    assert_eq!(batch.len(), 1);
+    assert_eq!(batch[0].to_string(), streamed[0].to_string());
+    assert_eq!(batch[0].time_offset_ms, streamed[0].time_offset_ms);
+    assert!((batch[0].time_offset_ms - 500).abs() <= 80);
+}
+
+#[test]
+fn reports_follow_sample_rate() {
+    for (sr, resample) in [
+        (48000.0, false),
+        (11025.0, false),
+        (48000.0, true),
+        (11025.0, true),
+    ] {
+        let (freq, time_offset_ms) = decode_at_rate(sr, resample);
+        assert!((freq as i32 - 1000).abs() <= 4);
+        assert!((time_offset_ms - 500).abs() <= 80);
+    }
+}
+
+#[test]
+fn message_fields_are_public() {
+    let data = MessageData::from_text("CQ K1ABC FN42").unwrap();
+    let encoder = ft8::FT8Encoder::new(&ft8::EncodeParams {
+        freq: 1500.0,
+        amplitude: 0.1,
+        ..Default::default()
+    });
+    let mut samples = vec![0.0; 12000 * 15];
+    zip(
+        &mut samples[7200..],
+        encoder.encode(&data.to_bits().unwrap()),
+    )
+    .for_each(|(s, w)| *s += w);
+
+    let mut decoder = ft8::FT8Decoder::new(&ft8::DecodeParams {
+        from_freq: 1400.0,
+        to_freq: 1600.0,
+        ..Default::default()
+    });
+    let messages = decoder.decode(&samples).unwrap();
+    assert_eq!(messages.len(), 1);
+    let message = &messages[0];
+    assert_eq!(message.kind(), MessageKind::StandardR);
+    assert_eq!(message.bits(), data.to_bits().unwrap());
+    assert!((message.freq_bin_hz as i32 - 1500).abs() <= 4);
+    assert!((message.dt_seconds() - 0.1).abs() <= 0.08);
+    assert!(message.snr() > 0.0);
+    assert_eq!(message.ap_pass, None);
+    assert_eq!(message.grid().as_deref(), Some("FN42"));
+    assert_eq!(message.report(), None);
+    let MessageData::StandardR(standard) = &message.data else {
+        panic!("expected a standard message");
+    };
+    assert_eq!(standard.callsign1.to_string(), "CQ");
+    assert_eq!(standard.callsign2.to_string(), "K1ABC");
+    assert_eq!(
+        standard.grid.value(),
+        Grid4Value::Locator("FN42".to_string())
+    );
+    assert!(message.time_offset_ms > 0);
+}
+
+#[test]
+fn wsjtx_decodes_reach_listener() {
+    let encoder = ft8::FT8Encoder::new(&ft8::EncodeParams {
+        freq: 1000.0,
+        amplitude: 0.1,
+        ..Default::default()
+    });
+    let mut samples = vec![0.0; 12000 * 15];
+    zip(&mut samples[6000..], encoder.encode(&freetext_tnx_bob())).for_each(|(s, w)| *s += w);
+    let messages = ft8::FT8Decoder::new(&ft8::DecodeParams {
+        from_freq: 900.0,
+        to_freq: 1100.0,
+        ..Default::default()
+    })
+    .decode(&samples)
+    .unwrap();
+
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut server = wsjtx::UdpServer::new("weaksignal", listener.local_addr().unwrap()).unwrap();
+    // 12:34:45 UTC
+    let cycle_start = UNIX_EPOCH + Duration::from_secs(19_000 * 86400 + 45_285);
+    server.heartbeat().unwrap();
+    server
+        .publish(&messages, wsjtx::FT8_MODE, cycle_start)
+        .unwrap();
+
+    let mut buf = [0; 1024];
+    let mut receive = || {
+        let len = listener.recv(&mut buf).unwrap();
+        Packet::from_bytes(&buf[..len]).unwrap()
+    };
+    let (id, packet) = receive();
+    assert_eq!(id, "weaksignal");
+    assert!(matches!(packet, Packet::Heartbeat(_)));
+    let (_, Packet::Decode(decode)) = receive() else {
+        panic!("expected a decode");
+    };
+    assert_eq!(decode.message, "TNX BOB 73 GL");
+    assert_eq!(decode.mode, wsjtx::FT8_MODE);
+    assert_eq!(decode.time, 45_285_000);
+    assert_eq!(decode.snr, messages[0].snr().round() as i32);
+    assert!((decode.delta_frequency as i32 - 1000).abs() <= 4);
+    assert!(decode.delta_time.abs() <= 0.08);
+    assert!(decode.new && !decode.low_confidence);
+
+    // Answer the decode the way GridTracker does, then halt Tx for another station
+    let server_addr = SocketAddr::from(([127, 0, 0, 1], server.local_addr().unwrap().port()));
+    let reply = Reply {
+        time: decode.time,
+        snr: decode.snr,
+        delta_time: decode.delta_time,
+        delta_frequency: decode.delta_frequency,
+        mode: decode.mode.clone(),
+        message: decode.message.clone(),
+        low_confidence: false,
+        modifiers: 0,
+    };
+    let halt = Packet::HaltTx(HaltTx {
+        auto_tx_only: false,
+    });
+    // A HighlightCallsign (type 13) from JTAlert, which is not parsed, comes first
+    let mut highlight = [0xadbccbda, 3, 13, 10]
+        .iter()
+        .flat_map(|n: &u32| n.to_be_bytes())
+        .collect::<Vec<_>>();
+    highlight.extend(b"weaksignal\0\0\0\x05K1ABC");
+    highlight.extend([1, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0, 0].repeat(2));
+    highlight.push(1);
+    for packet in [
+        highlight,
+        halt.to_bytes("other"),
+        Packet::Reply(reply.clone()).to_bytes("weaksignal"),
+        halt.to_bytes("weaksignal"),
+    ] {
+        listener.send_to(&packet, server_addr).unwrap();
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut commands = Vec::new();
+    while commands.len() < 2 && Instant::now() < deadline {
+        match server.poll().unwrap() {
+            Some(command) => commands.push(command),
+            None => sleep(Duration::from_millis(10)),
+        }
+    }
+    assert_eq!(commands.len(), 2);
+    let Command::Reply {
+        reply: received,
+        message: Some(message),
+    } = &commands[0]
+    else {
+        panic!("expected a reply to the published decode");
+    };
+    assert_eq!(received, &reply);
+    assert_eq!(message.bits(), messages[0].bits());
+    assert!(matches!(
+        commands[1],
+        Command::HaltTx(HaltTx {
+            auto_tx_only: false
+        })
+    ));
+    assert!(server.poll().unwrap().is_none());
+
+    drop(server);
+    assert_eq!(receive(), ("weaksignal".to_string(), Packet::Close));
+}
+
+#[test]
+fn wsjtx_decode_carries_mode() {
+    let cycle_start = UNIX_EPOCH + Duration::from_secs(19_000 * 86400 + 45_285);
+    let message = decoded("CQ K1ABC FN42", -5.0, &[]);
+    let decode = Decode::from_message(&message, wsjtx::FT4_MODE, cycle_start);
+    let bytes = Packet::Decode(decode).to_bytes("weaksignal");
+    let (_, Packet::Decode(decode)) = Packet::from_bytes(&bytes).unwrap() else {
+        panic!("expected a decode");
+    };
+    assert_eq!(decode.mode, "+");
+    assert_eq!(decode.message, "CQ K1ABC FN42");
+    assert_eq!(decode.snr, -5);
+}
+
+#[test]
+fn pskreporter_batches_spots() {
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let mut reporter = PskReporter::new(&PskReporterParams {
+        receiver: Receiver {
+            callsign: "W9XYZ".to_string(),
+            locator: "EN37".to_string(),
+            decoding_software: "weaksignal".to_string(),
+        },
+        host: listener.local_addr().unwrap().to_string(),
+        ..Default::default()
+    })
+    .unwrap();
+    let message = |text: &str| {
+        let bits = MessageData::from_text(text).unwrap().to_bits().unwrap();
+        Message::from_bits(-10.0, 1200, 500, &bits, &Default::default()).unwrap()
+    };
+    let contains =
+        |packet: &[u8], text: &str| packet.windows(text.len()).any(|w| w == text.as_bytes());
+    let cycle_start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let mut buf = [0; 1500];
+
+    // The first report goes out at once, with each sender once
+    let messages = [
+        message("CQ K1ABC FN42"),
+        message("W9XYZ K1ABC -12"),
+        message("K1ABC G4ABC RR73"),
+        message("TNX BOB 73 GL"),
+    ];
+    reporter
+        .report(&messages, "FT8", 14_074_000, cycle_start)
+        .unwrap();
+    let len = listener.recv(&mut buf).unwrap();
+    let packet = &buf[..len];
+    assert_eq!(u16::from_be_bytes([packet[2], packet[3]]) as usize, len);
+    assert!(contains(packet, "W9XYZ") && contains(packet, "EN37"));
+    assert!(contains(packet, "K1ABC") && contains(packet, "FN42"));
+    assert!(contains(packet, "G4ABC"));
+    assert_eq!(packet.windows(5).filter(|w| w == b"K1ABC").count(), 1);
+    assert!(packet.windows(4).any(|w| w == 14_075_200u32.to_be_bytes()));
+    assert!(contains(packet, "FT8"));
+
+    // Later spots wait for the report interval, and a callsign already reported on the
+    // band is skipped for the dedupe interval, an hour by default
+    reporter
+        .report(
+            &[message("CQ PA9XYZ JO22"), message("CQ K1ABC FN42")],
+            "FT4",
+            14_080_000,
+            cycle_start,
+        )
+        .unwrap();
+    assert!(listener.recv(&mut buf).is_err());
+    drop(reporter);
+    let len = listener.recv(&mut buf).unwrap();
+    assert!(contains(&buf[..len], "PA9XYZ") && contains(&buf[..len], "FT4"));
+    assert!(!contains(&buf[..len], "K1ABC") && !contains(&buf[..len], "FT8"));
+
+    // Frequencies past the 32 bit field, such as 10 GHz and up, are not spotted
+    let cq = message("CQ K1ABC FN42");
+    let spot = Spot::from_message(&cq, "FT8", 14_074_000, cycle_start).unwrap();
+    assert_eq!(spot.frequency, 14_075_200);
+    assert!(Spot::from_message(&cq, "FT8", u32::MAX - 1000, cycle_start).is_none());
+}
+
+#[test]
+fn adif_roundtrip_and_worked_before() {
+    let start = UNIX_EPOCH + Duration::from_secs(1_718_454_600);
+    let exchanges: Vec<_> = [
+        "CQ W9XYZ EN37",
+        "W9XYZ K1ABC FN42",
+        "K1ABC W9XYZ -08",
+        "W9XYZ G4ABC IO91",
+        "W9XYZ K1ABC R-12",
+        "K1ABC W9XYZ RR73",
+        "W9XYZ K1ABC 73",
+    ]
+    .iter()
+    .enumerate()
+    .map(|(i, text)| {
+        (
+            start + Duration::from_secs(15 * i as u64),
+            MessageData::from_text(text).unwrap(),
+        )
+    })
+    .collect();
+    let mut qso = Qso::from_exchanges("W9XYZ", "FT8", &exchanges).unwrap();
+    qso.set_frequency(14_075_200);
+    assert_eq!(qso.call, "K1ABC");
+    assert_eq!(qso.grid.as_deref(), Some("FN42"));
+    assert_eq!(qso.rst_sent.as_deref(), Some("-08"));
+    assert_eq!(qso.rst_rcvd.as_deref(), Some("-12"));
+    assert_eq!(qso.band.as_deref(), Some("20m"));
+    assert_eq!(qso.time_on, start + Duration::from_secs(15));
+    assert_eq!(qso.time_off, start + Duration::from_secs(90));
+
+    let field_day: Vec<_> = ["W9XYZ K1ABC 3A WI", "K1ABC W9XYZ R 2B EMA"]
+        .iter()
+        .map(|text| (start, MessageData::from_text(text).unwrap()))
+        .collect();
+    let contest = Qso::from_exchanges("W9XYZ", "FT4", &field_day).unwrap();
+    assert_eq!(contest.mode, "FT4");
+    assert_eq!(contest.contest_id.as_deref(), Some("ARRL-FD"));
+    assert_eq!(contest.exchange_rcvd.as_deref(), Some("3A WI"));
+    assert_eq!(contest.exchange_sent.as_deref(), Some("2B EMA"));
+
+    let mut file = Vec::new();
+    adif::write(&mut file, &[qso.clone(), contest.clone()]).unwrap();
+    let text = String::from_utf8(file).unwrap();
+    assert!(text.contains("<QSO_DATE:8>20240615 <TIME_ON:6>123015 "));
+    assert!(text.contains("<FREQ:9>14.075200 "));
+    assert!(text.contains("<MODE:4>MFSK <SUBMODE:3>FT4 "));
+    assert_eq!(adif::read(&text).unwrap(), vec![qso, contest]);
+
+    // Other loggers write lowercase names, data types and short times
+    let log = adif::read(
+        "Exported log\n<adif_ver:5>3.1.4\n<eoh>\n\
+         <call:5:S>g4abc <band:3>40M <gridsquare:6>IO91wm <mode:4>MFSK <submode:3>FT4 \
+         <qso_date:8>20231231 <time_on:4>2359 <eor>\n",
+    )
+    .unwrap();
+    assert_eq!(log[0].call, "G4ABC");
+    assert_eq!(log[0].mode, "FT4");
+    assert_eq!(
+        log[0].time_on,
+        UNIX_EPOCH + Duration::from_secs(1_704_067_140)
+    );
+    assert!(adif::read("<CALL:5>K1AB<EOR>").is_err());
+    assert!(adif::read("<CALL:5>K1ABC <EOR>").is_err());
+
+    let worked = WorkedBefore::new(&log);
+    assert!(worked.call("g4abc", None));
+    assert!(worked.call("G4ABC", Some("40m")));
+    assert!(!worked.call("G4ABC", Some("20m")));
+    assert!(worked.grid("IO91", Some("40m")));
+    let decode = |text: &str| MessageData::from_text(text).unwrap();
+    assert_eq!(
+        worked.message(&decode("CQ G4ABC IO91"), Some("40m")),
+        Some(true)
+    );
+    assert_eq!(
+        worked.message(&decode("W9XYZ G4ABC -03"), Some("20m")),
+        Some(false)
+    );
+    assert_eq!(worked.message(&decode("CQ K1ABC FN42"), None), Some(false));
+    assert_eq!(worked.message(&decode("TNX BOB 73 GL"), None), None);
+}
+
+#[test]
+fn cabrillo_contest_logs() {
+    let start = UNIX_EPOCH + Duration::from_secs(1_719_079_200);
+    let qso = |my_call: &str, texts: &[&str], freq_hz: u64| {
+        let exchanges: Vec<_> = texts
+            .iter()
+            .map(|text| (start, MessageData::from_text(text).unwrap()))
+            .collect();
+        let mut qso = Qso::from_exchanges(my_call, "FT8", &exchanges).unwrap();
+        qso.set_frequency(freq_hz);
+        qso
+    };
+
+    let field_day = qso(
+        "W9XYZ",
+        &["W9XYZ K1ABC 3A WI", "K1ABC W9XYZ R 2B EMA"],
+        14_074_000,
+    );
+    let mut log = Vec::new();
+    cabrillo::write(
+        &mut log,
+        Contest::FieldDay,
+        &[("CALLSIGN", "W9XYZ"), ("LOCATION", "EMA")],
+        std::slice::from_ref(&field_day),
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(log).unwrap(),
+        format!(
+            "START-OF-LOG: 3.0\nCONTEST: ARRL-FD\nCALLSIGN: W9XYZ\nLOCATION: EMA\n\
+             CREATED-BY: weaksignal {}\n\
+             QSO: 14074 DI 2024-06-22 1800 W9XYZ         2B EMA     K1ABC         3A WI\n\
+             END-OF-LOG:\n",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+
+    let roundup = qso(
+        "W9XYZ",
+        &["W9XYZ K1ABC 559 MA", "K1ABC W9XYZ R 579 WI"],
+        7_074_000,
+    );
+    assert_eq!(
+        cabrillo::qso_line(Contest::RttyRoundup, &roundup, None).unwrap(),
+        "QSO:  7074 DG 2024-06-22 1800 W9XYZ         579 WI     K1ABC         559 MA"
+    );
+
+    let vhf = qso(
+        "PA3XYZ",
+        &[
+            "<PA3XYZ> <G4ABC> 570012 IO91WM",
+            "<G4ABC> <PA3XYZ> R 590003 JO22DC",
+        ],
+        144_174_000,
+    );
+    assert_eq!(
+        cabrillo::qso_line(Contest::EuVhf, &vhf, None).unwrap(),
+        "QSO:   144 DG 2024-06-22 1800 PA3XYZ        59  0003 JO22DC G4ABC         57  0012 IO91WM"
+    );
+
+    // Lines need both halves of the exchange
+    let mut missing = field_day;
+    missing.exchange_rcvd = None;
+    assert!(cabrillo::qso_line(Contest::FieldDay, &missing, None).is_err());
+}
+
+// A decode of `text` as the decoder would report it, hashes resolved from `calls`
+fn decoded(text: &str, snr: f32, calls: &[&str]) -> Message {
+    received(&MessageData::from_text(text).unwrap(), snr, calls)
+}
+
+fn received(data: &MessageData, snr: f32, calls: &[&str]) -> Message {
+    let mut callsign_map = CallsignMap::new();
+    for call in calls {
+        callsign_map.insert(&call.to_string());
+    }
+    Message::from_bits(snr, 1500, 500, &data.to_bits().unwrap(), &callsign_map).unwrap()
+}
+
+#[test]
+fn sequencer_standard_contact() {
+    let mut sequencer = Sequencer::new(SequencerParams {
+        my_call: "W9XYZ".to_string(),
+        my_grid: "EN37".to_string(),
+        ..Default::default()
+    });
+    let send =
+        |sequencer: &mut Sequencer, cycle| sequencer.transmit(cycle).map(|data| data.to_string());
+    assert_eq!(send(&mut sequencer, 100), None);
+    sequencer.call_cq(Slot::Even);
+    assert_eq!(send(&mut sequencer, 100).as_deref(), Some("CQ W9XYZ EN37"));
+
+    // The strongest caller is answered, in our slot only
+    sequencer.receive(
+        &[
+            decoded("W9XYZ G4ABC IO91", -15.0, &[]),
+            decoded("W9XYZ K1ABC FN42", -7.0, &[]),
+            decoded("CQ PA9XYZ JO22", 3.0, &[]),
+        ],
+        101,
+    );
+    assert_eq!(sequencer.dx_call(), Some("K1ABC"));
+    assert_eq!(sequencer.step(), Some(Step::Report));
+    assert_eq!(send(&mut sequencer, 101), None);
+    assert_eq!(
+        send(&mut sequencer, 102).as_deref(),
+        Some("K1ABC W9XYZ -07")
+    );
+
+    // Other stations and repeats of an earlier step do not move the contact on
+    sequencer.receive(&[decoded("W9XYZ G4ABC -10", -15.0, &[])], 103);
+    assert_eq!(sequencer.step(), Some(Step::Report));
+    sequencer.receive(&[decoded("W9XYZ K1ABC R-12", -9.0, &[])], 105);
+    assert_eq!(
+        send(&mut sequencer, 106).as_deref(),
+        Some("K1ABC W9XYZ RR73")
+    );
+    assert!(sequencer.take_logged().is_empty());
+    sequencer.receive(&[decoded("W9XYZ K1ABC 73", -9.0, &[])], 107);
+    assert_eq!(sequencer.step(), Some(Step::Cq));
+    assert_eq!(sequencer.dx_call(), None);
+
+    let logged = sequencer.take_logged();
+    assert_eq!(logged.len(), 1);
+    assert_eq!(logged[0].call, "K1ABC");
+    assert_eq!(logged[0].grid.as_deref(), Some("FN42"));
+    assert_eq!(logged[0].rst_sent.as_deref(), Some("-07"));
+    assert_eq!(logged[0].rst_rcvd.as_deref(), Some("-12"));
+    assert_eq!(
+        logged[0].time_on,
+        UNIX_EPOCH + Duration::from_secs(101 * 15)
+    );
+    assert_eq!(send(&mut sequencer, 108).as_deref(), Some("CQ W9XYZ EN37"));
+}
+
+#[test]
+fn sequencer_contests_and_timeouts() {
+    // Field Day starts with the exchange and logs after RR73 goes unanswered
+    let mut sequencer = Sequencer::new(SequencerParams {
+        my_call: "W9XYZ".to_string(),
+        my_grid: "EN37".to_string(),
+        contest: Some(Contest::FieldDay),
+        exchange: "2B EMA".to_string(),
+        max_repeats: 2,
+        cycle_length: Duration::from_millis(7500),
+        mode: "FT4".to_string(),
+        ..Default::default()
+    });
+    assert!(sequencer.answer(&decoded("CQ FD K1ABC FN42", -3.0, &[]), 201));
+    assert_eq!(sequencer.slot(), Slot::Even);
+    let sent = sequencer.transmit(202).unwrap();
+    assert_eq!(sent.to_string(), "K1ABC W9XYZ 2B EMA");
+    sequencer.receive(&[decoded("W9XYZ K1ABC R 3A WI", -3.0, &[])], 203);
+    for cycle in [204, 206] {
+        assert_eq!(
+            sequencer.transmit(cycle).unwrap().to_string(),
+            "K1ABC W9XYZ RR73"
+        );
+    }
+    assert!(sequencer.transmit(208).is_none());
+    assert_eq!(sequencer.step(), None);
+    let logged = sequencer.take_logged();
+    assert_eq!(logged[0].mode, "FT4");
+    assert_eq!(logged[0].exchange_sent.as_deref(), Some("2B EMA"));
+    assert_eq!(logged[0].exchange_rcvd.as_deref(), Some("3A WI"));
+
+    // RTTY Roundup sends an RST and EU VHF a hashed exchange with the serial
+    let mut sequencer = Sequencer::new(SequencerParams {
+        my_call: "W9XYZ".to_string(),
+        my_grid: "EN37".to_string(),
+        contest: Some(Contest::RttyRoundup),
+        exchange: "WI".to_string(),
+        ..Default::default()
+    });
+    assert!(sequencer.answer(&decoded("CQ RU K1ABC FN42", -12.0, &[]), 201));
+    let sent = sequencer.transmit(202).unwrap();
+    assert_eq!(sent.to_string().trim_end(), "K1ABC W9XYZ 569 WI");
+    let mut sequencer = Sequencer::new(SequencerParams {
+        my_call: "PA3XYZ".to_string(),
+        my_grid: "JO22DC".to_string(),
+        contest: Some(Contest::EuVhf),
+        serial: 3,
+        ..Default::default()
+    });
+    sequencer.call_cq(Slot::Even);
+    assert_eq!(
+        sequencer.transmit(200).unwrap().to_string(),
+        "CQ TEST PA3XYZ JO22"
+    );
+    sequencer.receive(
+        &[decoded(
+            "<PA3XYZ> <G4ABC> 570012 IO91WM",
+            -20.0,
+            &["PA3XYZ", "G4ABC"],
+        )],
+        201,
+    );
+    let sent = sequencer.transmit(202).unwrap();
+    assert_eq!(sent.to_string(), "G4ABC PA3XYZ R 540003 JO22DC");
+
+    // A station that never replies is dropped and CQ resumes
+    let mut sequencer = Sequencer::new(SequencerParams {
+        my_call: "W9XYZ".to_string(),
+        my_grid: "EN37".to_string(),
+        max_repeats: 2,
+        watchdog: 5,
+        ..Default::default()
+    });
+    sequencer.call_cq(Slot::Odd);
+    sequencer.receive(&[decoded("W9XYZ K1ABC FN42", -7.0, &[])], 300);
+    let sent: Vec<_> = (301..=311)
+        .step_by(2)
+        .map(|cycle| sequencer.transmit(cycle).map(|data| data.to_string()))
+        .collect();
+    assert_eq!(
+        sent,
+        [
+            Some("K1ABC W9XYZ -07"),
+            Some("K1ABC W9XYZ -07"),
+            Some("CQ W9XYZ EN37"),
+            Some("CQ W9XYZ EN37"),
+            Some("CQ W9XYZ EN37"),
+            None,
+        ]
+        .map(|text| text.map(str::to_string))
+    );
+    assert!(sequencer.take_logged().is_empty());
+
+    // A nonstandard call goes in full with the grid and RR73, hashed with reports
+    let mut sequencer = Sequencer::new(SequencerParams {
+        my_call: "PJ4/W9XYZ".to_string(),
+        my_grid: "FK52".to_string(),
+        ..Default::default()
+    });
+    assert!(sequencer.answer(&decoded("CQ K1ABC FN42", -11.0, &[]), 400));
+    let sent = sequencer.transmit(401).unwrap();
+    assert_eq!(sent.kind(), MessageKind::NonStdCall);
+    assert_eq!(sent.to_string().trim_end(), "K1ABC PJ4/W9XYZ");
+    sequencer.receive(
+        &[decoded("<PJ4/W9XYZ> K1ABC -05", -11.0, &["PJ4/W9XYZ"])],
+        402,
+    );
+    let sent = sequencer.transmit(403).unwrap();
+    assert_eq!(sent.kind(), MessageKind::StandardR);
+    assert_eq!(sent.to_string(), "K1ABC PJ4/W9XYZ R-11");
+    sequencer.receive(
+        &[decoded("<PJ4/W9XYZ> K1ABC RR73", -11.0, &["PJ4/W9XYZ"])],
+        404,
+    );
+    assert_eq!(
+        sequencer.transmit(405).unwrap().kind(),
+        MessageKind::NonStdCall
+    );
+    assert_eq!(sequencer.step(), None);
+    assert_eq!(sequencer.take_logged()[0].rst_rcvd.as_deref(), Some("-05"));
+}
+
+#[test]
+fn fox_and_hounds_complete_contacts() {
+    let fox_call = "KH1/KH7Z";
+    let mut fox = Fox::new(FoxParams {
+        my_call: fox_call.to_string(),
+        my_grid: "BJ11".to_string(),
+        streams: 2,
+        ..Default::default()
+    });
+    let hound = |call: &str, grid: &str| {
+        let mut hound = Hound::new(HoundParams {
+            my_call: call.to_string(),
+            my_grid: grid.to_string(),
+            fox_call: fox_call.to_string(),
+            tx_freq: 600.0,
+            ..Default::default()
+        });
+        hound.call();
+        hound
+    };
+    let mut w9xyz = hound("W9XYZ", "EN37");
+    let mut k1abc = hound("K1ABC", "FN42");
+    assert_eq!(w9xyz.tx_freq(), 1000.0);
+    assert_eq!(
+        foxhound::pick_tx_freq(&[decoded("CQ G4ABC IO91", 0.0, &[])]),
+        2950.0
+    );
+    let calls = [fox_call, "W9XYZ", "K1ABC"];
+    let heard = |streams: &[(f32, MessageData)], snr| {
+        streams
+            .iter()
+            .map(|(_, data)| received(data, snr, &calls))
+            .collect::<Vec<_>>()
+    };
+
+    let cq = fox.transmit(0);
+    assert_eq!(cq.len(), 1);
+    assert_eq!(cq[0].1.kind(), MessageKind::NonStdCall);
+    w9xyz.receive(&heard(&cq, -5.0), 0);
+    k1abc.receive(&heard(&cq, -5.0), 0);
+    assert!(w9xyz.transmit(0).is_none());
+    let calling = [w9xyz.transmit(1).unwrap(), k1abc.transmit(1).unwrap()];
+    assert_eq!(calling[0].to_string(), "KH1/KH7Z W9XYZ EN37");
+    fox.receive(
+        &[
+            received(&calling[0], -8.0, &calls),
+            received(&calling[1], -14.0, &calls),
+        ],
+        1,
+    );
+    assert_eq!(fox.queue(), ["W9XYZ", "K1ABC"]);
+
+    // Both reports go out at once below 1000 Hz and decode from the combined audio
+    let reports = fox.transmit(2);
+    let sent: Vec<_> = reports
+        .iter()
+        .map(|(freq, data)| (*freq, data.to_string()))
+        .collect();
+    assert_eq!(
+        sent,
+        [
+            (300.0, "W9XYZ KH1/KH7Z -08".to_string()),
+            (360.0, "K1ABC KH1/KH7Z -14".to_string())
+        ]
+    );
+    let mut samples = vec![0.0; 12000 * 15];
+    let audio = foxhound::synthesize(&reports, 12000.0, 0.2);
+    zip(&mut samples[6000..], audio).for_each(|(s, w)| *s += w);
+    let mut decoder = ft8::FT8Decoder::new(&ft8::DecodeParams {
+        from_freq: 200.0,
+        to_freq: 500.0,
+        ..Default::default()
+    });
+    decoder.insert_callsign(&fox_call.to_string());
+    let mut decodes = decoder.decode(&samples).unwrap();
+    decodes.sort_by_key(|message| message.freq_bin_hz);
+    let texts: Vec<_> = decodes
+        .iter()
+        .map(|message| message.data.to_string())
+        .collect();
+    assert_eq!(texts, ["W9XYZ KH1/KH7Z -08", "K1ABC KH1/KH7Z -14"]);
+
+    // Only W9XYZ copies its report, K1ABC keeps calling
+    w9xyz.receive(&decodes[..1], 2);
+    assert_eq!(w9xyz.state(), HoundState::Reported);
+    let roger = w9xyz.transmit(3).unwrap();
+    let report_sent = format!("{:+03}", decodes[0].snr().round() as i16);
+    assert_eq!(
+        roger.to_string(),
+        format!("KH1/KH7Z W9XYZ R{}", report_sent)
+    );
+    fox.receive(
+        &[
+            received(&roger, -8.0, &calls),
+            received(&k1abc.transmit(3).unwrap(), -14.0, &calls),
+        ],
+        3,
+    );
+
+    // RR73 for W9XYZ shares a stream with the report for K1ABC
+    let next = fox.transmit(4);
+    assert_eq!(next.len(), 1);
+    assert_eq!(next[0].1.kind(), MessageKind::Dxpedition);
+    let logged = fox.take_logged();
+    assert_eq!(logged.len(), 1);
+    assert_eq!(logged[0].call, "W9XYZ");
+    assert_eq!(logged[0].rst_sent.as_deref(), Some("-08"));
+    assert_eq!(logged[0].rst_rcvd, Some(report_sent));
+    let next = heard(&next, -5.0);
+    assert_eq!(next[0].data.to_string(), "W9XYZ K1ABC KH1/KH7Z -14");
+    w9xyz.receive(&next, 4);
+    k1abc.receive(&next, 4);
+    assert_eq!(w9xyz.state(), HoundState::Idle);
+    assert_eq!(w9xyz.take_logged()[0].call, fox_call);
+    assert_eq!(k1abc.state(), HoundState::Reported);
+}
+
+#[test]
+fn fox_and_hound_recover_from_lost_messages() {
+    let fox_call = "KH1/KH7Z";
+    let calls = [fox_call, "W9XYZ"];
+
+    // A Hound whose R never gets an RR73 gives up instead of sending it forever
+    let mut hound = Hound::new(HoundParams {
+        my_call: "W9XYZ".to_string(),
+        my_grid: "EN37".to_string(),
+        fox_call: fox_call.to_string(),
+        max_reports: 2,
+        ..Default::default()
+    });
+    hound.call();
+    hound.receive(&[decoded("W9XYZ <KH1/KH7Z> -10", -7.0, &calls)], 0);
+    assert_eq!(hound.state(), HoundState::Reported);
+    assert!(hound.transmit(1).is_some() && hound.transmit(3).is_some());
+    assert!(hound.transmit(5).is_none());
+    assert_eq!(hound.state(), HoundState::Idle);
+
+    // The Fox moves on after one report, then the R arrives and still gets RR73
+    let mut fox = Fox::new(FoxParams {
+        my_call: fox_call.to_string(),
+        streams: 1,
+        max_repeats: 1,
+        ..Default::default()
+    });
+    fox.receive(&[decoded("<KH1/KH7Z> W9XYZ EN37", -9.4, &calls)], 1);
+    let report = fox.transmit(2);
+    // -9.4 dB rounds to the nearest even report
+    assert_eq!(report[0].1.to_string(), "W9XYZ KH1/KH7Z -10");
+    assert!(fox.transmit(4)[0].1.to_string().starts_with("CQ"));
+    fox.receive(&[decoded("<KH1/KH7Z> W9XYZ R-07", -9.4, &calls)], 5);
+    assert_eq!(fox.transmit(6)[0].1.to_string(), "W9XYZ KH1/KH7Z RR73");
+    let logged = fox.take_logged();
+    assert_eq!(logged[0].call, "W9XYZ");
+    assert_eq!(logged[0].rst_sent.as_deref(), Some("-10"));
+    assert_eq!(logged[0].rst_rcvd.as_deref(), Some("-07"));
+}
+
+#[test]
+fn wspr_beacon_cycles() {
+    for text in ["K1ABC/P 30", "K1ABC/12 0", "F/K1ABC 60", "<K1ABC> FN42AX 7"] {
+        let message = WsprMessage::from_text(text).unwrap();
+        let unpacked =
+            WsprMessage::from_bits(&message.to_bits().unwrap(), &CallsignMap::new()).unwrap();
+        assert_eq!(unpacked.power_dbm(), message.power_dbm());
+        if unpacked.callsign().is_some() {
+            assert_eq!(unpacked, message);
+        }
+    }
+    assert!(WsprMessage::from_text("K1ABC FN42 36").is_none());
+    assert!(WsprMessage::beacon("PJ4/K1ABC", "FN42", 37).is_err());
+
+    let beacon = WsprMessage::beacon("PJ4/K1ABC", "fn42ax", 37).unwrap();
+    assert_eq!(beacon.len(), 2);
+    assert_eq!(beacon[0].to_string(), "PJ4/K1ABC 37");
+    assert_eq!(beacon[1].to_string(), "<PJ4/K1ABC> FN42AX 37");
+
+    let beacon_encoder = WsprEncoder::new(&WsprEncodeParams {
+        freq: 1480.0,
+        amplitude: 0.05,
+        ..Default::default()
+    });
+    let other = WsprEncoder::new(&WsprEncodeParams {
+        freq: 1530.0,
+        amplitude: 0.02,
+        ..Default::default()
+    });
+    let other_message = WsprMessage::from_text("W9XYZ EN37 23").unwrap();
+    let wave = other.encode(&other_message).unwrap();
+    assert_eq!(wave.len(), 162 * 8192);
+
+    let mut decoder = WsprDecoder::new(&Default::default());
+    let mut cycle = beacon_encoder.encode_cycle(&beacon[0]).unwrap();
+    assert_eq!(cycle.len(), 120 * 12000);
+    zip(&mut cycle[24000..], &wave).for_each(|(s, w)| *s += w);
+    let filename = temp_wav("wspr_beacon");
+    beacon_encoder.write_wav(&filename, &cycle).unwrap();
+    let cycle = read_wav(filename.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+    let spots = decoder.decode(&cycle).unwrap();
+    spots.iter().for_each(|spot| println!("{}", spot));
+    assert_eq!(spots.len(), 2);
+    assert_eq!(spots[0].message, beacon[0]);
+    assert!((spots[0].freq_hz - 1480.0).abs() <= 0.8);
+    assert!(spots[0].dt.abs() <= 0.2);
+    assert_eq!(spots[1].message, other_message);
+    assert!((spots[1].freq_hz - 1530.0).abs() <= 0.8);
+    assert!((spots[1].dt - 1.0).abs() <= 0.2);
+
+    // The type 1 and 2 messages heard so far resolve the hash in the type 3 message
+    let spots = decoder
+        .decode(&beacon_encoder.encode_cycle(&beacon[1]).unwrap())
+        .unwrap();
+    assert_eq!(spots.len(), 1);
+    assert_eq!(spots[0].message.to_string(), "<PJ4/K1ABC> FN42AX 37");
+    assert_eq!(spots[0].message.grid(), Some("FN42AX"));
+}
+
+#[test]
+fn jt65b_cycle() {
+    let cq = Jt65Message::from_text("CQ PJ4/K1ABC FN42").unwrap();
+    let text = MessageData::from_text("TNX BOB 73 GL").unwrap();
+    let encoder = |freq| {
+        Jt65Encoder::new(&Jt65EncodeParams {
+            submode: Jt65Submode::B,
+            freq,
+            amplitude: 0.1,
+            ..Default::default()
+        })
+    };
+    assert!(encoder(800.0)
+        .encode(&MessageData::from_text("K1ABC W9XYZ +05").unwrap())
+        .is_none());
+    let mut cycle = encoder(800.0)
+        .encode_cycle(&MessageData::Jt65(cq.clone()))
+        .unwrap();
+    assert_eq!(cycle.len(), (jt65::CYCLE_SECONDS * 12000.0) as usize);
+    let wave = encoder(1500.0).encode(&text).unwrap();
+    assert_eq!(
+        wave.len(),
+        (126.0 * 12000.0 * 4096.0 / 11025.0_f32).round() as usize
+    );
+    zip(&mut cycle[24000..], &wave).for_each(|(s, w)| *s += w);
+    let filename = temp_wav("jt65b");
+    encoder(800.0).write_wav(&filename, &cycle).unwrap();
+    let cycle = read_wav(filename.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+
+    let messages = Jt65Decoder::new(&Jt65DecodeParams {
+        submode: Jt65Submode::B,
+        from_freq: 700.0,
+        to_freq: 1600.0,
+        ..Default::default()
+    })
+    .decode(&cycle)
+    .unwrap();
+    messages.iter().for_each(|f| println!("{}", f));
+    assert_eq!(messages.len(), 2);
+    assert!(matches!(&messages[0].data, MessageData::Jt65(message) if *message == cq));
+    assert_eq!(messages[0].bits(), cq.to_bits().unwrap());
+    assert_eq!(messages[0].callsigns(), ["CQ", "PJ4/K1ABC"]);
+    assert_eq!(messages[0].grid().as_deref(), Some("FN42"));
+    assert!((messages[0].freq_bin_hz as i32 - 800).abs() <= 1);
+    assert!((messages[0].time_offset_ms - 1000).abs() <= 200);
+    assert_eq!(messages[1].kind(), MessageKind::Jt65);
+    assert_eq!(messages[1].data.to_string(), "TNX BOB 73 GL");
+    let text = Jt65Message::FreeText("TNX BOB 73 GL".to_string());
+    assert_eq!(messages[1].bits(), text.to_bits().unwrap());
+    assert!((messages[1].freq_bin_hz as i32 - 1500).abs() <= 1);
+    assert!((messages[1].time_offset_ms - 2000).abs() <= 200);
+}